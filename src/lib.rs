@@ -0,0 +1,12 @@
+// A functional logic language run on a call-by-push-value machine with
+// narrowing. `Program` embeds it: compile a source's definitions once and
+// solve queries against them, reading answers as typed `Value`s.
+pub mod parser;
+pub mod machine;
+pub mod repl;
+mod program;
+mod value;
+
+pub use machine::{mterms::MValue, Answer, Config};
+pub use program::{Error, Program, Solutions, TypedSolutions};
+pub use value::{FromValue, HasType, IntoValue, TypeError, Value};
@@ -0,0 +1,167 @@
+use std::{fmt::Debug, hash::{Hash, Hasher}, sync::Arc};
+use im::Vector;
+
+use super::{env::Env, error::RuntimeError, mterms::MValue, senv::SuspAt, store::{LogicStore, SuspStore}, value_type::ValueType, Ident, VClosure};
+
+// What a host function gives for its ground arguments: its results, each
+// a branch of the call, which fails if there are none
+pub type Callback = dyn Fn(&[MValue]) -> Vec<MValue> + Send + Sync;
+
+// A function of the host program callable from programs. A call takes
+// the innermost variables of the environment as its arguments, of types
+// `params` with the first outermost, once they are ground. Results must
+// be closed values of type `result`.
+pub struct Builtin {
+    pub name : String,
+    pub params : Vec<ValueType>,
+    pub result : ValueType,
+    call : Box<Callback>,
+}
+
+impl Builtin {
+
+    pub fn new(name : &str, params : Vec<ValueType>, result : ValueType, call : impl Fn(&[MValue]) -> Vec<MValue> + Send + Sync + 'static) -> Builtin {
+        Builtin { name : name.to_string(), params, result, call : Box::new(call) }
+    }
+
+    pub fn arity(&self) -> usize { self.params.len() }
+
+    // The results for `args`, failing the branch if one isn't a value of
+    // the result type
+    pub fn call(&self, args : &[MValue]) -> Result<Vec<MValue>, RuntimeError> {
+        let results = (self.call)(args);
+        match results.iter().find(|v| !has_type(v, &self.result)) {
+            Some(v) => Err(RuntimeError::BuiltinResult { name : self.name.clone(), found : v.clone().into() }),
+            None => Ok(results),
+        }
+    }
+
+    // The arguments of a call in `env`, or what they wait for
+    pub fn args<L : LogicStore, S : SuspStore>(&self, env : &Env, lenv : &L, senv : &S) -> Result<Vec<MValue>, NotGround> {
+        (0..self.arity()).rev()
+            .map(|i| ground(env.lookup(i).expect("builtin argument undefined in env"), lenv, senv, &mut vec![]).map(|v| (*v).clone()))
+            .collect()
+    }
+}
+
+// Builtins are only equal to themselves, as their callbacks can't be
+// compared
+impl PartialEq for Builtin {
+    fn eq(&self, other : &Builtin) -> bool { std::ptr::eq(self, other) }
+}
+
+impl Eq for Builtin {}
+
+impl Hash for Builtin {
+    fn hash<H : Hasher>(&self, state : &mut H) {
+        self.name.hash(state);
+        self.params.hash(state)
+    }
+}
+
+impl Debug for Builtin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "builtin {}/{}", self.name, self.arity())
+    }
+}
+
+// A call whose arguments were unbound when nothing else could run. It
+// returned the logic variable `result`, and runs once the arguments in
+// `env` are ground to bind it.
+#[derive(Clone, Debug)]
+pub struct Goal {
+    pub builtin : Arc<Builtin>,
+    pub env : Arc<Env>,
+    pub result : Ident,
+}
+
+impl Goal {
+
+    // The arguments of the call if they are ground now. Arguments that
+    // can never be ground are an error.
+    pub fn ready<L : LogicStore, S : SuspStore>(&self, lenv : &L, senv : &S) -> Result<Option<Vec<MValue>>, RuntimeError> {
+        match self.builtin.args(&self.env, lenv, senv) {
+            Ok(args) => Ok(Some(args)),
+            Err(NotGround::Susp(_) | NotGround::Unbound) => Ok(None),
+            Err(e) => Err(not_ground(&self.builtin, e)),
+        }
+    }
+}
+
+// The first of `goals` whose arguments are ground, with them
+pub fn ready<L : LogicStore, S : SuspStore>(goals : &Vector<Goal>, lenv : &L, senv : &S) -> Result<Option<(usize, Vec<MValue>)>, RuntimeError> {
+    for (i, goal) in goals.iter().enumerate() {
+        if let Some(args) = goal.ready(lenv, senv)? { return Ok(Some((i, args))) }
+    }
+    Ok(None)
+}
+
+// Why an argument isn't ground yet, or can't be
+pub enum NotGround {
+    Susp(SuspAt),
+    Unbound,
+    Thunk,
+    // a rational tree, which has no finite value
+    Cyclic,
+}
+
+// The error ending a call whose arguments can't be made ground
+pub fn not_ground(f : &Builtin, e : NotGround) -> RuntimeError {
+    let reason = match e {
+        NotGround::Susp(_) | NotGround::Unbound => unreachable!("the call waits for its arguments"),
+        NotGround::Thunk => "a thunk",
+        NotGround::Cyclic => "a cyclic term",
+    };
+    RuntimeError::Builtin { name : f.name.clone(), reason }
+}
+
+// Whether the closed value `val` has type `t`
+fn has_type(val : &MValue, t : &ValueType) -> bool {
+    match (val, t) {
+        (MValue::Unit, ValueType::Unit) | (MValue::Zero, ValueType::Nat) | (MValue::Nil, ValueType::List(_)) => true,
+        (MValue::Succ(v), ValueType::Nat) => has_type(v, t),
+        (MValue::Cons(v, w), ValueType::List(elem)) => has_type(v, elem) && has_type(w, t),
+        (MValue::Pair(v, w), ValueType::Product(a, b)) => has_type(v, a) && has_type(w, b),
+        (MValue::Inl(v), ValueType::Sum(a, _)) => has_type(v, a),
+        (MValue::Inr(v), ValueType::Sum(_, b)) => has_type(v, b),
+        _ => false,
+    }
+}
+
+// The value with its variables substituted away. `path` holds the roots of
+// the bound variables whose bindings are being substituted, so that a
+// cyclic term is caught when one is met again.
+fn ground<L : LogicStore, S : SuspStore>(vclos : VClosure, lenv : &L, senv : &S, path : &mut Vec<Ident>) -> Result<Arc<MValue>, NotGround> {
+    let depth = path.len();
+    let mut vclos = vclos;
+    let (val, env) = loop {
+        vclos = match vclos {
+            VClosure::Clos { val, env } => match &*val {
+                MValue::Var(i) => env.lookup(*i).expect("index undefined in env"),
+                _ => break (val, env),
+            },
+            VClosure::LogicVar { ident } => {
+                let root = lenv.root(ident);
+                match lenv.lookup(root) {
+                    Some(_) if path.contains(&root) => return Err(NotGround::Cyclic),
+                    Some(binding) => { path.push(root); binding },
+                    None => return Err(NotGround::Unbound),
+                }
+            },
+            VClosure::Susp { ident } => senv.lookup(&ident).map_err(NotGround::Susp)?,
+        }
+    };
+    let mut arg = |v : &Arc<MValue>| ground(VClosure::mk_clos(v, &env), lenv, senv, path);
+    let val = match &*val {
+        MValue::Var(_) => unreachable!("indirections were followed"),
+        MValue::Unit | MValue::Zero | MValue::Nil => val.clone(),
+        MValue::Succ(v) => MValue::Succ(arg(v)?).into(),
+        MValue::Inl(v) => MValue::Inl(arg(v)?).into(),
+        MValue::Inr(v) => MValue::Inr(arg(v)?).into(),
+        MValue::Pair(v, w) => MValue::Pair(arg(v)?, arg(w)?).into(),
+        MValue::Cons(v, w) => MValue::Cons(arg(v)?, arg(w)?).into(),
+        MValue::Thunk(_) => return Err(NotGround::Thunk),
+    };
+    path.truncate(depth);
+    Ok(val)
+}
@@ -0,0 +1,749 @@
+use std::{collections::HashMap, sync::Arc};
+use im::Vector;
+
+use super::{builtin::{not_ground, ready, Builtin, Goal, NotGround}, env::Env, error::RuntimeError, gc::GcStats, lvar::LogicEnv, mterms::{MComputation, MValue}, search::Branch, senv::{Schedule, SuspAt, SuspEnv}, store::{LogicStore, SuspStore}, table::Producer, trace::{Event, Fork, Shape, Tracer, Unified}, unify::{unify, UnifyError}, value_type::ValueType, vclosure::Seen, Ident, VClosure};
+
+pub type Addr = usize;
+
+// An instruction of the compiled program. Each computation becomes one
+// instruction, followed directly by the code of its first continuation;
+// the addresses of any others are stored in the instruction. Operands are
+// values over the environment, whose slots the machine addresses by
+// de Bruijn index like a register file. Their thunks are compiled too,
+// each to `MComputation::Code` of its address.
+#[derive(Clone, Debug)]
+pub enum Instr {
+    Return(Arc<MValue>),
+    // `let` of a value: extend the environment and continue
+    Let(Arc<MValue>),
+    // `let` of a computation: suspend the code, then continue
+    Suspend(Arc<MComputation>),
+    Force(Arc<MValue>),
+    Lambda,
+    Push(Arc<MValue>),
+    Choice(Vec<Addr>),
+    Exists(ValueType, Option<String>),
+    Equate(Arc<MValue>, Arc<MValue>),
+    // the zero branch follows, the successor branch is at the address
+    Ifz(Arc<MValue>, Addr),
+    Match(Arc<MValue>, Addr),
+    Case(Arc<MValue>, Addr),
+    // the thunk of the `Rec` itself
+    Rec(Arc<MValue>),
+    // searches memoise tabled calls, see `table::Tables`
+    Table,
+    // a call followed by `Results` and a `Return` of the innermost
+    // variable. A single result is bound for the `Return`, and several as
+    // a list for `Results`, as `Search` takes a step to choose between them.
+    Builtin(Arc<Builtin>),
+    // a branch for each element of the list in the innermost variable,
+    // which the element replaces
+    Results,
+}
+
+// A compiled program
+pub struct Code {
+    pub instrs : Vec<Instr>,
+    // the computation each instruction was compiled from, which traces,
+    // statistics and tables see
+    sources : Vec<Arc<MComputation>>,
+    // a `Return` of the innermost variable, where consumers of a table
+    // resume with an answer
+    resume : Addr,
+    // `Results`, then the unification of the result variable under them
+    // with each, then a `Return` of the value under that: where a finished
+    // machine runs a waiting builtin call
+    wake : Addr,
+}
+
+// Compiles each computation once. The maps are keyed by the addresses of
+// the program's own computations and environments, which outlive them.
+struct Compiler {
+    code : Code,
+    thunks : HashMap<*const MComputation, Addr>,
+    envs : HashMap<*const Env, Arc<Env>>,
+}
+
+impl Code {
+
+    // Compiles `comp` to address 0, along with every computation reachable
+    // from it or from the thunks of `env`. The thunks of the returned
+    // environment run the compiled code.
+    pub fn compile(comp : &Arc<MComputation>, env : &Arc<Env>) -> (Code, Arc<Env>) {
+        let mut c = Compiler { code : Code { instrs : vec![], sources : vec![], resume : 0, wake : 0 }, thunks : HashMap::new(), envs : HashMap::new() };
+        c.comp(comp);
+        let env = c.env(env);
+        let ret = MComputation::Return(MValue::Var(0).into()).into();
+        c.code.resume = c.emit(Instr::Return(MValue::Var(0).into()), &ret);
+
+        // the computations `Machine` steps through instead
+        let var = |i| -> Arc<MValue> { MValue::Var(i).into() };
+        let ret : Arc<MComputation> = MComputation::Return(var(2)).into();
+        let equate = MComputation::Equate { lhs : var(1), rhs : var(0), body : ret.clone() }.into();
+        c.code.wake = c.emit(Instr::Results, &MComputation::Choice(vec![]).into());
+        c.emit(Instr::Equate(var(1), var(0)), &equate);
+        c.emit(Instr::Return(var(2)), &ret);
+        (c.code, env)
+    }
+}
+
+// The address of the code a thunk or suspension runs
+fn addr(comp : &MComputation) -> Addr {
+    match comp {
+        MComputation::Code(addr) => *addr,
+        _ => unreachable!("computation outside the compiled program"),
+    }
+}
+
+impl Compiler {
+
+    fn emit(&mut self, instr : Instr, source : &Arc<MComputation>) -> Addr {
+        self.code.instrs.push(instr);
+        self.code.sources.push(source.clone());
+        self.code.instrs.len() - 1
+    }
+
+    fn thunk(&mut self, comp : &Arc<MComputation>) -> Arc<MValue> {
+        MValue::Thunk(MComputation::Code(self.code_of(comp)).into()).into()
+    }
+
+    fn code_of(&mut self, comp : &Arc<MComputation>) -> Addr {
+        match self.thunks.get(&Arc::as_ptr(comp)) {
+            Some(addr) => *addr,
+            None => {
+                self.thunks.insert(Arc::as_ptr(comp), self.code.instrs.len());
+                self.comp(comp)
+            }
+        }
+    }
+
+    // `val` with its thunks compiled, shared where it has none
+    fn val(&mut self, val : &Arc<MValue>) -> Arc<MValue> {
+        if let MValue::Thunk(t) = &**val { return self.thunk(t) }
+        let mut sub = |v : &Arc<MValue>, changed : &mut bool| {
+            let w = self.val(v);
+            *changed |= !Arc::ptr_eq(v, &w);
+            w
+        };
+        let mut changed = false;
+        let new = match &**val {
+            MValue::Var(_) | MValue::Unit | MValue::Zero | MValue::Nil | MValue::Thunk(_) => return val.clone(),
+            MValue::Succ(v) => MValue::Succ(sub(v, &mut changed)),
+            MValue::Inl(v) => MValue::Inl(sub(v, &mut changed)),
+            MValue::Inr(v) => MValue::Inr(sub(v, &mut changed)),
+            MValue::Pair(v, w) => MValue::Pair(sub(v, &mut changed), sub(w, &mut changed)),
+            MValue::Cons(v, w) => MValue::Cons(sub(v, &mut changed), sub(w, &mut changed)),
+        };
+        if changed { new.into() } else { val.clone() }
+    }
+
+    fn env(&mut self, env : &Arc<Env>) -> Arc<Env> {
+        if let Some(env) = self.envs.get(&Arc::as_ptr(env)) { return env.clone() }
+        let vcloss : Vec<VClosure> = env.iter().map(|vclos| match vclos {
+            VClosure::Clos { val, env } => VClosure::Clos { val : self.val(val), env : self.env(env) },
+            vclos => vclos.clone(),
+        }).collect();
+        let mut new = Arc::unwrap_or_clone(Env::empty());
+        vcloss.into_iter().rev().for_each(|vclos| new.push(vclos));
+        let new = Arc::new(new);
+        self.envs.insert(Arc::as_ptr(env), new.clone());
+        new
+    }
+
+    // An eliminator followed by its first branch, its operand compiled and
+    // its second branch's address patched in once that has been compiled
+    fn branch(&mut self, instr : Instr, source : &Arc<MComputation>, first : &Arc<MComputation>, second : &Arc<MComputation>) {
+        let pc = self.emit(instr, source);
+        self.comp(first);
+        let addr = self.comp(second);
+        let v = match &self.code.instrs[pc] {
+            Instr::Ifz(v, _) | Instr::Match(v, _) | Instr::Case(v, _) => v.clone(),
+            _ => unreachable!("not an eliminator"),
+        };
+        let w = self.val(&v);
+        match &mut self.code.instrs[pc] {
+            Instr::Ifz(v, k) | Instr::Match(v, k) | Instr::Case(v, k) => { *v = w; *k = addr },
+            _ => unreachable!("not an eliminator"),
+        }
+    }
+
+    // Operands are compiled after the continuation, so that it follows its
+    // instruction, and patched in
+    fn comp(&mut self, comp : &Arc<MComputation>) -> Addr {
+        let pc = self.code.instrs.len();
+        match &**comp {
+            MComputation::Return(v) => {
+                self.emit(Instr::Return(v.clone()), comp);
+                self.code.instrs[pc] = Instr::Return(self.val(v));
+            },
+            MComputation::Bind { comp : first, cont } => match &**first {
+                MComputation::Return(v) => {
+                    self.emit(Instr::Let(v.clone()), comp);
+                    self.comp(cont);
+                    self.code.instrs[pc] = Instr::Let(self.val(v));
+                },
+                _ => {
+                    self.emit(Instr::Suspend(first.clone()), comp);
+                    self.comp(cont);
+                    let first = MComputation::Code(self.code_of(first)).into();
+                    self.code.instrs[pc] = Instr::Suspend(first);
+                }
+            },
+            MComputation::Force(v) => {
+                self.emit(Instr::Force(v.clone()), comp);
+                self.code.instrs[pc] = Instr::Force(self.val(v));
+            },
+            MComputation::Lambda { body } => {
+                self.emit(Instr::Lambda, comp);
+                self.comp(body);
+            },
+            MComputation::App { op, arg } => {
+                self.emit(Instr::Push(arg.clone()), comp);
+                self.comp(op);
+                self.code.instrs[pc] = Instr::Push(self.val(arg));
+            },
+            MComputation::Choice(choices) => {
+                self.emit(Instr::Choice(vec![]), comp);
+                let addrs = choices.iter().map(|c| self.comp(c)).collect();
+                self.code.instrs[pc] = Instr::Choice(addrs);
+            },
+            MComputation::Exists { ptype, name, body } => {
+                self.emit(Instr::Exists(ptype.clone(), name.clone()), comp);
+                self.comp(body);
+            },
+            MComputation::Equate { lhs, rhs, body } => {
+                self.emit(Instr::Equate(lhs.clone(), rhs.clone()), comp);
+                self.comp(body);
+                self.code.instrs[pc] = Instr::Equate(self.val(lhs), self.val(rhs));
+            },
+            MComputation::Ifz { num, zk, sk } => self.branch(Instr::Ifz(num.clone(), 0), comp, zk, sk),
+            MComputation::Match { list, nilk, consk } => self.branch(Instr::Match(list.clone(), 0), comp, nilk, consk),
+            MComputation::Case { sum, inlk, inrk } => self.branch(Instr::Case(sum.clone(), 0), comp, inlk, inrk),
+            MComputation::Rec { body } => {
+                let thunk = MValue::Thunk(MComputation::Code(pc).into()).into();
+                self.emit(Instr::Rec(thunk), comp);
+                self.comp(body);
+            },
+            MComputation::Table { body, .. } => {
+                self.emit(Instr::Table, comp);
+                self.comp(body);
+            },
+            MComputation::Builtin(f) => {
+                self.emit(Instr::Builtin(f.clone()), comp);
+                self.emit(Instr::Results, &MComputation::Choice(vec![]).into());
+                let ret = MComputation::Return(MValue::Var(0).into()).into();
+                self.emit(Instr::Return(MValue::Var(0).into()), &ret);
+            },
+            MComputation::Code(addr) => return *addr,
+        }
+        pc
+    }
+}
+
+#[derive(Clone)]
+pub(super) enum Frame {
+    // an argument waiting for a `Lambda`
+    Value(VClosure),
+    // the suspension being forced, and where to continue once it is set
+    Set(Ident, Addr, Arc<Env>),
+}
+
+// A machine running compiled code: `Machine` with a program counter in
+// place of the computation. The machine owns its environment and stack,
+// so each step updates them in place unless a sibling branch shares them.
+#[derive(Clone)]
+pub struct BMachine {
+    pub pc : Addr,
+    code : Arc<Code>,
+    // the top frame is at the back
+    pub(super) stack : Vector<Frame>,
+    pub env : Arc<Env>,
+    pub lenv : LogicEnv,
+    pub senv : SuspEnv,
+    pub done : bool,
+    pub producer : Option<Producer>,
+    pub steps : usize,
+    pub bindings : Vector<(String, Ident)>,
+    pub goals : Vector<Goal>,
+    pub node : usize,
+}
+
+impl BMachine {
+
+    // A machine at the start of `comp` compiled along with the functions of
+    // `env`
+    pub fn new(comp : MComputation, env : &Arc<Env>) -> BMachine {
+        let (code, env) = Code::compile(&comp.into(), env);
+        BMachine {
+            pc : 0, code : code.into(), stack : Vector::new(), env, lenv : LogicEnv::new(), senv : SuspEnv::new(),
+            done : false, producer : None, steps : 0, bindings : Vector::new(), goals : Vector::new(), node : 0
+        }
+    }
+
+    pub fn with_schedule(self, schedule : Schedule) -> BMachine {
+        BMachine { senv : self.senv.with_schedule(schedule), ..self }
+    }
+
+    pub fn with_occurs_check(self, occurs_check : bool) -> BMachine {
+        BMachine { lenv : self.lenv.with_occurs_check(occurs_check), ..self }
+    }
+
+    // Binds a new innermost variable
+    fn bind(&mut self, vclos : VClosure) { Arc::make_mut(&mut self.env).push(vclos) }
+
+    // An operand as a closure; a variable's slot is copied rather than
+    // captured with the environment
+    fn operand(&self, val : &Arc<MValue>) -> VClosure {
+        match &**val {
+            MValue::Var(i) => self.env.lookup(*i).expect("index undefined in env"),
+            _ => VClosure::mk_clos(val, &self.env),
+        }
+    }
+
+    fn eval_susp_then(&mut self, a : SuspAt) {
+        let env = std::mem::replace(&mut self.env, a.env);
+        self.stack.push_back(Frame::Set(a.ident, self.pc, env));
+        self.pc = addr(&a.comp);
+    }
+
+    fn force_susp(&mut self, a : SuspAt, tracer : &mut impl Tracer) {
+        tracer.event(self.steps, Event::Force { susp : a.ident });
+        self.eval_susp_then(a)
+    }
+
+    // Forces the suspension the machine is blocked on, or whichever the
+    // schedule prefers
+    fn unblock(mut self, a : SuspAt, tracer : &mut impl Tracer) -> BMachine {
+        let a = self.scheduled(a);
+        self.force_susp(a, tracer);
+        self
+    }
+
+    // The suspension to force when the machine is blocked on `a`, see
+    // `Machine::scheduled`
+    fn scheduled(&self, a : SuspAt) -> SuspAt {
+        match self.senv.schedule() {
+            Schedule::Demand => a,
+            _ => self.senv.next(Some(a.ident), &[], &self.forcing()).unwrap_or(a),
+        }
+    }
+
+    fn demanded_susps(&self, val : &Arc<MValue>) -> Vec<Ident> {
+        let mut out = vec![];
+        if matches!(self.senv.schedule(), Schedule::Demand) {
+            VClosure::mk_clos(val, &self.env).demanded_susps(&self.lenv, &self.senv, &mut Seen::default(), &mut out);
+        }
+        out
+    }
+
+    // The suspensions being forced, whose values the stack waits for
+    fn forcing(&self) -> Vec<Ident> {
+        self.stack.iter().rev().filter_map(|frame| match frame {
+            Frame::Set(ident, _, _) => Some(*ident),
+            Frame::Value(_) => None,
+        }).collect()
+    }
+
+    // Binds the results of a call for the `Results` at `at`, or a single
+    // result directly for the instruction after it
+    fn results(mut self, results : Vec<MValue>, at : Addr) -> BMachine {
+        match &results[..] {
+            [v] => {
+                self.bind(VClosure::mk_clos(&v.clone().into(), &Env::empty()));
+                BMachine { pc : at + 1, ..self }
+            },
+            vs => {
+                let list = vs.iter().rev().fold(MValue::Nil.into(), |xs, v| MValue::Cons(v.clone().into(), xs).into());
+                self.bind(VClosure::mk_clos(&list, &Env::empty()));
+                BMachine { pc : at, ..self }
+            },
+        }
+    }
+
+    // Every machine the machine steps to, with the events `Machine::step`
+    // traces for the computation compiled to the instruction
+    pub fn step(self, tracer : &mut impl Tracer) -> Result<Vec<BMachine>, RuntimeError> {
+        let steps = self.steps + 1;
+        let mut ms = self.transition(tracer)?;
+        ms.iter_mut().for_each(|m| m.steps = steps);
+        Ok(ms)
+    }
+
+    fn transition(self, tracer : &mut impl Tracer) -> Result<Vec<BMachine>, RuntimeError> {
+        let mut m = self;
+        let code = m.code.clone();
+        let depth = m.steps;
+        let next = m.pc + 1;
+
+        Ok(match &code.instrs[m.pc] {
+
+            Instr::Return(val) => match m.stack.pop_back() {
+                None => match m.senv.next(None, &m.demanded_susps(val), &[]) {
+                    Some(a) => { m.force_susp(a, tracer); vec![m] },
+                    None => match ready(&m.goals, &m.lenv, &m.senv)? {
+                        Some((i, args)) => {
+                            let goal = m.goals.remove(i);
+                            let results = goal.builtin.call(&args)?;
+                            let vclos = m.operand(val);
+                            m.bind(vclos);
+                            m.bind(VClosure::LogicVar { ident : goal.result });
+                            vec![m.results(results, code.wake)]
+                        },
+                        None => vec![BMachine { done : true, ..m }],
+                    },
+                },
+                Some(Frame::Set(i, pc, env)) => {
+                    m.senv.set(&i, val, &m.env);
+                    vec![BMachine { pc, env, ..m }]
+                },
+                Some(Frame::Value(_)) => unreachable!("return throws value to a value"),
+            },
+
+            Instr::Let(v) => {
+                let vclos = m.operand(v);
+                m.bind(vclos);
+                vec![BMachine { pc : next, ..m }]
+            },
+
+            Instr::Suspend(comp) => {
+                let ident = m.senv.fresh(comp, &m.env);
+                tracer.event(depth, Event::Suspend { susp : ident });
+                m.bind(VClosure::Susp { ident });
+                vec![BMachine { pc : next, ..m }]
+            },
+
+            Instr::Force(v) => match m.operand(v).close_head(&mut m.lenv, &m.senv) {
+                Ok(VClosure::Clos { val, env }) => match &*val {
+                    MValue::Thunk(t) => vec![BMachine { pc : addr(t), env, ..m }],
+                    _ => return Err(RuntimeError::ForceValue(val.clone())),
+                },
+                Ok(VClosure::LogicVar { ident }) => return Err(RuntimeError::ForceLogicVar(m.lenv.get_type(ident))),
+                Ok(VClosure::Susp { .. }) => unreachable!("shouldn't be forcing a suspension"),
+                Err(a) => vec![m.unblock(a, tracer)],
+            },
+
+            Instr::Lambda => match m.stack.pop_back() {
+                Some(Frame::Value(vclos)) => {
+                    m.bind(vclos);
+                    vec![BMachine { pc : next, ..m }]
+                },
+                Some(Frame::Set(..)) | None => return Err(RuntimeError::NoArgument),
+            },
+
+            Instr::Push(arg) => {
+                let vclos = m.operand(arg);
+                m.stack.push_back(Frame::Value(vclos));
+                vec![BMachine { pc : next, ..m }]
+            },
+
+            Instr::Choice(addrs) => {
+                tracer.event(depth, Event::Fork { cause : Fork::Choice, branches : addrs.len() });
+                addrs.iter().map(|pc| BMachine { pc : *pc, ..m.clone() }).collect()
+            },
+
+            Instr::Exists(ptype, name) => {
+                let ident = m.lenv.fresh(ptype.clone());
+                if let Some(name) = name { m.bindings.push_back((name.clone(), ident)) }
+                m.bind(VClosure::LogicVar { ident });
+                vec![BMachine { pc : next, ..m }]
+            },
+
+            Instr::Equate(lhs, rhs) => {
+                let result = unify(lhs, rhs, &m.env, &mut m.lenv, &m.senv, &mut |lvar, value| tracer.event(depth, Event::Bind { lvar, value }));
+                let outcome = match result {
+                    Ok(()) => Some(Unified::Success),
+                    Err(UnifyError::Fail) => Some(Unified::Fail),
+                    Err(UnifyError::Occurs) => Some(Unified::Occurs),
+                    Err(_) => None,
+                };
+                if let Some(outcome) = outcome { tracer.event(depth, Event::Unify { outcome }) }
+                match result {
+                    Ok(()) => vec![BMachine { pc : next, ..m }],
+                    Err(UnifyError::Susp(a)) => vec![m.unblock(a, tracer)],
+                    Err(UnifyError::Runtime(e)) => return Err(e),
+                    Err(_) => vec![],
+                }
+            },
+
+            Instr::Ifz(num, sk) => match m.operand(num).close_head(&mut m.lenv, &m.senv) {
+                Err(a) => vec![m.unblock(a, tracer)],
+                Ok(VClosure::Clos { val, env }) => match &*val {
+                    MValue::Zero => vec![BMachine { pc : next, ..m }],
+                    MValue::Succ(v) => {
+                        m.bind(VClosure::mk_clos(v, &env));
+                        vec![BMachine { pc : *sk, ..m }]
+                    },
+                    _ => return Err(RuntimeError::Eliminate { expected : "a number", found : val.clone() }),
+                },
+                Ok(VClosure::LogicVar { ident }) => {
+                    tracer.event(depth, Event::Fork { cause : Fork::Narrow(Shape::Nat), branches : 2 });
+                    let mut zero = m.clone();
+                    let value = VClosure::mk_clos(&MValue::Zero.into(), &Env::empty());
+                    tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                    zero.lenv.set_vclos(ident, value);
+                    let pred = m.lenv.fresh(ValueType::Nat);
+                    let value = VClosure::mk_clos(&MValue::Succ(MValue::Var(0).into()).into(), &Env::empty().extend_lvar(pred));
+                    tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                    m.lenv.set_vclos(ident, value);
+                    m.bind(VClosure::LogicVar { ident : pred });
+                    vec![BMachine { pc : next, ..zero }, BMachine { pc : *sk, ..m }]
+                },
+                Ok(VClosure::Susp { .. }) => unreachable!("shouldn't be encountering a suspension here"),
+            },
+
+            Instr::Match(list, consk) => match m.operand(list).close_head(&mut m.lenv, &m.senv) {
+                Err(a) => vec![m.unblock(a, tracer)],
+                Ok(VClosure::Clos { val, env }) => match &*val {
+                    MValue::Nil => vec![BMachine { pc : next, ..m }],
+                    MValue::Cons(v, w) => {
+                        m.bind(VClosure::mk_clos(v, &env));
+                        m.bind(VClosure::mk_clos(w, &env));
+                        vec![BMachine { pc : *consk, ..m }]
+                    },
+                    _ => return Err(RuntimeError::Eliminate { expected : "a list", found : val.clone() }),
+                },
+                Ok(VClosure::LogicVar { ident }) => {
+                    let ptype = match m.lenv.get_type(ident) {
+                        ValueType::List(t) => t,
+                        found => return Err(RuntimeError::Narrow { expected : "a list", found }),
+                    };
+                    tracer.event(depth, Event::Fork { cause : Fork::Narrow(Shape::List), branches : 2 });
+                    let mut nil = m.clone();
+                    let value = VClosure::mk_clos(&MValue::Nil.into(), &Env::empty());
+                    tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                    nil.lenv.set_vclos(ident, value);
+                    let head = m.lenv.fresh(*ptype.clone());
+                    let tail = m.lenv.fresh(ValueType::List(ptype));
+                    let value = VClosure::mk_clos(
+                        &MValue::Cons(MValue::Var(1).into(), MValue::Var(0).into()).into(),
+                        &Env::empty().extend_lvar(head).extend_lvar(tail)
+                    );
+                    tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                    m.lenv.set_vclos(ident, value);
+                    m.bind(VClosure::LogicVar { ident : head });
+                    m.bind(VClosure::LogicVar { ident : tail });
+                    vec![BMachine { pc : next, ..nil }, BMachine { pc : *consk, ..m }]
+                },
+                Ok(VClosure::Susp { .. }) => unreachable!("shouldn't be matching on a suspension"),
+            },
+
+            Instr::Case(sum, inrk) => match m.operand(sum).close_head(&mut m.lenv, &m.senv) {
+                Err(a) => vec![m.unblock(a, tracer)],
+                Ok(VClosure::Clos { val, env }) => match &*val {
+                    MValue::Inl(v) => {
+                        m.bind(VClosure::mk_clos(v, &env));
+                        vec![BMachine { pc : next, ..m }]
+                    },
+                    MValue::Inr(v) => {
+                        m.bind(VClosure::mk_clos(v, &env));
+                        vec![BMachine { pc : *inrk, ..m }]
+                    },
+                    _ => return Err(RuntimeError::Eliminate { expected : "a sum", found : val.clone() }),
+                },
+                Ok(VClosure::LogicVar { ident }) => {
+                    let (ptype1, ptype2) = match m.lenv.get_type(ident) {
+                        ValueType::Sum(t1, t2) => (t1, t2),
+                        found => return Err(RuntimeError::Narrow { expected : "a sum", found }),
+                    };
+                    tracer.event(depth, Event::Fork { cause : Fork::Narrow(Shape::Sum), branches : 2 });
+                    let mut inl = m.clone();
+                    let l = inl.lenv.fresh(*ptype1);
+                    let value = VClosure::mk_clos(&MValue::Inl(MValue::Var(0).into()).into(), &Env::empty().extend_lvar(l));
+                    tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                    inl.lenv.set_vclos(ident, value);
+                    inl.bind(VClosure::LogicVar { ident : l });
+                    let r = m.lenv.fresh(*ptype2);
+                    let value = VClosure::mk_clos(&MValue::Inr(MValue::Var(0).into()).into(), &Env::empty().extend_lvar(r));
+                    tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                    m.lenv.set_vclos(ident, value);
+                    m.bind(VClosure::LogicVar { ident : r });
+                    vec![BMachine { pc : next, ..inl }, BMachine { pc : *inrk, ..m }]
+                },
+                Ok(VClosure::Susp { .. }) => unreachable!("shouldn't be casing on a suspension"),
+            },
+
+            Instr::Rec(thunk) => {
+                let vclos = VClosure::mk_clos(thunk, &m.env);
+                m.bind(vclos);
+                vec![BMachine { pc : next, ..m }]
+            },
+
+            Instr::Table => vec![BMachine { pc : next, ..m }],
+
+            Instr::Builtin(f) => match f.args(&m.env, &m.lenv, &m.senv) {
+                Ok(args) => {
+                    let results = f.call(&args)?;
+                    vec![BMachine { pc : next, ..m }.results(results, next)]
+                },
+                Err(NotGround::Susp(a)) => vec![m.unblock(a, tracer)],
+                Err(NotGround::Unbound) => match m.senv.next(None, &[], &m.forcing()) {
+                    Some(a) => { m.force_susp(a, tracer); vec![m] },
+                    None => {
+                        let result = m.lenv.fresh(f.result.clone());
+                        m.goals.push_back(Goal { builtin : f.clone(), env : m.env.clone(), result });
+                        m.bind(VClosure::LogicVar { ident : result });
+                        vec![BMachine { pc : next + 1, ..m }]
+                    },
+                },
+                Err(e) => return Err(not_ground(f, e)),
+            },
+
+            // the list is closed, as `Builtin` made it
+            Instr::Results => {
+                let mut vals = vec![];
+                if let Some(VClosure::Clos { val, .. }) = m.env.lookup(0) {
+                    let mut list = val;
+                    while let MValue::Cons(v, w) = &*list {
+                        vals.push(v.clone());
+                        list = w.clone()
+                    }
+                }
+                tracer.event(depth, Event::Fork { cause : Fork::Choice, branches : vals.len() });
+                vals.into_iter().map(|v| {
+                    let env = m.env.set(0, VClosure::mk_clos(&v, &Env::empty()));
+                    BMachine { pc : next, env, ..m.clone() }
+                }).collect()
+            },
+        })
+    }
+}
+
+impl Branch for BMachine {
+    fn comp(&self) -> &MComputation { &self.code.sources[self.pc] }
+
+    fn step(self, tracer : &mut impl Tracer) -> Result<Vec<BMachine>, RuntimeError> { BMachine::step(self, tracer) }
+
+    fn done(&self) -> bool { self.done }
+
+    fn steps(&self) -> usize { self.steps }
+
+    fn node(&self) -> usize { self.node }
+
+    fn set_node(&mut self, node : usize) { self.node = node }
+
+    fn env(&self) -> &Arc<Env> { &self.env }
+
+    fn lenv(&self) -> &LogicEnv { &self.lenv }
+
+    fn senv(&self) -> &SuspEnv { &self.senv }
+
+    fn bindings(&self) -> &Vector<(String, Ident)> { &self.bindings }
+
+    fn goals(&self) -> &Vector<Goal> { &self.goals }
+
+    fn producer(&self) -> Option<&Producer> { self.producer.as_ref() }
+
+    fn collect(&mut self) -> GcStats { BMachine::collect(self) }
+
+    fn result(&self) -> VClosure {
+        match &self.code.instrs[self.pc] {
+            Instr::Return(v) => VClosure::mk_clos(v, &self.env),
+            _ => unreachable!("machine hasn't finished"),
+        }
+    }
+
+    fn enter(self) -> BMachine { BMachine { pc : self.pc + 1, ..self } }
+
+    fn force(mut self, a : SuspAt) -> BMachine {
+        let a = self.scheduled(a);
+        self.eval_susp_then(a);
+        self
+    }
+
+    fn spawn(&self, env : Arc<Env>, lenv : LogicEnv, producer : Producer) -> BMachine {
+        BMachine {
+            pc : self.pc + 1, code : self.code.clone(), stack : Vector::new(), env, lenv,
+            senv : SuspEnv::new().with_schedule(self.senv.schedule()),
+            done : false, producer : Some(producer), steps : self.steps, bindings : Vector::new(), goals : Vector::new(), node : self.node
+        }
+    }
+
+    fn resume(&self, result : Arc<MValue>, env : Arc<Env>, lenv : LogicEnv) -> BMachine {
+        let mut m = BMachine { pc : self.code.resume, lenv, ..self.clone() };
+        m.bind(VClosure::Clos { val : result, env });
+        m
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{machine::{limits::{LimitHit, Limits}, search::Search, step::Machine, trace::JsonTracer, translate::translate}, parser};
+    use super::*;
+
+    // The answers, steps and JSON trace of a search
+    fn run<M : Branch>(m : M, steps : usize) -> (Vec<String>, usize, Option<LimitHit>, Vec<u8>) {
+        let mut trace = vec![];
+        let mut search = Search::new(m, Limits { max_steps : Some(steps), ..Limits::default() }).with_tracer(JsonTracer::new(&mut trace));
+        let answers = search.by_ref().map(|m| m.answer(None, true).unwrap().to_string()).collect();
+        let (steps, hit) = (search.steps(), search.limit_hit());
+        drop(search);
+        (answers, steps, hit, trace)
+    }
+
+    fn agree(src : &str, schedule : Schedule, steps : usize) -> Option<LimitHit> {
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        let tree = run(Machine::new(comp.clone(), env.clone()).with_schedule(schedule), steps);
+        let compiled = run(BMachine::new(comp, &env).with_schedule(schedule), steps);
+        assert_eq!(compiled.0, tree.0);
+        assert_eq!(compiled.1, tree.1);
+        assert!(compiled.3 == tree.3, "traces differ");
+        tree.2
+    }
+
+    #[test]
+    fn agrees_on_examples() {
+        // the examples that search forever
+        const ENDLESS : [&str; 4] = ["poke", "pythagorean", "square2", "perm2"];
+        let mut compared = 0;
+        for entry in std::fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            let src = std::fs::read_to_string(&path).unwrap();
+            if parser::parse(&src).is_err() { continue }
+            let endless = ENDLESS.iter().any(|name| path.file_stem().unwrap() == *name);
+            // creation order starves some of them, perm among others
+            let hit = agree(&src, Schedule::Demand, if endless { 5000 } else { 1_000_000 });
+            assert!(endless || hit.is_none(), "{} didn't finish", path.display());
+            compared += 1;
+        }
+        assert!(compared > 10);
+    }
+
+    #[test]
+    fn deep_answers() {
+        // the answer is as deep as the recursion that built it, which
+        // neither finding, closing nor dropping it may recurse on
+        let src = "double :: Nat -> Nat
+double n = case n of
+    Z -> 0
+  | S m -> S (S (double m)).
+
+pow :: Nat -> Nat
+pow n = case n of
+    Z -> 1
+  | S m -> double (pow m).
+
+pow 11.";
+        let thread = std::thread::Builder::new().stack_size(1 << 20).spawn(move || agree(src, Schedule::Demand, 1_000_000));
+        assert_eq!(thread.unwrap().join().unwrap(), None);
+    }
+
+    #[test]
+    fn suspensions() {
+        for schedule in [Schedule::Oldest, Schedule::Newest, Schedule::Demand] {
+            agree("let a = (1 <> 2) in let b = (3 <> 4) in (b, a).", schedule, 1000);
+        }
+    }
+
+    #[test]
+    fn tables() {
+        let src = "edge :: Nat -> Nat
+edge x = case x of Z -> 1 | S n -> (case n of Z -> (0 <> 2) | S m -> 0).
+
+table reach
+reach :: Nat -> Nat
+reach x = edge x <> (let y = reach x in edge y).
+
+reach 0.";
+        assert_eq!(agree(src, Schedule::default(), 1000), None);
+    }
+}
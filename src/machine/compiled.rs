@@ -0,0 +1,391 @@
+use std::{fmt::Display, sync::Arc};
+
+use super::{mterms::{MComputation, MValue}, value_type::{ComputationType, ValueType}, Env, VClosure};
+
+const MAGIC : &[u8; 4] = b"FLPC";
+const VERSION : usize = 1;
+
+// A translated program as written by `compile`: the functions, oldest
+// first, with their names, and the query with the type of its answers.
+//
+// The encoding is the magic `FLPC` and the format's version, then the
+// names, the functions and the query. Numbers are LEB128 and strings are
+// their length and UTF-8 bytes; terms are written prefix, each node a tag
+// byte followed by its fields. Sharing between terms isn't kept.
+pub struct Compiled {
+    pub query : MComputation,
+    pub env : Arc<Env>,
+    pub ptype : Option<ValueType>,
+    pub names : Vec<String>,
+}
+
+// Why a file couldn't be loaded as a compiled program
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadError {
+    // the file doesn't start with the magic
+    Magic,
+    // written in another version of the format
+    Version(usize),
+    // the file ends within a term
+    Truncated,
+    Tag { kind : &'static str, tag : u8 },
+    // a de Bruijn index with only `depth` variables in scope
+    Index { index : usize, depth : usize },
+    // a tabled call taking more arguments than there are variables
+    Arity { arity : usize, depth : usize },
+    Utf8,
+    // bytes after the query
+    Trailing(usize),
+    // a call of a host function, which the loader doesn't have
+    Builtin(String),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Magic => write!(f, "not a compiled program"),
+            LoadError::Version(v) => write!(f, "compiled with format version {}, but this reads version {}", v, VERSION),
+            LoadError::Truncated => write!(f, "the file is truncated"),
+            LoadError::Tag { kind, tag } => write!(f, "unknown {} tag {}", kind, tag),
+            LoadError::Index { index, depth } => write!(f, "variable #{} with only {} in scope", index, depth),
+            LoadError::Arity { arity, depth } => write!(f, "tabled call of arity {} with only {} variables in scope", arity, depth),
+            LoadError::Utf8 => write!(f, "a name isn't UTF-8"),
+            LoadError::Trailing(n) => write!(f, "{} bytes after the query", n),
+            LoadError::Builtin(name) => write!(f, "the program calls the builtin {}, which only its host can run", name),
+        }
+    }
+}
+
+// Why a program couldn't be written
+#[derive(Clone, Debug, PartialEq)]
+pub enum SaveError {
+    // the function at this position, oldest first, isn't a closure over the
+    // functions before it
+    Global(usize),
+    // code compiled for the bytecode machine, which is only ever in memory
+    Code,
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Global(i) => write!(f, "function #{} isn't a closure over the functions before it", i),
+            SaveError::Code => write!(f, "the program is compiled to bytecode"),
+        }
+    }
+}
+
+impl Compiled {
+
+    // Each function's closure must be over the functions before it, as
+    // `translate` makes them
+    pub fn encode(&self) -> Result<Vec<u8>, SaveError> {
+        let mut out = Encoder { out : MAGIC.to_vec() };
+        out.nat(VERSION);
+        out.nat(self.names.len());
+        self.names.iter().for_each(|name| out.str(name));
+        let functions : Vec<&VClosure> = self.env.iter().collect();
+        out.nat(functions.len());
+        for (i, vclos) in functions.into_iter().rev().enumerate() {
+            match vclos {
+                VClosure::Clos { val, env } if env.iter().count() == i => out.val(val)?,
+                _ => return Err(SaveError::Global(i)),
+            }
+        }
+        match &self.ptype {
+            Some(ptype) => { out.byte(1); out.vtype(ptype) },
+            None => out.byte(0),
+        }
+        out.comp(&self.query)?;
+        Ok(out.out)
+    }
+
+    // Reads a program back, checking that every variable is bound
+    pub fn decode(bytes : &[u8]) -> Result<Compiled, LoadError> {
+        if !bytes.starts_with(MAGIC) { return Err(LoadError::Magic) }
+        let mut input = Decoder { bytes, at : MAGIC.len() };
+        match input.nat()? {
+            VERSION => (),
+            v => return Err(LoadError::Version(v)),
+        }
+        let names = (0..input.count()?).map(|_| input.str()).collect::<Result<_, _>>()?;
+        let mut env = Env::empty();
+        for depth in 0..input.count()? {
+            let val = input.val(depth)?;
+            env = env.extend_val(val.into(), env.clone());
+        }
+        let ptype = match input.byte()? {
+            0 => None,
+            1 => Some(input.vtype()?),
+            tag => return Err(LoadError::Tag { kind : "type", tag }),
+        };
+        let query = input.comp(env.iter().count())?;
+        match bytes.len() - input.at {
+            0 => Ok(Compiled { query, env, ptype, names }),
+            n => Err(LoadError::Trailing(n)),
+        }
+    }
+}
+
+struct Encoder { out : Vec<u8> }
+
+impl Encoder {
+
+    fn byte(&mut self, b : u8) { self.out.push(b) }
+
+    fn nat(&mut self, mut n : usize) {
+        while n >= 0x80 {
+            self.out.push((n & 0x7f) as u8 | 0x80);
+            n >>= 7;
+        }
+        self.out.push(n as u8)
+    }
+
+    fn str(&mut self, s : &str) {
+        self.nat(s.len());
+        self.out.extend_from_slice(s.as_bytes())
+    }
+
+    fn vtype(&mut self, t : &ValueType) {
+        match t {
+            ValueType::Unit => self.byte(0),
+            ValueType::Nat => self.byte(1),
+            ValueType::Product(a, b) => { self.byte(2); self.vtype(a); self.vtype(b) },
+            ValueType::Sum(a, b) => { self.byte(3); self.vtype(a); self.vtype(b) },
+            ValueType::List(t) => { self.byte(4); self.vtype(t) },
+            ValueType::Thunk(c) => { self.byte(5); self.ctype(c) },
+        }
+    }
+
+    fn ctype(&mut self, t : &ComputationType) {
+        match t {
+            ComputationType::Return(v) => { self.byte(0); self.vtype(v) },
+            ComputationType::Arrow(a, c) => { self.byte(1); self.vtype(a); self.ctype(c) },
+        }
+    }
+
+    fn val(&mut self, v : &MValue) -> Result<(), SaveError> {
+        match v {
+            MValue::Var(i) => { self.byte(0); self.nat(*i) },
+            MValue::Unit => self.byte(1),
+            MValue::Zero => self.byte(2),
+            MValue::Succ(v) => { self.byte(3); self.val(v)? },
+            MValue::Pair(v, w) => { self.byte(4); self.val(v)?; self.val(w)? },
+            MValue::Inl(v) => { self.byte(5); self.val(v)? },
+            MValue::Inr(v) => { self.byte(6); self.val(v)? },
+            MValue::Nil => self.byte(7),
+            MValue::Cons(v, w) => { self.byte(8); self.val(v)?; self.val(w)? },
+            MValue::Thunk(c) => { self.byte(9); self.comp(c)? },
+        }
+        Ok(())
+    }
+
+    fn comp(&mut self, c : &MComputation) -> Result<(), SaveError> {
+        match c {
+            MComputation::Ifz { num, zk, sk } => { self.byte(0); self.val(num)?; self.comp(zk)?; self.comp(sk)? },
+            MComputation::Match { list, nilk, consk } => { self.byte(1); self.val(list)?; self.comp(nilk)?; self.comp(consk)? },
+            MComputation::Case { sum, inlk, inrk } => { self.byte(2); self.val(sum)?; self.comp(inlk)?; self.comp(inrk)? },
+            MComputation::Return(v) => { self.byte(3); self.val(v)? },
+            MComputation::Bind { comp, cont } => { self.byte(4); self.comp(comp)?; self.comp(cont)? },
+            MComputation::Force(v) => { self.byte(5); self.val(v)? },
+            MComputation::Lambda { body } => { self.byte(6); self.comp(body)? },
+            MComputation::App { op, arg } => { self.byte(7); self.comp(op)?; self.val(arg)? },
+            MComputation::Choice(choices) => {
+                self.byte(8);
+                self.nat(choices.len());
+                for c in choices { self.comp(c)? }
+            },
+            MComputation::Exists { ptype, name, body } => {
+                self.byte(9);
+                self.vtype(ptype);
+                match name {
+                    Some(name) => { self.byte(1); self.str(name) },
+                    None => self.byte(0),
+                }
+                self.comp(body)?
+            },
+            MComputation::Equate { lhs, rhs, body } => { self.byte(10); self.val(lhs)?; self.val(rhs)?; self.comp(body)? },
+            MComputation::Rec { body } => { self.byte(11); self.comp(body)? },
+            MComputation::Table { id, name, arity, body } => {
+                self.byte(12);
+                self.nat(*id);
+                self.str(name);
+                self.nat(*arity);
+                self.comp(body)?
+            },
+            // only the program that registered a builtin can call it, so
+            // it is written to be reported when loaded
+            MComputation::Builtin(f) => { self.byte(13); self.str(&f.name) },
+            MComputation::Code(_) => return Err(SaveError::Code),
+        }
+        Ok(())
+    }
+}
+
+// Reads terms, each checked against the `depth` variables in scope
+struct Decoder<'a> { bytes : &'a [u8], at : usize }
+
+impl Decoder<'_> {
+
+    fn byte(&mut self) -> Result<u8, LoadError> {
+        let b = *self.bytes.get(self.at).ok_or(LoadError::Truncated)?;
+        self.at += 1;
+        Ok(b)
+    }
+
+    fn nat(&mut self) -> Result<usize, LoadError> {
+        let mut n = 0;
+        for shift in (0..usize::BITS).step_by(7) {
+            let b = self.byte()?;
+            n |= ((b & 0x7f) as usize) << shift;
+            if b & 0x80 == 0 { return Ok(n) }
+        }
+        Err(LoadError::Tag { kind : "number", tag : self.bytes[self.at - 1] })
+    }
+
+    // The length of a sequence, each of whose items takes a byte at least
+    fn count(&mut self) -> Result<usize, LoadError> {
+        let n = self.nat()?;
+        if n > self.bytes.len() - self.at { return Err(LoadError::Truncated) }
+        Ok(n)
+    }
+
+    fn str(&mut self) -> Result<String, LoadError> {
+        let len = self.count()?;
+        let bytes = &self.bytes[self.at..self.at + len];
+        self.at += len;
+        String::from_utf8(bytes.to_vec()).map_err(|_| LoadError::Utf8)
+    }
+
+    fn vtype(&mut self) -> Result<ValueType, LoadError> {
+        Ok(match self.byte()? {
+            0 => ValueType::Unit,
+            1 => ValueType::Nat,
+            2 => ValueType::Product(self.vtype()?.into(), self.vtype()?.into()),
+            3 => ValueType::Sum(self.vtype()?.into(), self.vtype()?.into()),
+            4 => ValueType::List(self.vtype()?.into()),
+            5 => ValueType::Thunk(self.ctype()?.into()),
+            tag => return Err(LoadError::Tag { kind : "type", tag }),
+        })
+    }
+
+    fn ctype(&mut self) -> Result<ComputationType, LoadError> {
+        Ok(match self.byte()? {
+            0 => ComputationType::Return(self.vtype()?.into()),
+            1 => ComputationType::Arrow(self.vtype()?.into(), self.ctype()?.into()),
+            tag => return Err(LoadError::Tag { kind : "computation type", tag }),
+        })
+    }
+
+    fn val(&mut self, depth : usize) -> Result<MValue, LoadError> {
+        Ok(match self.byte()? {
+            0 => match self.nat()? {
+                index if index < depth => MValue::Var(index),
+                index => return Err(LoadError::Index { index, depth }),
+            },
+            1 => MValue::Unit,
+            2 => MValue::Zero,
+            3 => MValue::Succ(self.val(depth)?.into()),
+            4 => MValue::Pair(self.val(depth)?.into(), self.val(depth)?.into()),
+            5 => MValue::Inl(self.val(depth)?.into()),
+            6 => MValue::Inr(self.val(depth)?.into()),
+            7 => MValue::Nil,
+            8 => MValue::Cons(self.val(depth)?.into(), self.val(depth)?.into()),
+            9 => MValue::Thunk(self.comp(depth)?.into()),
+            tag => return Err(LoadError::Tag { kind : "value", tag }),
+        })
+    }
+
+    // The binders are those of `step`: the successor case binds the
+    // predecessor, the cons case the head and tail, and so on
+    fn comp(&mut self, depth : usize) -> Result<MComputation, LoadError> {
+        Ok(match self.byte()? {
+            0 => MComputation::Ifz { num : self.val(depth)?.into(), zk : self.comp(depth)?.into(), sk : self.comp(depth + 1)?.into() },
+            1 => MComputation::Match { list : self.val(depth)?.into(), nilk : self.comp(depth)?.into(), consk : self.comp(depth + 2)?.into() },
+            2 => MComputation::Case { sum : self.val(depth)?.into(), inlk : self.comp(depth + 1)?.into(), inrk : self.comp(depth + 1)?.into() },
+            3 => MComputation::Return(self.val(depth)?.into()),
+            4 => MComputation::Bind { comp : self.comp(depth)?.into(), cont : self.comp(depth + 1)?.into() },
+            5 => MComputation::Force(self.val(depth)?.into()),
+            6 => MComputation::Lambda { body : self.comp(depth + 1)?.into() },
+            7 => MComputation::App { op : self.comp(depth)?.into(), arg : self.val(depth)?.into() },
+            8 => MComputation::Choice((0..self.count()?).map(|_| self.comp(depth).map(Arc::new)).collect::<Result<_, _>>()?),
+            9 => {
+                let ptype = self.vtype()?;
+                let name = match self.byte()? {
+                    0 => None,
+                    1 => Some(self.str()?),
+                    tag => return Err(LoadError::Tag { kind : "name", tag }),
+                };
+                MComputation::Exists { ptype, name, body : self.comp(depth + 1)?.into() }
+            },
+            10 => MComputation::Equate { lhs : self.val(depth)?.into(), rhs : self.val(depth)?.into(), body : self.comp(depth)?.into() },
+            11 => MComputation::Rec { body : self.comp(depth + 1)?.into() },
+            12 => {
+                let (id, name, arity) = (self.nat()?, self.str()?, self.nat()?);
+                if arity > depth { return Err(LoadError::Arity { arity, depth }) }
+                MComputation::Table { id, name, arity, body : self.comp(depth)?.into() }
+            },
+            13 => return Err(LoadError::Builtin(self.str()?)),
+            tag => return Err(LoadError::Tag { kind : "computation", tag }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{machine::{pretty, translate::{globals, query_type, translate}}, parser};
+    use super::*;
+
+    fn compile(src : &str) -> Compiled {
+        let ast = parser::parse(src).unwrap();
+        let (ptype, names) = (query_type(&ast), globals(&ast));
+        let (query, env) = translate(ast).unwrap();
+        Compiled { query, env, ptype, names }
+    }
+
+    #[test]
+    fn round_trip() {
+        for entry in fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            let src = fs::read_to_string(&path).unwrap();
+            // some examples use syntax the parser doesn't have
+            if parser::parse(&src).is_err() { continue }
+            let program = compile(&src);
+            let loaded = Compiled::decode(&program.encode().unwrap()).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            assert_eq!(loaded.query, program.query, "{}", path.display());
+            assert_eq!(loaded.ptype, program.ptype);
+            assert_eq!(loaded.names, program.names);
+            let names = Some(&program.names[..]);
+            assert_eq!(pretty::definitions(&loaded.env, names), pretty::definitions(&program.env, names), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn validation() {
+        let bytes = compile("f :: Nat -> Nat\nf x = x.\n\nf 1.").encode().unwrap();
+        assert_eq!(Compiled::decode(b"-- not compiled").err(), Some(LoadError::Magic));
+        assert_eq!(Compiled::decode(&bytes[..bytes.len() - 1]).err(), Some(LoadError::Truncated));
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(Compiled::decode(&longer).err(), Some(LoadError::Trailing(1)));
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert_eq!(Compiled::decode(&newer).err(), Some(LoadError::Version(2)));
+
+        // only `f` is in scope of the query
+        let unbound = Compiled { query : MComputation::Return(MValue::Var(1).into()), ..compile("f :: Nat -> Nat\nf x = x.\n\nf 1.") };
+        assert_eq!(Compiled::decode(&unbound.encode().unwrap()).err(), Some(LoadError::Index { index : 1, depth : 1 }));
+    }
+
+    #[test]
+    fn unsaveable() {
+        let program = || compile("f :: Nat -> Nat\nf x = x.\n\nf 1.");
+        let code = Compiled { query : MComputation::Code(0), ..program() };
+        assert_eq!(code.encode().err(), Some(SaveError::Code));
+        // the only function, closed over `f`
+        let closed = Compiled { env : Env::empty().extend_val(MValue::Unit.into(), program().env), ..program() };
+        assert_eq!(closed.encode().err(), Some(SaveError::Global(0)));
+    }
+}
@@ -0,0 +1,213 @@
+use std::time::Instant;
+
+use super::{answer, error::{Failure, OnError}, limits::{LimitHit, Limits}, step::Machine, store::{LogicStore, SuspStore}, stats::Stats, trace::{Event, NoTrace, Tracer}};
+
+// A machine that stepped to several branches, of which `next` onwards are
+// still to be explored, and the points to roll its stores back to first
+struct ChoicePoint<L, S> {
+    machine : Machine<L, S>,
+    next : usize,
+    alternatives : usize,
+    marks : (usize, usize),
+}
+
+// Depth-first search over the branches of `Machine::step_alt`, yielding
+// finished machines as they are found. With trailed stores every machine
+// shares one logic and one suspension store, and backtracking undoes the
+// changes made since the choice point. Backtracking is deferred to the
+// next call, so the stores of a yielded machine stay valid until then.
+// Tabled functions are evaluated without their tables.
+pub struct Dfs<L, S, T = NoTrace> {
+    current : Option<Machine<L, S>>,
+    choices : Vec<ChoicePoint<L, S>>,
+    limits : Limits,
+    start : Instant,
+    steps : usize,
+    stopped : Option<LimitHit>,
+    on_error : OnError,
+    failures : Vec<Failure>,
+    tracer : T,
+    stats : Option<Stats>,
+}
+
+impl<L : LogicStore, S : SuspStore> Dfs<L, S> {
+
+    pub fn new(m : Machine<L, S>, limits : Limits) -> Dfs<L, S> {
+        Dfs { current : Some(m), choices : vec![], limits, start : Instant::now(), steps : 0, stopped : None, on_error : OnError::default(), failures : vec![], tracer : NoTrace, stats : None }
+    }
+}
+
+impl<L : LogicStore, S : SuspStore, T : Tracer> Dfs<L, S, T> {
+
+    pub fn with_on_error(self, on_error : OnError) -> Dfs<L, S, T> { Dfs { on_error, ..self } }
+
+    pub fn with_tracer<U : Tracer>(self, tracer : U) -> Dfs<L, S, U> {
+        let Dfs { current, choices, limits, start, steps, stopped, on_error, failures, stats, .. } = self;
+        Dfs { current, choices, limits, start, steps, stopped, on_error, failures, tracer, stats }
+    }
+
+    // Counts what the search does
+    pub fn with_stats(self, stats : Stats) -> Dfs<L, S, T> { Dfs { stats : Some(stats), ..self } }
+
+    pub fn take_stats(&mut self) -> Option<Stats> { self.stats.take() }
+
+    pub fn steps(&self) -> usize { self.steps }
+
+    // The branches that ended in runtime errors
+    pub fn failures(&self) -> &[Failure] { &self.failures }
+
+    // Whether a runtime error stopped the search
+    pub fn aborted(&self) -> bool { self.on_error == OnError::Abort && !self.failures.is_empty() }
+
+    // Steps `m` to its `k`th branch, recording the failure if it errs
+    fn step_alt(&mut self, m : Machine<L, S>, k : usize) -> (Option<Machine<L, S>>, usize) {
+        let steps = m.steps + 1;
+        self.steps += 1;
+        self.tracer.event(m.steps, Event::Step { comp : &m.comp });
+        let result = match &mut self.stats {
+            Some(stats) => {
+                stats.frontier(self.choices.len() + 1);
+                stats.event(m.steps, Event::Step { comp : &m.comp });
+                m.step_alt(k, &mut (&mut self.tracer, stats))
+            },
+            None => m.step_alt(k, &mut self.tracer),
+        };
+        result.unwrap_or_else(|error| {
+            self.failures.push(Failure { steps, error });
+            (None, 0)
+        })
+    }
+
+    // The limit that cut the search short, if any
+    pub fn limit_hit(&self) -> Option<LimitHit> { self.stopped }
+
+    // Steps to the next unexplored branch of the latest choice point
+    fn backtrack(&mut self) -> Option<Machine<L, S>> {
+        while !self.aborted() {
+            let cp = self.choices.last_mut()?;
+            cp.machine.lenv.undo(cp.marks.0);
+            cp.machine.senv.undo(cp.marks.1);
+            let k = cp.next;
+            cp.next += 1;
+            let m = if cp.next == cp.alternatives {
+                self.choices.pop().unwrap().machine
+            } else {
+                cp.machine.clone()
+            };
+            if let (Some(m), _) = self.step_alt(m, k) { return Some(m) }
+        }
+        None
+    }
+}
+
+impl<L : LogicStore, S : SuspStore, T : Tracer> Iterator for Dfs<L, S, T> {
+    type Item = Machine<L, S>;
+
+    fn next(&mut self) -> Option<Machine<L, S>> {
+        while self.stopped.is_none() && !self.aborted() {
+            let m = match self.current.take() {
+                Some(m) => m,
+                None => self.backtrack()?,
+            };
+            if m.done {
+                if self.tracer.enabled() {
+                    if let Ok(answer) = answer(&m, None, false) { self.tracer.event(m.steps, Event::Answer { answer : &answer }) }
+                }
+                return Some(m)
+            }
+
+            // the choice points stand for the frontier
+            if let Some(hit) = self.limits.check(self.steps, self.choices.len(), m.lenv.size(), self.start) {
+                self.stopped = Some(hit);
+                break
+            }
+
+            let marks = (m.lenv.mark(), m.senv.mark());
+            let (next, alternatives) = self.step_alt(m.clone(), 0);
+            if alternatives > 1 {
+                self.choices.push(ChoicePoint { machine : m, next : 1, alternatives, marks });
+            }
+            self.current = next;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{machine::{lvar::LogicEnv, mterms::MComputation, output, search::Search, senv::SuspEnv, trail::{TrailLogicEnv, TrailSuspEnv}, translate::translate}, parser};
+    use super::*;
+
+    fn answers<L : LogicStore, S : SuspStore>(ms : impl Iterator<Item = Machine<L, S>>) -> Vec<String> {
+        ms.map(|m| match &*m.comp {
+                MComputation::Return(v) => output(v.clone(), m.env.clone(), None, &[], &m.lenv, &m.senv).unwrap(),
+                _ => unreachable!()
+            })
+            .collect()
+    }
+
+    fn dfs<L : LogicStore, S : SuspStore>(src : &str) -> Vec<String> {
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        answers(Dfs::new(Machine::<L, S>::new(comp, env), Limits::default()))
+    }
+
+    fn bfs(src : &str) -> Vec<String> {
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        answers(Search::new(Machine::new(comp, env), Limits::default()))
+    }
+
+    fn agree(src : &str) {
+        let persistent = dfs::<LogicEnv, SuspEnv>(src);
+        let trailed = dfs::<TrailLogicEnv, TrailSuspEnv>(src);
+        assert_eq!(persistent, trailed);
+        let (mut sorted, mut breadth) = (trailed, bfs(src));
+        sorted.sort();
+        breadth.sort();
+        assert_eq!(sorted, breadth);
+    }
+
+    #[test]
+    fn choices() {
+        agree("(1 <> 2) <> (3 <> 4).");
+    }
+
+    #[test]
+    fn narrowing() {
+        agree("add :: Nat -> Nat -> Nat
+add n m = case m of
+      Z -> n
+    | S z -> S (add n z).
+
+exists x :: Nat. exists y :: Nat. add x y =:= 6. (x, y).");
+    }
+
+    #[test]
+    fn lists() {
+        agree("append :: [Nat] -> [Nat] -> [Nat]
+append xs ys = case xs of
+      [] -> ys
+    | (z:zs) -> z : (append zs ys).
+
+exists xs :: [Nat]. exists ys :: [Nat]. append xs ys =:= [1, 2, 3]. (xs, ys).");
+    }
+
+    #[test]
+    fn suspensions() {
+        agree("let a = (1 <> 2) in let b = (3 <> 4) in (a, b).");
+        agree("let a = (1 <> 2) in let b = (3 <> 4) in 5.");
+    }
+
+    #[test]
+    fn step_limit() {
+        let src = "poke :: Nat -> Nat
+poke n = case n of
+    Z -> 0
+  | S z -> S (poke z).
+
+exists x :: Nat. poke x.";
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        let mut dfs = Dfs::new(Machine::<TrailLogicEnv, TrailSuspEnv>::new(comp, env), Limits { max_steps : Some(500), ..Limits::default() });
+        assert!(dfs.by_ref().count() > 0);
+        assert_eq!(dfs.limit_hit(), Some(LimitHit::Steps(500)));
+    }
+}
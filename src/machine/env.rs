@@ -1,4 +1,4 @@
-use std::rc::Rc;
+use std::{any::Any, cell::RefCell, sync::Arc};
 use im::Vector;
 use super::Ident;
 use super::{mterms::MValue, VClosure};
@@ -10,29 +10,75 @@ pub struct Env {
 
 impl Env {
 
-    pub fn empty() -> Rc<Env> { 
+    pub fn empty() -> Arc<Env> { 
         Env { vec : Vector::new() }.into()
     }
 
     pub fn lookup(&self, i : usize) -> Option<VClosure> {
-        self.vec.get(i).map(|v| v.clone())
+        self.vec.get(i).cloned()
     }
     
+    pub fn iter(&self) -> impl Iterator<Item = &VClosure> { self.vec.iter() }
+
     fn extend(&self, vclos : VClosure) -> Env {
         let mut vec = self.vec.clone();
         vec.push_front(vclos);
         Env { vec }
     }
 
-    pub fn extend_val(&self, val : Rc<MValue>, env : Rc<Env>) -> Rc<Env> {
+    // Binds a new innermost variable in place, so a machine that owns its
+    // environment extends it without copying
+    pub fn push(&mut self, vclos : VClosure) { self.vec.push_front(vclos) }
+
+    pub fn set(&self, i : usize, vclos : VClosure) -> Arc<Env> {
+        Env { vec : self.vec.update(i, vclos) }.into()
+    }
+
+    pub fn extend_val(&self, val : Arc<MValue>, env : Arc<Env>) -> Arc<Env> {
         self.extend( VClosure::Clos { val, env }).into()
     }
 
-    pub fn extend_lvar(&self, ident : Ident) -> Rc<Env> {
+    pub fn extend_lvar(&self, ident : Ident) -> Arc<Env> {
         self.extend(VClosure::LogicVar { ident }).into()
     }
 
-    pub fn extend_susp(&self, ident : Ident) -> Rc<Env> {
+    pub fn extend_susp(&self, ident : Ident) -> Arc<Env> {
         self.extend(VClosure::Susp { ident }).into()
     }
-}
\ No newline at end of file
+}
+// Environments hold closures over older environments, so dropping the
+// last reference to a long chain of them would recurse once per link
+impl Drop for Env {
+    fn drop(&mut self) { drop_nested(std::mem::take(&mut self.vec)) }
+}
+
+// How deeply drops may nest before the rest are deferred
+const MAX_DROP_DEPTH : usize = 64;
+
+thread_local! {
+    // how deeply `drop_nested` calls are nested, and what they deferred
+    static DROPS : RefCell<(usize, Vec<Box<dyn Any>>)> = const { RefCell::new((0, vec![])) };
+}
+
+// Drops `parts`, unless drops are already nested deep, in which case the
+// outermost drop drops them once the others have returned
+fn drop_nested(parts : impl Any) {
+    let depth = DROPS.with(|drops| {
+        let mut drops = drops.borrow_mut();
+        drops.0 += 1;
+        drops.0
+    });
+    if depth > MAX_DROP_DEPTH {
+        DROPS.with(|drops| {
+            let mut drops = drops.borrow_mut();
+            drops.1.push(Box::new(parts));
+            drops.0 -= 1
+        });
+        return
+    }
+    drop(parts);
+    if depth == 1 {
+        while let Some(parts) = DROPS.with(|drops| drops.borrow_mut().1.pop()) { drop(parts) }
+    }
+    DROPS.with(|drops| drops.borrow_mut().0 -= 1)
+}
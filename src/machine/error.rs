@@ -0,0 +1,68 @@
+use std::{fmt::Display, sync::Arc};
+
+use super::{mterms::MValue, value_type::ValueType};
+
+// Ill-formed states a machine can reach. Each ends the branch it occurs
+// in, and carries the term that couldn't be eliminated.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RuntimeError {
+    // `force` of a value that isn't a thunk
+    ForceValue(Arc<MValue>),
+    // `force` of an unbound logic variable
+    ForceLogicVar(ValueType),
+    // a lambda with no argument on the stack
+    NoArgument,
+    // an eliminator met a value of the wrong shape
+    Eliminate { expected : &'static str, found : Arc<MValue> },
+    // an eliminator met an unbound logic variable of the wrong type
+    Narrow { expected : &'static str, found : ValueType },
+    // unification reached a thunk
+    UnifyThunk,
+    // an answer contains a thunk
+    CloseThunk,
+    // an answer depends on a suspension that never ran
+    CloseSusp,
+    // a builtin's arguments can't be made ground
+    Builtin { name : String, reason : &'static str },
+    // a builtin returned a value not of its result type
+    BuiltinResult { name : String, found : Arc<MValue> },
+    // a tabled function returned a value its table can't hold
+    Table { name : String, reason : &'static str },
+}
+
+impl Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::ForceValue(v) => write!(f, "forced {}, which isn't a thunk", v),
+            RuntimeError::ForceLogicVar(t) => write!(f, "forced a logic variable of type {:?}", t),
+            RuntimeError::NoArgument => write!(f, "applied a lambda to no argument"),
+            RuntimeError::Eliminate { expected, found } => write!(f, "expected {} but found {}", expected, found),
+            RuntimeError::Narrow { expected, found } =>
+                write!(f, "expected {} but found a logic variable of type {:?}", expected, found),
+            RuntimeError::UnifyThunk => write!(f, "tried to unify a thunk"),
+            RuntimeError::CloseThunk => write!(f, "the answer contains a thunk"),
+            RuntimeError::CloseSusp => write!(f, "the answer depends on a suspension that never ran"),
+            RuntimeError::Builtin { name, reason } => write!(f, "{} was called on {}", name, reason),
+            RuntimeError::BuiltinResult { name, found } => write!(f, "{} returned {}, which isn't of its result type", name, found),
+            RuntimeError::Table { name, reason } => write!(f, "tabled function {} returned {}", name, reason),
+        }
+    }
+}
+
+// What a search does with the other branches once one has failed with a
+// runtime error
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OnError {
+    // report the error and carry on with the other branches
+    #[default]
+    Keep,
+    // report the error and stop the search
+    Abort,
+}
+
+// A branch that ended in a runtime error, and the step it reached
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub steps : usize,
+    pub error : RuntimeError,
+}
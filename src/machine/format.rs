@@ -0,0 +1,307 @@
+use super::{error::{Failure, RuntimeError}, gc::GcStats, stats::Stats, limits::LimitHit, term::{Answer, Term, Typed}, value_type::ValueType};
+
+// How answers and the summary of a search are printed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    // `> answer` lines and `>>>` reports, for people
+    #[default]
+    Text,
+    // one JSON object per line
+    Json,
+    // one S-expression per line
+    Sexp,
+}
+
+// How a search went, as reported after its answers
+pub struct Summary<'a> {
+    pub solutions : usize,
+    pub steps : usize,
+    pub limit_hit : Option<LimitHit>,
+    pub aborted : bool,
+    pub failures : &'a [Failure],
+    pub gc : Option<GcStats>,
+    pub stats : Option<&'a Stats>,
+}
+
+impl Summary<'_> {
+    fn termination(&self) -> &'static str {
+        if self.aborted { "aborted" } else if self.limit_hit.is_some() { "limit" } else { "exhausted" }
+    }
+}
+
+pub(super) fn json_string(s : &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn sexp_string(s : &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// A term as a JSON value tagged with its kind, e.g. `{"nat":2}` or
+// `{"list":[{"var":0}]}`. Booleans are told from other sums by their type.
+fn json(term : &Term, ptype : Option<&ValueType>) -> String {
+    let elem = match ptype {
+        Some(ValueType::List(t)) => Some(&**t),
+        _ => None
+    };
+    let (left, right) = match ptype {
+        Some(ValueType::Product(t, u)) | Some(ValueType::Sum(t, u)) => (Some(&**t), Some(&**u)),
+        _ => (None, None)
+    };
+    let is_bool = ptype == Some(&ValueType::bool());
+    match term {
+        Term::Unit => "{\"unit\":null}".to_string(),
+        Term::Zero => "{\"nat\":0}".to_string(),
+        Term::Succ(t) => match term.nat() {
+            Some(n) => format!("{{\"nat\":{}}}", n),
+            None => format!("{{\"succ\":{}}}", json(t, ptype)),
+        },
+        Term::Nil | Term::Cons(_, _) => {
+            let mut xs = vec![];
+            let mut rest = term;
+            while let Term::Cons(x, xs1) = rest {
+                xs.push(json(x, elem));
+                rest = xs1
+            }
+            match rest {
+                Term::Nil => format!("{{\"list\":[{}]}}", xs.join(",")),
+                _ => format!("{{\"list\":[{}],\"tail\":{}}}", xs.join(","), json(rest, ptype)),
+            }
+        },
+        Term::Pair(t, u) => format!("{{\"pair\":[{},{}]}}", json(t, left), json(u, right)),
+        Term::Inl(_) if is_bool => "{\"bool\":true}".to_string(),
+        Term::Inr(_) if is_bool => "{\"bool\":false}".to_string(),
+        Term::Inl(t) => format!("{{\"left\":{}}}", json(t, left)),
+        Term::Inr(t) => format!("{{\"right\":{}}}", json(t, right)),
+        Term::Var(i) => format!("{{\"var\":{}}}", i),
+    }
+}
+
+// A term as an S-expression headed by its kind, e.g. `(nat 2)`
+fn sexp(term : &Term, ptype : Option<&ValueType>) -> String {
+    let elem = match ptype {
+        Some(ValueType::List(t)) => Some(&**t),
+        _ => None
+    };
+    let (left, right) = match ptype {
+        Some(ValueType::Product(t, u)) | Some(ValueType::Sum(t, u)) => (Some(&**t), Some(&**u)),
+        _ => (None, None)
+    };
+    let is_bool = ptype == Some(&ValueType::bool());
+    match term {
+        Term::Unit => "(unit)".to_string(),
+        Term::Zero => "(nat 0)".to_string(),
+        Term::Succ(t) => match term.nat() {
+            Some(n) => format!("(nat {})", n),
+            None => format!("(succ {})", sexp(t, ptype)),
+        },
+        Term::Nil | Term::Cons(_, _) => {
+            let mut xs = vec![];
+            let mut rest = term;
+            while let Term::Cons(x, xs1) = rest {
+                xs.push(sexp(x, elem));
+                rest = xs1
+            }
+            let xs : String = xs.iter().map(|x| format!(" {}", x)).collect();
+            match rest {
+                Term::Nil => format!("(list{})", xs),
+                _ => format!("(list{} . {})", xs, sexp(rest, ptype)),
+            }
+        },
+        Term::Pair(t, u) => format!("(pair {} {})", sexp(t, left), sexp(u, right)),
+        Term::Inl(_) if is_bool => "(bool true)".to_string(),
+        Term::Inr(_) if is_bool => "(bool false)".to_string(),
+        Term::Inl(t) => format!("(left {})", sexp(t, left)),
+        Term::Inr(t) => format!("(right {})", sexp(t, right)),
+        Term::Var(i) => format!("(var {})", i),
+    }
+}
+
+impl Format {
+
+    pub fn answer(self, answer : &Answer) -> String {
+        let json_typed = |t : &Typed| json(&t.term, t.ptype.as_ref());
+        let sexp_typed = |t : &Typed| sexp(&t.term, t.ptype.as_ref());
+        match self {
+            Format::Text => format!("> {}", answer),
+            Format::Json => {
+                let mut out = format!("{{\"answer\":{}", json_typed(&answer.value));
+                if !answer.bindings.is_empty() {
+                    let bindings : Vec<String> = answer.bindings.iter()
+                        .map(|(var, t)| format!("{}:{}", json_string(var), json_typed(t))).collect();
+                    out += &format!(",\"bindings\":{{{}}}", bindings.join(","));
+                }
+                if !answer.equations.is_empty() {
+                    let equations : Vec<String> = answer.equations.iter()
+                        .map(|(name, t)| format!("{{\"var\":{},\"value\":{}}}", name, json_typed(t))).collect();
+                    out += &format!(",\"equations\":[{}]", equations.join(","));
+                }
+                if !answer.residuals.is_empty() {
+                    let residuals : Vec<String> = answer.residuals.iter()
+                        .map(|r| {
+                            let args : Vec<String> = r.args.iter().map(json_typed).collect();
+                            format!("{{\"call\":{},\"args\":[{}],\"result\":{}}}", json_string(&r.name), args.join(","), json_typed(&r.result))
+                        }).collect();
+                    out += &format!(",\"residuals\":[{}]", residuals.join(","));
+                }
+                out + "}"
+            },
+            Format::Sexp => {
+                let mut out = format!("(answer {}", sexp_typed(&answer.value));
+                if !answer.bindings.is_empty() {
+                    let bindings : String = answer.bindings.iter()
+                        .map(|(var, t)| format!(" ({} {})", var, sexp_typed(t))).collect();
+                    out += &format!(" (bindings{})", bindings);
+                }
+                if !answer.equations.is_empty() {
+                    let equations : String = answer.equations.iter()
+                        .map(|(name, t)| format!(" ({} {})", name, sexp_typed(t))).collect();
+                    out += &format!(" (equations{})", equations);
+                }
+                if !answer.residuals.is_empty() {
+                    let residuals : String = answer.residuals.iter()
+                        .map(|r| {
+                            let args : String = r.args.iter().map(|t| format!(" {}", sexp_typed(t))).collect();
+                            format!(" (({}{}) {})", r.name, args, sexp_typed(&r.result))
+                        }).collect();
+                    out += &format!(" (residuals{})", residuals);
+                }
+                out + ")"
+            },
+        }
+    }
+
+    // A branch whose answer couldn't be printed
+    pub fn answer_error(self, error : &RuntimeError) -> String {
+        match self {
+            Format::Text => format!(">>> branch failed on its answer: {}", error),
+            Format::Json => format!("{{\"error\":{}}}", json_string(&error.to_string())),
+            Format::Sexp => format!("(error {})", sexp_string(&error.to_string())),
+        }
+    }
+
+    pub fn summary(self, summary : &Summary) -> String {
+        match self {
+            Format::Text => {
+                let mut lines = vec![format!(">>> {} solutions", summary.solutions)];
+                for failure in summary.failures {
+                    lines.push(format!(">>> branch failed at step {}: {}", failure.steps, failure.error));
+                }
+                if summary.aborted {
+                    lines.push(format!(">>> search aborted after {} steps", summary.steps));
+                }
+                if let Some(hit) = summary.limit_hit {
+                    lines.push(format!(">>> search stopped after {} steps: {}", summary.steps, hit));
+                }
+                if let Some(gc) = summary.gc {
+                    lines.push(format!(">>> gc: {} sweeps reclaimed {} logic variables and {} suspensions", gc.sweeps, gc.lvars, gc.susps));
+                }
+                if let Some(stats) = summary.stats {
+                    let [nat, list, sum] = stats.narrowings;
+                    lines.push(format!(">>> steps: {}", summary.steps));
+                    lines.push(format!(">>> frontier: peak {}, average {:.2}", stats.peak_frontier, stats.average_frontier()));
+                    lines.push(format!(">>> forks: {} choices, {} number narrowings, {} list narrowings, {} sum narrowings", stats.choices, nat, list, sum));
+                    lines.push(format!(">>> failed unifications: {}, and {} on the occurs check", stats.unify_failures, stats.occurs_failures));
+                    lines.push(format!(">>> suspensions: {} created, {} forced", stats.suspended, stats.forced));
+                    let calls : Vec<String> = stats.calls.iter().map(|(f, n)| format!("{} {}", f, n)).collect();
+                    lines.push(format!(">>> calls: {}", calls.join(", ")));
+                }
+                lines.join("\n")
+            },
+            Format::Json => {
+                let mut out = format!("{{\"summary\":{{\"solutions\":{},\"steps\":{},\"termination\":{}",
+                    summary.solutions, summary.steps, json_string(summary.termination()));
+                if let Some(hit) = summary.limit_hit {
+                    out += &format!(",\"limit\":{}", json_string(&hit.to_string()));
+                }
+                let failures : Vec<String> = summary.failures.iter()
+                    .map(|failure| format!("{{\"step\":{},\"error\":{}}}", failure.steps, json_string(&failure.error.to_string()))).collect();
+                out += &format!(",\"failures\":[{}]", failures.join(","));
+                if let Some(gc) = summary.gc {
+                    out += &format!(",\"gc\":{{\"sweeps\":{},\"lvars\":{},\"susps\":{}}}", gc.sweeps, gc.lvars, gc.susps);
+                }
+                if let Some(stats) = summary.stats {
+                    let [nat, list, sum] = stats.narrowings;
+                    let calls : Vec<String> = stats.calls.iter().map(|(f, n)| format!("{}:{}", json_string(f), n)).collect();
+                    out += &format!(",\"stats\":{{\"frontier\":{{\"peak\":{},\"average\":{:.2}}},\"forks\":{{\"choice\":{},\"nat\":{},\"list\":{},\"sum\":{}}},\"unify_failures\":{},\"occurs_failures\":{},\"suspensions\":{{\"created\":{},\"forced\":{}}},\"calls\":{{{}}}}}",
+                        stats.peak_frontier, stats.average_frontier(), stats.choices, nat, list, sum,
+                        stats.unify_failures, stats.occurs_failures, stats.suspended, stats.forced, calls.join(","));
+                }
+                out + "}}"
+            },
+            Format::Sexp => {
+                let mut out = format!("(summary (solutions {}) (steps {}) (termination {})",
+                    summary.solutions, summary.steps, summary.termination());
+                if let Some(hit) = summary.limit_hit {
+                    out += &format!(" (limit {})", sexp_string(&hit.to_string()));
+                }
+                let failures : String = summary.failures.iter()
+                    .map(|failure| format!(" ({} {})", failure.steps, sexp_string(&failure.error.to_string()))).collect();
+                out += &format!(" (failures{})", failures);
+                if let Some(gc) = summary.gc {
+                    out += &format!(" (gc (sweeps {}) (lvars {}) (susps {}))", gc.sweeps, gc.lvars, gc.susps);
+                }
+                if let Some(stats) = summary.stats {
+                    let [nat, list, sum] = stats.narrowings;
+                    let calls : String = stats.calls.iter().map(|(f, n)| format!(" ({} {})", sexp_string(f), n)).collect();
+                    out += &format!(" (stats (frontier (peak {}) (average {:.2})) (forks (choice {}) (nat {}) (list {}) (sum {})) (unify-failures {}) (occurs-failures {}) (suspensions (created {}) (forced {})) (calls{}))",
+                        stats.peak_frontier, stats.average_frontier(), stats.choices, nat, list, sum,
+                        stats.unify_failures, stats.occurs_failures, stats.suspended, stats.forced, calls);
+                }
+                out + ")"
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::machine::term::Residual;
+    use super::*;
+
+    fn nat(n : usize) -> Term { (0..n).fold(Term::Zero, |t, _| Term::Succ(Box::new(t))) }
+
+    #[test]
+    fn terms() {
+        let list = Term::Cons(Box::new(nat(1)), Box::new(Term::Cons(Box::new(Term::Var(0)), Box::new(Term::Var(1)))));
+        assert_eq!(json(&list, None), r#"{"list":[{"nat":1},{"var":0}],"tail":{"var":1}}"#);
+        assert_eq!(sexp(&list, None), "(list (nat 1) (var 0) . (var 1))");
+        let pair = Term::Pair(Box::new(Term::Inl(Box::new(Term::Unit))), Box::new(Term::Succ(Box::new(Term::Var(0)))));
+        let ptype = ValueType::Product(Box::new(ValueType::bool()), Box::new(ValueType::Nat));
+        assert_eq!(json(&pair, Some(&ptype)), r#"{"pair":[{"bool":true},{"succ":{"var":0}}]}"#);
+        assert_eq!(sexp(&pair, None), "(pair (left (unit)) (succ (var 0)))");
+    }
+
+    #[test]
+    fn residuals() {
+        let typed = |term| Typed { term, ptype : Some(ValueType::Nat) };
+        let residual = Residual { name : "price".to_string(), args : vec![typed(Term::Succ(Box::new(Term::Var(1))))], result : typed(Term::Var(0)) };
+        let answer = Answer { bindings : vec![], value : typed(Term::Var(0)), equations : vec![], residuals : vec![residual] };
+        assert_eq!(Format::Text.answer(&answer), "> _0 where price (S _1) = _0");
+        assert_eq!(Format::Json.answer(&answer),
+            r#"{"answer":{"var":0},"residuals":[{"call":"price","args":[{"succ":{"var":1}}],"result":{"var":0}}]}"#);
+        assert_eq!(Format::Sexp.answer(&answer), "(answer (var 0) (residuals ((price (succ (var 1))) (var 0))))");
+    }
+
+    #[test]
+    fn summaries() {
+        let failures = [Failure { steps : 3, error : RuntimeError::NoArgument }];
+        let summary = Summary { solutions : 2, steps : 10, limit_hit : Some(LimitHit::Steps(10)), aborted : false, failures : &failures, gc : None, stats : None };
+        assert_eq!(Format::Json.summary(&summary),
+            r#"{"summary":{"solutions":2,"steps":10,"termination":"limit","limit":"step limit of 10 reached","failures":[{"step":3,"error":"applied a lambda to no argument"}]}}"#);
+        assert_eq!(Format::Sexp.summary(&summary),
+            r#"(summary (solutions 2) (steps 10) (termination limit) (limit "step limit of 10 reached") (failures (3 "applied a lambda to no argument")))"#);
+        assert_eq!(json_string("a \"b\"\n"), r#""a \"b\"\n""#);
+    }
+}
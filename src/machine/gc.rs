@@ -0,0 +1,185 @@
+use std::{collections::HashSet, ops::AddAssign, sync::Arc};
+
+use im::Vector;
+
+use super::{builtin::Goal, bytecode::{BMachine, Frame}, env::Env, lvar::LogicEnv, mterms::MValue, senv::SuspEnv, step::Machine, table::Producer, Ident, VClosure};
+
+// What the sweeps of a search have reclaimed
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GcStats {
+    pub sweeps : usize,
+    pub lvars : usize,
+    pub susps : usize,
+}
+
+impl AddAssign for GcStats {
+    fn add_assign(&mut self, other : GcStats) {
+        self.sweeps += other.sweeps;
+        self.lvars += other.lvars;
+        self.susps += other.susps;
+    }
+}
+
+// Marks the logic variables and suspensions reachable from a machine.
+// Closures over thunks keep their whole environment alive, since the free
+// variables of a computation aren't tracked.
+struct Marker<'a> {
+    lenv : &'a LogicEnv,
+    senv : &'a SuspEnv,
+    lvars : HashSet<Ident>,
+    susps : HashSet<Ident>,
+    envs : HashSet<*const Env>,
+}
+
+impl Marker<'_> {
+
+    fn env(&mut self, env : &Arc<Env>) {
+        if !self.envs.insert(Arc::as_ptr(env)) { return }
+        env.iter().for_each(|vclos| self.vclos(vclos));
+    }
+
+    fn vclos(&mut self, vclos : &VClosure) {
+        match vclos {
+            VClosure::Clos { val, env } => self.val(val, env),
+            VClosure::LogicVar { ident } => self.lvar(*ident),
+            VClosure::Susp { ident } => self.susp(*ident),
+        }
+    }
+
+    fn val(&mut self, val : &Arc<MValue>, env : &Arc<Env>) {
+        match &**val {
+            MValue::Var(i) => self.vclos(&env.lookup(*i).expect("index undefined in env")),
+            MValue::Unit | MValue::Zero | MValue::Nil => (),
+            MValue::Succ(v) | MValue::Inl(v) | MValue::Inr(v) => self.val(v, env),
+            MValue::Pair(v, w) | MValue::Cons(v, w) => { self.val(v, env); self.val(w, env) },
+            MValue::Thunk(_) => self.env(env),
+        }
+    }
+
+    fn lvar(&mut self, ident : Ident) {
+        for ident in self.lenv.path(ident) {
+            if self.lvars.insert(ident) {
+                if let Some(vclos) = self.lenv.binding(ident) { self.vclos(&vclos) }
+            }
+        }
+    }
+
+    // What keeps variables alive besides the environment and stack: the
+    // pending suspensions, the named query variables, the waiting builtin
+    // calls and a producer's arguments
+    fn roots(&mut self, bindings : &Vector<(String, Ident)>, goals : &Vector<Goal>, producer : Option<&Producer>) {
+        for ident in self.senv.pending().cloned().collect::<Vec<_>>() { self.susp(ident) }
+        bindings.iter().for_each(|(_, ident)| self.lvar(*ident));
+        for goal in goals {
+            self.env(&goal.env);
+            self.lvar(goal.result)
+        }
+        if let Some(producer) = producer {
+            producer.args.iter().for_each(|vclos| self.vclos(vclos));
+        }
+    }
+
+    fn susp(&mut self, ident : Ident) {
+        if !self.susps.insert(ident) { return }
+        match self.senv.entry(ident) {
+            Some(Ok(vclos)) => self.vclos(&vclos.clone()),
+            Some(Err((_, env))) => self.env(&env.clone()),
+            None => unreachable!("unknown suspension ident"),
+        }
+    }
+}
+
+impl Machine {
+
+    // Drops the logic variables and forced suspensions that are no longer
+    // reachable from the computation, environment, stack, pending
+    // suspensions, named query variables or waiting builtin calls of the
+    // machine
+    pub fn collect(&mut self) -> GcStats {
+        let (lvars, susps) = {
+            let mut marker = Marker { lenv : &self.lenv, senv : &self.senv, lvars : HashSet::new(), susps : HashSet::new(), envs : HashSet::new() };
+            marker.env(&self.env);
+            for (val, env) in self.stack.frames() {
+                match val {
+                    Some(val) => marker.val(&val, &env),
+                    None => marker.env(&env),
+                }
+            }
+            marker.roots(&self.bindings, &self.goals, self.producer.as_ref());
+            (marker.lvars, marker.susps)
+        };
+        GcStats { sweeps : 1, lvars : self.lenv.retain(&lvars), susps : self.senv.retain(&susps) }
+    }
+}
+
+impl BMachine {
+
+    // `Machine::collect` for a machine running compiled code
+    pub fn collect(&mut self) -> GcStats {
+        let (lvars, susps) = {
+            let mut marker = Marker { lenv : &self.lenv, senv : &self.senv, lvars : HashSet::new(), susps : HashSet::new(), envs : HashSet::new() };
+            marker.env(&self.env);
+            for frame in &self.stack {
+                match frame {
+                    Frame::Value(vclos) => marker.vclos(vclos),
+                    Frame::Set(_, _, env) => marker.env(env),
+                }
+            }
+            marker.roots(&self.bindings, &self.goals, self.producer.as_ref());
+            (marker.lvars, marker.susps)
+        };
+        GcStats { sweeps : 1, lvars : self.lenv.retain(&lvars), susps : self.senv.retain(&susps) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{machine::{limits::Limits, mterms::MComputation, output, search::Search, translate::translate}, parser};
+    use super::*;
+
+    fn answers(src : &str, gc : Option<usize>) -> (Vec<String>, GcStats) {
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        let mut search = Search::new(Machine::new(comp, env), Limits::default()).with_gc(gc);
+        let answers = search.by_ref()
+            .map(|m| match &*m.comp {
+                MComputation::Return(v) => output(v.clone(), m.env.clone(), None, &[], &m.lenv, &m.senv).unwrap(),
+                _ => unreachable!()
+            })
+            .collect();
+        (answers, search.gc_stats())
+    }
+
+    #[test]
+    fn reclaims_dead_variables() {
+        let src = "count :: Nat -> Nat
+count n = case n of
+      Z -> 0
+    | S m -> (exists y :: Nat. y =:= m. S (count m)).
+
+count 30.";
+        let (answers, stats) = answers(src, Some(5));
+        assert_eq!(answers, vec!["30"]);
+        assert!(stats.sweeps > 0);
+        assert!(stats.lvars > 0);
+    }
+
+    #[test]
+    fn keeps_pending_suspensions() {
+        let src = "let a = (1 <> 2) in let b = (3 <> 4) in 5.";
+        assert_eq!(answers(src, Some(1)).0, answers(src, None).0);
+        assert_eq!(answers(src, Some(1)).0.len(), 4);
+    }
+
+    #[test]
+    fn agrees_without_gc() {
+        let src = "add :: Nat -> Nat -> Nat
+add n m = case m of
+      Z -> n
+    | S z -> S (add n z).
+
+exists x :: Nat. exists y :: Nat. add x y =:= 10. (x, y).";
+        let (answers, stats) = answers(src, Some(3));
+        assert_eq!(answers, self::answers(src, None).0);
+        assert!(stats.susps > 0);
+    }
+}
@@ -0,0 +1,52 @@
+use std::{fmt::Display, time::{Duration, Instant}};
+
+// Resource limits on a search, `None` meaning unbounded
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    pub max_steps : Option<usize>,
+    pub timeout : Option<Duration>,
+    pub max_frontier : Option<usize>,
+    pub max_lvars : Option<usize>,
+}
+
+impl Limits {
+
+    // The limit reached by a search started at `start`, before it takes
+    // another step, having taken `steps` with `frontier` machines left to
+    // step, the next of which has `lvars` logic variables
+    pub fn check(&self, steps : usize, frontier : usize, lvars : usize, start : Instant) -> Option<LimitHit> {
+        if let Some(n) = self.max_steps {
+            if steps >= n { return Some(LimitHit::Steps(n)) }
+        }
+        if let Some(d) = self.timeout {
+            if start.elapsed() >= d { return Some(LimitHit::Timeout(d)) }
+        }
+        if let Some(n) = self.max_frontier {
+            if frontier > n { return Some(LimitHit::Frontier(n)) }
+        }
+        if let Some(n) = self.max_lvars {
+            if lvars > n { return Some(LimitHit::LogicVars(n)) }
+        }
+        None
+    }
+}
+
+// Why a search stopped before exhausting its frontier
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LimitHit {
+    Steps(usize),
+    Timeout(Duration),
+    Frontier(usize),
+    LogicVars(usize),
+}
+
+impl Display for LimitHit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitHit::Steps(n) => write!(f, "step limit of {} reached", n),
+            LimitHit::Timeout(d) => write!(f, "timeout of {:?} reached", d),
+            LimitHit::Frontier(n) => write!(f, "frontier limit of {} machines reached", n),
+            LimitHit::LogicVars(n) => write!(f, "logic variable limit of {} reached", n),
+        }
+    }
+}
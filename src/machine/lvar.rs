@@ -1,66 +1,95 @@
-use std::{cell::RefCell, ptr, rc::Rc};
+use std::{cell::RefCell, collections::HashSet, ptr, sync::Arc};
 
 use im::HashMap;
 
 use crate::machine::value_type::ValueType;
 
-use super::{env::Env, mterms::MValue, union_find::UnionFind, Ident, VClosure};
+use super::{env::Env, store::LogicStore, mterms::MValue, union_find::UnionFind, Ident, VClosure};
 
 #[derive(Clone)]
 pub struct LogicEnv {
     map : HashMap<Ident, (ValueType, Option<VClosure>)>,
     union_vars : UnionFind,
-    next : usize
+    next : usize,
+    occurs_check : bool,
 }
 
-impl LogicEnv {
+impl LogicStore for LogicEnv {
 
-    pub fn new() -> LogicEnv {
+    fn new() -> LogicEnv {
         LogicEnv {
             map : HashMap::new(),
             union_vars : UnionFind::new(),
-            next : 0 
+            next : 0,
+            occurs_check : true,
         }
     }
     
-    pub fn size(&self) -> usize { self.map.len() }
+    fn size(&self) -> usize { self.map.len() }
+
+    fn with_occurs_check(self, occurs_check : bool) -> LogicEnv { LogicEnv { occurs_check, ..self } }
+
+    fn occurs_check(&self) -> bool { self.occurs_check }
 
-    pub fn fresh(&mut self, ptype : ValueType) -> Ident {
+    fn fresh(&mut self, ptype : ValueType) -> Ident {
         let next = self.next;
         self.union_vars.register(self.next);
         self.map.insert(next, (ptype, None));
-        // println!("generated {} of type {}", next, ptype);
         self.next = next + 1;
         next
     }
     
-    pub fn lookup(&self, ident : Ident) -> Option<VClosure> {
+    fn lookup(&self, ident : Ident) -> Option<VClosure> {
         let root = self.union_vars.find(ident);
-        if let Some((_, Some(vclos))) = self.map.get(&root) { 
-            // println!("[DEBUG] looked up {} to be {}", ident, vclos.clone().val());
-            return Some(vclos.clone())
-        }
-        else { 
-            // println!("[DEBUG] LENV failed to find {}", ident);
-            return None
-         }
+        self.map.get(&root).and_then(|(_, vclos)| vclos.clone())
     }
     
-    pub fn set_vclos(&mut self, ident : Ident, vclos : VClosure) {
+    fn resolve(&mut self, ident : Ident) -> Option<VClosure> {
+        let root = self.union_vars.find_compress(ident);
+        self.map.get(&root).and_then(|(_, vclos)| vclos.clone())
+    }
+
+    fn root(&self, ident : Ident) -> Ident { self.union_vars.find(ident) }
+
+    fn set_vclos(&mut self, ident : Ident, vclos : VClosure) {
+        let ident = self.union_vars.find_compress(ident);
         let ptype = self.get_type(ident);
-        // println!("[DEBUG] setting {} to be {}", ident, vclos.val());
-        self.map.insert(ident, (ptype, Some(vclos.into())));
-        self.lookup(ident);
+        self.map.insert(ident, (ptype, Some(vclos)));
     }
     
-    pub fn get_type(&self, ident : Ident) -> ValueType {
-        if let Some((ptype, _)) = self.map.get(&ident) { 
-            return ptype.clone()
-        } 
-        else { unreachable!() }
+    fn get_type(&self, ident : Ident) -> ValueType {
+        match self.map.get(&ident) {
+            Some((ptype, _)) => ptype.clone(),
+            None => unreachable!(),
+        }
     }
     
-    pub fn identify(&mut self, ident1 : Ident, ident2 : Ident) {
+    fn identify(&mut self, ident1 : Ident, ident2 : Ident) {
         self.union_vars.union(ident1, ident2);
     }
+
+    // branches own their copies, so there is nothing to roll back
+    fn mark(&self) -> usize { 0 }
+
+    fn undo(&self, _ : usize) {}
+}
+
+impl LogicEnv {
+
+    // `ident` followed by the variables it was identified with on the way
+    // to its representative
+    pub fn path(&self, ident : Ident) -> Vec<Ident> { self.union_vars.path(ident) }
+
+    // The binding stored at `ident` itself, ignoring identifications
+    pub fn binding(&self, ident : Ident) -> Option<VClosure> {
+        self.map.get(&ident).and_then(|(_, vclos)| vclos.clone())
+    }
+
+    // Forgets every variable outside `live`, returning how many were dropped
+    pub fn retain(&mut self, live : &HashSet<Ident>) -> usize {
+        let before = self.map.len();
+        self.map.retain(|ident, _| live.contains(ident));
+        self.union_vars.retain(live);
+        before - self.map.len()
+    }
 }
\ No newline at end of file
@@ -3,64 +3,273 @@ mod vclosure;
 mod env;
 mod lvar;
 mod senv;
+mod store;
+mod trail;
 mod unify;
 mod step;
 mod union_find;
 mod value_type;
+pub use value_type::ValueType;
 pub mod translate;
-use std::{io::Write, rc::Rc};
+pub mod limits;
+pub use senv::Schedule;
+mod error;
+pub use error::{Failure, RuntimeError};
+mod term;
+pub use term::{Answer, Residual, Term, Typed};
+pub mod format;
+pub use format::Format;
+pub use error::OnError;
+mod search;
+mod parallel;
+mod dfs;
+mod bytecode;
+mod table;
+mod gc;
+pub mod pretty;
+mod trace;
+mod tree;
+mod stats;
+pub mod compiled;
+pub mod builtin;
+use std::{io::{self, Write}, path::{Path, PathBuf}, sync::Arc};
+use builtin::Goal;
 use env::Env;
 use im::vector::Vector;
-use lvar::LogicEnv;
+use limits::Limits;
+use store::{LogicStore, SuspStore};
+use search::{Branch, Search};
+use dfs::Dfs;
+use bytecode::BMachine;
+use gc::GcStats;
+use trail::{TrailLogicEnv, TrailSuspEnv};
+use parallel::ParSearch;
+use step::Machine;
 use mterms::{MComputation, MValue};
-use senv::SuspEnv;
-use step::{Stack, Machine};
 use vclosure::VClosure;
+use format::Summary;
+use trace::{JsonTracer, NoTrace, TextTracer, Tracer};
+use tree::SearchTree;
+use stats::Stats;
 use std::io::stdout;
 
 pub type Ident = usize;
 
 
-pub fn eval(comp : MComputation, env : Rc<Env>) {
+// How the search explores the branches of a program
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Engine {
+    // every branch in lockstep, which finds any answer eventually
+    #[default]
+    Breadth,
+    // one branch at a time over trailed stores, which is cheaper but can
+    // loop forever down an infinite branch
+    Depth,
+    // breadth-first over the program compiled to bytecode
+    Bytecode,
+}
+
+pub struct Config {
+    pub limits : Limits,
+    pub engine : Engine,
+    // worker threads; more than one selects the parallel search
+    pub threads : usize,
+    pub schedule : Schedule,
+    // sweep each machine for unreachable variables every so many steps
+    pub gc : Option<usize>,
+    pub on_error : OnError,
+    // off for rational-tree unification
+    pub occurs_check : bool,
+    // print the bindings of the query's existential variables
+    pub bindings : bool,
+    pub format : Format,
+    // print the events of the search to stderr, as text or JSON lines
+    pub trace : Option<Format>,
+    // write the search tree to this file as DOT, down to this many forks
+    pub search_tree : Option<(PathBuf, usize)>,
+    // count what the search does and report it with the summary
+    pub stats : bool,
+}
 
-    // println!("[DEBUG] main stmt: {}", comp.clone()) ;
-    let m = Machine { comp: comp.into() , env: env.clone(), stack: Stack::empty_stack(), lenv : LogicEnv::new().into(), senv : SuspEnv::new().into(), done: false };
-    let mut machines = vec![m];
+impl Default for Config {
+    fn default() -> Config { Config { limits : Limits::default(), engine : Engine::default(), threads : 1, schedule : Schedule::default(), gc : None, on_error : OnError::default(), occurs_check : true, bindings : false, format : Format::default(), trace : None, search_tree : None, stats : false } }
+}
+
+// Runs the query `comp`, whose answers have type `ptype` if it is known,
+// over the functions `env` named `names`
+pub fn eval(comp : MComputation, env : Arc<Env>, ptype : Option<ValueType>, names : &[String], config : Config) {
     let mut solns = 0;
-    while !machines.is_empty() {
-
-        let (done, ms) : (Vec<Machine>, Vec<Machine>) = machines.into_iter()
-            .flat_map(|m| m.step())
-            .partition(|m| m.done);
-
-        // println!("[DEBUG] machines: ");
-        // ms.iter().for_each(|m| {
-        //      println!("[DEBUG]   comp: {}", m.comp);
-        //      println!("[DEBUG]   stack size: {:?}", m.stack.len());
-        //      println!("[DEBUG]   env size: {:?}", m.env.size());
-        //      println!("[DEBUG]   lenv size: {:?}", m.lenv.size());
-        //      println!("[DEBUG]   senv size: {:?}", m.senv.size())
-        //  });
-
-        let mut dones = done.iter();
-        while let Some(m) = dones.next() {
-            match &*m.comp {
-                MComputation::Return(v) => {
-                    let out = output(v.clone(), m.env.clone(), &m.lenv, &m.senv);
-                    match out {
-                        Some(xs) => { println!("> {}", xs); solns += 1 }
-                        None => ()
-                    }
-                },
-                _ => unreachable!()
-            }
+    let mut failed = false;
+    // prints an answer, returning whether to carry on searching
+    let mut print = |out : Result<Answer, RuntimeError>| {
+        match out {
+            Ok(answer) => { println!("{}", config.format.answer(&answer)); solns += 1 }
+            Err(e) => { println!("{}", config.format.answer_error(&e)); failed = true }
         }
-        machines = ms;
-    }
+        !failed || config.on_error == OnError::Keep
+    };
+
+    // the tracer is boxed so that the searches are built once traced and
+    // once untraced
+    let tracer : Option<Box<dyn Tracer>> = match config.trace {
+        Some(Format::Json) => Some(Box::new(JsonTracer::new(io::stderr()))),
+        Some(_) => Some(Box::new(TextTracer::new(io::stderr()))),
+        None => None,
+    };
+
+    let stats = config.stats.then(|| Stats::new(&env, names));
+    let (steps, limit_hit, failures, aborted, gc_stats, stats) = if config.engine == Engine::Depth {
+        let m = Machine::<TrailLogicEnv, TrailSuspEnv>::new(comp, env).with_schedule(config.schedule).with_occurs_check(config.occurs_check);
+        let mut search = Dfs::new(m, config.limits).with_on_error(config.on_error);
+        if let Some(stats) = stats { search = search.with_stats(stats) }
+        match tracer {
+            Some(tracer) => depth_first(search.with_tracer(tracer), &mut print, ptype.as_ref(), config.bindings),
+            None => depth_first(search, &mut print, ptype.as_ref(), config.bindings),
+        }
+    } else if config.engine == Engine::Bytecode {
+        let m = BMachine::new(comp, &env).with_schedule(config.schedule).with_occurs_check(config.occurs_check);
+        let search = Search::new(m, config.limits).with_gc(config.gc).with_on_error(config.on_error);
+        traced(search, tracer, stats, &config, &mut print, ptype.as_ref())
+    } else if config.threads > 1 {
+        let m = Machine::new(comp, env).with_schedule(config.schedule).with_occurs_check(config.occurs_check);
+        let search = ParSearch::new(m, config.limits, config.threads).with_gc(config.gc).with_on_error(config.on_error);
+        search.run(|m| print(answer(&m, ptype.as_ref(), config.bindings)));
+        (search.steps(), search.limit_hit(), search.failures(), search.aborted(), search.gc_stats(), None)
+    } else {
+        let m = Machine::new(comp, env).with_schedule(config.schedule).with_occurs_check(config.occurs_check);
+        let search = Search::new(m, config.limits).with_gc(config.gc).with_on_error(config.on_error);
+        traced(search, tracer, stats, &config, &mut print, ptype.as_ref())
+    };
     
-    println!(">>> {} solutions", solns);
+    let summary = Summary {
+        solutions : solns, steps, limit_hit, failures : &failures,
+        aborted : aborted || (failed && config.on_error == OnError::Abort),
+        gc : config.gc.map(|_| gc_stats),
+        stats : stats.as_ref(),
+    };
+    println!("{}", config.format.summary(&summary));
+}
+
+// A query's answers, searched for as they are asked for
+pub struct Answers {
+    search : Searcher,
+    ptype : Option<ValueType>,
+    bindings : bool,
+}
+
+enum Searcher {
+    Breadth(Search),
+    Depth(Dfs<TrailLogicEnv, TrailSuspEnv>),
+    Bytecode(Search<NoTrace, BMachine>),
+}
+
+// The answers of the query `comp` one at a time, which the parallel search
+// can't give, so it is run on one thread
+pub fn answers(comp : MComputation, env : Arc<Env>, ptype : Option<ValueType>, config : &Config) -> Answers {
+    let search = match config.engine {
+        Engine::Depth => {
+            let m = Machine::new(comp, env).with_schedule(config.schedule).with_occurs_check(config.occurs_check);
+            Searcher::Depth(Dfs::new(m, config.limits).with_on_error(config.on_error))
+        },
+        Engine::Bytecode => {
+            let m = BMachine::new(comp, &env).with_schedule(config.schedule).with_occurs_check(config.occurs_check);
+            Searcher::Bytecode(Search::new(m, config.limits).with_gc(config.gc).with_on_error(config.on_error))
+        },
+        Engine::Breadth => {
+            let m = Machine::new(comp, env).with_schedule(config.schedule).with_occurs_check(config.occurs_check);
+            Searcher::Breadth(Search::new(m, config.limits).with_gc(config.gc).with_on_error(config.on_error))
+        },
+    };
+    Answers { search, ptype, bindings : config.bindings }
+}
+
+impl Answers {
+
+    pub fn steps(&self) -> usize {
+        match &self.search {
+            Searcher::Breadth(search) => search.steps(),
+            Searcher::Depth(search) => search.steps(),
+            Searcher::Bytecode(search) => search.steps(),
+        }
+    }
+
+    pub fn limit_hit(&self) -> Option<limits::LimitHit> {
+        match &self.search {
+            Searcher::Breadth(search) => search.limit_hit(),
+            Searcher::Depth(search) => search.limit_hit(),
+            Searcher::Bytecode(search) => search.limit_hit(),
+        }
+    }
+
+    // The branches that ended in runtime errors
+    pub fn failures(&self) -> &[error::Failure] {
+        match &self.search {
+            Searcher::Breadth(search) => search.failures(),
+            Searcher::Depth(search) => search.failures(),
+            Searcher::Bytecode(search) => search.failures(),
+        }
+    }
+}
+
+impl Iterator for Answers {
+    type Item = Result<Answer, RuntimeError>;
+
+    fn next(&mut self) -> Option<Result<Answer, RuntimeError>> {
+        let ptype = self.ptype.as_ref();
+        match &mut self.search {
+            Searcher::Breadth(search) => search.next().map(|m| m.answer(ptype, self.bindings)),
+            Searcher::Depth(search) => search.next().map(|m| answer(&m, ptype, self.bindings)),
+            Searcher::Bytecode(search) => search.next().map(|m| m.answer(ptype, self.bindings)),
+        }
+    }
+}
+
+// What a search reports once its answers are printed: the steps taken, the
+// limit hit, the failed branches, whether it aborted, the gc's work and the
+// statistics if collected
+type Outcome = (usize, Option<limits::LimitHit>, Vec<error::Failure>, bool, GcStats, Option<Stats>);
+
+// Runs a breadth-first search with the tracer, statistics and search tree
+// the configuration asks for
+fn traced<M : Branch>(mut search : Search<NoTrace, M>, tracer : Option<Box<dyn Tracer>>, stats : Option<Stats>, config : &Config, print : &mut impl FnMut(Result<Answer, RuntimeError>) -> bool, ptype : Option<&ValueType>) -> Outcome {
+    if let Some((_, max_depth)) = config.search_tree { search = search.with_search_tree(SearchTree::new(max_depth)) }
+    if let Some(stats) = stats { search = search.with_stats(stats) }
+    let tree_file = config.search_tree.as_ref().map(|(file, _)| file.as_path());
+    match tracer {
+        Some(tracer) => breadth_first(search.with_tracer(tracer), print, ptype, config.bindings, tree_file),
+        None => breadth_first(search, print, ptype, config.bindings, tree_file),
+    }
+}
+
+fn breadth_first<T : Tracer, M : Branch>(mut search : Search<T, M>, print : &mut impl FnMut(Result<Answer, RuntimeError>) -> bool, ptype : Option<&ValueType>, bindings : bool, tree_file : Option<&Path>) -> Outcome {
+    for m in search.by_ref() { if !print(m.answer(ptype, bindings)) { break } }
+    if let (Some(tree), Some(file)) = (search.search_tree(), tree_file) {
+        if let Err(e) = tree.save(file) { eprintln!("Error: Could not write file '{}': {}", file.display(), e) }
+    }
+    (search.steps(), search.limit_hit(), search.failures().to_vec(), search.aborted(), search.gc_stats(), search.take_stats())
+}
+
+fn depth_first<T : Tracer>(mut search : Dfs<TrailLogicEnv, TrailSuspEnv, T>, print : &mut impl FnMut(Result<Answer, RuntimeError>) -> bool, ptype : Option<&ValueType>, bindings : bool) -> Outcome {
+    for m in search.by_ref() { if !print(answer(&m, ptype, bindings)) { break } }
+    (search.steps(), search.limit_hit(), search.failures().to_vec(), search.aborted(), GcStats::default(), search.take_stats())
+}
+
+// The answer a finished machine prints, with the bindings of the query's
+// variables if `bindings` is set and the builtin calls still waiting
+fn answer<L : LogicStore, S : SuspStore>(m : &Machine<L, S>, ptype : Option<&ValueType>, bindings : bool) -> Result<Answer, RuntimeError> {
+    let bindings : Vec<_> = if bindings { m.bindings.iter().cloned().collect() } else { vec![] };
+    match &*m.comp {
+        MComputation::Return(v) => close(v.clone(), m.env.clone(), ptype, &bindings, &m.goals, &m.lenv, &m.senv),
+        _ => unreachable!()
+    }
+}
+
+fn close<L : LogicStore, S : SuspStore>(val : Arc<MValue>, env : Arc<Env>, ptype : Option<&ValueType>, bindings : &[(String, Ident)], goals : &Vector<Goal>, lenv : &L, senv : &S) -> Result<Answer, RuntimeError> {
+    term::close(&VClosure::Clos { val, env }, ptype, bindings, goals, lenv, senv)
 }
 
-fn output(val : Rc<MValue>, env : Rc<Env>, lenv : &LogicEnv, senv : &SuspEnv) -> Option<String> {
-    Some(VClosure::Clos { val, env }.close(lenv, senv)?.to_string())
+// The answer as printed in text
+#[cfg(test)]
+fn output<L : LogicStore, S : SuspStore>(val : Arc<MValue>, env : Arc<Env>, ptype : Option<&ValueType>, bindings : &[(String, Ident)], lenv : &L, senv : &S) -> Result<String, RuntimeError> {
+    Ok(close(val, env, ptype, bindings, &Vector::new(), lenv, senv)?.to_string())
 }
\ No newline at end of file
@@ -1,18 +1,19 @@
-use std::{fmt::Display, rc::Rc};
+use std::{fmt::Display, sync::Arc};
 
-use crate::machine::{value_type::ValueType, vclosure::VClosure};
+use crate::machine::{builtin::Builtin, pretty::Printer, value_type::ValueType, vclosure::VClosure};
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum MValue {
     Var(usize),
+    Unit,
     Zero,
-    Succ(Rc<MValue>),
-    Pair(Rc<MValue>, Rc<MValue>),
-    Inl(Rc<MValue>),
-    Inr(Rc<MValue>),
+    Succ(Arc<MValue>),
+    Pair(Arc<MValue>, Arc<MValue>),
+    Inl(Arc<MValue>),
+    Inr(Arc<MValue>),
     Nil,
-    Cons(Rc<MValue>, Rc<MValue>),
-    Thunk(Rc<MComputation>)
+    Cons(Arc<MValue>, Arc<MValue>),
+    Thunk(Arc<MComputation>)
 }
 
 impl MValue {
@@ -20,6 +21,7 @@ impl MValue {
         match self {
             MValue::Var(i) if *i < offset => MValue::Var(*i),
             MValue::Var(i) => MValue::Var(*i + 1),
+            MValue::Unit => MValue::Unit,
             MValue::Zero => MValue::Zero,
             MValue::Succ(rc) => MValue::Succ(rc.up(offset).into()),
             MValue::Pair(rc, rc1) => MValue::Pair(rc.up(offset).into(), rc1.up(offset).into()),
@@ -32,118 +34,79 @@ impl MValue {
     }
 }
     
-fn print_nat(n : &MValue) -> Option<String> {
-    fn print_nat_aux(n : &MValue, i : usize) -> Option<usize> {
-        match n {
-            MValue::Zero => Some(i),
-            MValue::Succ(v) => print_nat_aux(&v, i+1),
-            _ => None
-        }
-    }
-    Some(print_nat_aux(n, 0)?.to_string())
-}
-
-fn print_list(xs : &MValue) -> Option<String> {
-    fn print_list_aux(n : &MValue, outs : &mut Vec<String>) -> bool {
-        match n {
-            MValue :: Nil => true,
-            MValue::Cons(v, w) => {
-                let value = v.to_string();
-                outs.push(value);
-                print_list_aux(&w, outs)
-            },
-            _ => false
-        }
-    }
-    let mut outs = vec![];
-    let result = print_list_aux(xs, &mut outs);
-    if result {
-        let output = outs.join(", ");
-        Some("[".to_owned() + &output + "]")
-    } else { None }
-}
-
 impl Display for MValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            MValue::Var(i) => write!(f, "idx {}", i),
-            MValue::Zero => write!(f, "{}", print_nat(&MValue::Zero).expect("foo")),
-            MValue::Succ(v) => {
-                match print_nat(self) {
-                    Some(n) => write!(f, "{}", n),
-                    None => write!(f, "Succ({})", v),
-                }
-            },
-            MValue::Nil => {
-                match print_list(self) {
-                    Some(xs) => write!(f, "{}", xs),
-                    None => write!(f, "Nil")
-                }
-            }
-            MValue::Cons(v, w) => {
-                match print_list(self) {
-                    Some(xs) => write!(f, "{}", xs),
-                    None => write!(f, "Cons({}, {})", v, w)
-                }
-            },
-            MValue::Thunk(t) => write!(f, "Thunk({})", t),
-            MValue::Pair(v, w) => write!(f, "({}, {})", v, w),
-            MValue::Inl(v) => write!(f, "inl({})", v),
-            MValue::Inr(w) => write!(f, "inr({})", w)
-        }
+        write!(f, "{}", Printer::new(false).val(self, 0))
     }
 }
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum MComputation {
     // Value eliminators
     Ifz {
-        num : Rc<MValue>,
-        zk : Rc<MComputation>,
-        sk : Rc<MComputation>
+        num : Arc<MValue>,
+        zk : Arc<MComputation>,
+        sk : Arc<MComputation>
     },
     Match {
-        list : Rc<MValue>,
-        nilk : Rc<MComputation>,
-        consk : Rc<MComputation>
+        list : Arc<MValue>,
+        nilk : Arc<MComputation>,
+        consk : Arc<MComputation>
     },
     Case { 
-        sum : Rc<MValue>,
-        inlk : Rc<MComputation>,
-        inrk : Rc<MComputation>
+        sum : Arc<MValue>,
+        inlk : Arc<MComputation>,
+        inrk : Arc<MComputation>
     },
     // CBPV primitives
-    Return(Rc<MValue>),
+    Return(Arc<MValue>),
     Bind {
-        comp: Rc<MComputation>,
-        cont: Rc<MComputation>,
+        comp: Arc<MComputation>,
+        cont: Arc<MComputation>,
     },
-    Force(Rc<MValue>),
-    Lambda { body: Rc<MComputation> },
+    Force(Arc<MValue>),
+    Lambda { body: Arc<MComputation> },
     App {
-        op: Rc<MComputation>,
-        arg: Rc<MValue>
+        op: Arc<MComputation>,
+        arg: Arc<MValue>
     },
     // FLP
-    Choice(Vec<Rc<MComputation>>),
+    Choice(Vec<Arc<MComputation>>),
     Exists {
         ptype : ValueType,
-        body: Rc<MComputation>
+        // the source name of a variable of the query, whose binding can be
+        // shown with the answers
+        name : Option<String>,
+        body: Arc<MComputation>
     },
     Equate {
-        lhs: Rc<MValue>,
-        rhs: Rc<MValue>,
-        body: Rc<MComputation>
+        lhs: Arc<MValue>,
+        rhs: Arc<MValue>,
+        body: Arc<MComputation>
     },
     // Recursion
     Rec {
-        body : Rc<MComputation>
+        body : Arc<MComputation>
+    },
+    // Memoised call of a tabled function, whose arguments are the
+    // innermost `arity` variables of the environment
+    Table {
+        id : usize,
+        name : String,
+        arity : usize,
+        body : Arc<MComputation>
     },
+    // Call of a host function on the innermost `arity` variables of the
+    // environment
+    Builtin(Arc<Builtin>),
+    // The code at an address of a compiled program, standing in for the
+    // computation compiled there. Only the bytecode machine runs it.
+    Code(usize),
 }
 
 impl MComputation {
 
-    pub fn thunk(self : &Rc<MComputation>) -> Rc<MValue> {
+    pub fn thunk(self : &Arc<MComputation>) -> Arc<MValue> {
         MValue::Thunk(self.clone()).into()
     }
 
@@ -161,35 +124,20 @@ impl MComputation {
             MComputation::Lambda { body } => MComputation::Lambda { body: body.up(offset + 1).into() },
             MComputation::App { op, arg } => MComputation::App { op: op.up(offset).into(), arg: arg.up(offset).into() },
             MComputation::Choice(vec) => MComputation::Choice(vec.iter().map(|c| c.up(offset).into()).collect()),
-            MComputation::Exists { ptype, body } => MComputation::Exists { ptype: ptype.clone(), body: body.up(offset+1).into() },
+            MComputation::Exists { ptype, name, body } => MComputation::Exists { ptype: ptype.clone(), name: name.clone(), body: body.up(offset+1).into() },
             MComputation::Equate { lhs, rhs, body } => 
                 MComputation::Equate { lhs: lhs.up(offset).into(), rhs: rhs.up(offset).into(), body: body.up(offset).into() },
             MComputation::Rec { body } => MComputation::Rec { body: body.up(offset+1).into() },
+            MComputation::Table { id, name, arity, body } =>
+                MComputation::Table { id: *id, name: name.clone(), arity: *arity, body: body.up(offset).into() },
+            MComputation::Builtin(f) => MComputation::Builtin(f.clone()),
+            MComputation::Code(addr) => MComputation::Code(*addr),
         }
     }
 }
 
 impl Display for MComputation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            MComputation::Return(v) => write!(f, "return({})", v),
-            MComputation::Bind { comp, cont } => write!(f, "{} to {}", comp, cont),
-            MComputation::Force(v) => write!(f, "force({})", v),
-            MComputation::Lambda { body } => write!(f, "λ({})", body),
-            MComputation::App { op, arg } => write!(f, "{}({})", op, arg),
-            MComputation::Choice(vec) => {
-                vec.iter().map(|c| write!(f, "{} []", c)).last().expect("lol")
-            },
-            MComputation::Exists { ptype, body } => 
-                write!(f, "exists {}. {}", ptype, body),
-            MComputation::Equate { lhs, rhs, body } => 
-                write!(f, "{} =:= {}. {}", lhs, rhs, body),
-            MComputation::Ifz { num, zk, sk } => 
-                write!(f, "ifz({}, {}, {})", num, zk, sk),
-            MComputation::Rec { body } => write!(f, "rec({})", body),
-            MComputation::Match { list, nilk, consk } => 
-                write!(f, "match({}, {}, {})", list, nilk, consk),
-            _ => todo!()
-        }
+        write!(f, "{}", Printer::new(false).comp(self, 0))
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,271 @@
+use std::{collections::VecDeque, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, mpsc, Condvar, Mutex}, thread, time::Instant};
+
+use super::{error::{Failure, OnError, RuntimeError}, limits::{LimitHit, Limits}, mterms::MComputation, gc::GcStats, step::Machine, store::LogicStore, table::Tables, trace::NoTrace};
+
+// Multi-threaded search. Every worker owns a queue of machines which it
+// steps in FIFO order, so each worker explores its share breadth-first;
+// a worker whose queue runs dry steals the newest machines of the others.
+// A worker with nothing to steal sleeps until another queues machines.
+// Branches never share mutable state, so the answers are those of the
+// sequential search, though possibly in a different order.
+pub struct ParSearch {
+    queues : Vec<Mutex<VecDeque<Machine>>>,
+    // machines queued or being stepped, across all workers
+    pending : AtomicUsize,
+    steps : AtomicUsize,
+    stop : AtomicBool,
+    // idle workers wait on `wake`, and count themselves in `sleeping`
+    idle : Mutex<()>,
+    wake : Condvar,
+    sleeping : AtomicUsize,
+    stopped : Mutex<Option<LimitHit>>,
+    tables : Mutex<Tables>,
+    limits : Limits,
+    gc : Option<usize>,
+    gc_stats : Mutex<GcStats>,
+    on_error : OnError,
+    failures : Mutex<Vec<Failure>>,
+    start : Instant,
+}
+
+impl ParSearch {
+
+    pub fn new(m : Machine, limits : Limits, threads : usize) -> ParSearch {
+        let threads = threads.max(1);
+        let queues : Vec<_> = (0..threads).map(|_| Mutex::new(VecDeque::new())).collect();
+        queues[0].lock().unwrap().push_back(m);
+        ParSearch {
+            queues,
+            pending : AtomicUsize::new(1),
+            steps : AtomicUsize::new(0),
+            stop : AtomicBool::new(false),
+            idle : Mutex::new(()),
+            wake : Condvar::new(),
+            sleeping : AtomicUsize::new(0),
+            stopped : Mutex::new(None),
+            tables : Mutex::new(Tables::new()),
+            limits,
+            gc : None,
+            gc_stats : Mutex::new(GcStats::default()),
+            on_error : OnError::default(),
+            failures : Mutex::new(vec![]),
+            start : Instant::now(),
+        }
+    }
+
+    // Sweeps each machine every `interval` of its steps
+    pub fn with_gc(self, interval : Option<usize>) -> ParSearch { ParSearch { gc : interval, ..self } }
+
+    pub fn with_on_error(self, on_error : OnError) -> ParSearch { ParSearch { on_error, ..self } }
+
+    pub fn steps(&self) -> usize { self.steps.load(Ordering::Relaxed) }
+
+    // The branches that ended in runtime errors
+    pub fn failures(&self) -> Vec<Failure> { self.failures.lock().unwrap().clone() }
+
+    // Whether a runtime error stopped the search
+    pub fn aborted(&self) -> bool { self.on_error == OnError::Abort && !self.failures.lock().unwrap().is_empty() }
+
+    pub fn gc_stats(&self) -> GcStats { *self.gc_stats.lock().unwrap() }
+
+    pub fn limit_hit(&self) -> Option<LimitHit> { *self.stopped.lock().unwrap() }
+
+    // Runs the search to completion, passing each finished machine to
+    // `on_answer` on the calling thread as soon as a worker finds it, and
+    // stopping early if that returns false
+    pub fn run(&self, mut on_answer : impl FnMut(Machine) -> bool) {
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|scope| {
+            for id in 0..self.queues.len() {
+                let tx = tx.clone();
+                scope.spawn(move || self.work(id, tx));
+            }
+            drop(tx);
+            for m in rx.iter() {
+                if !on_answer(m) {
+                    self.halt(None);
+                    break
+                }
+            }
+        });
+    }
+
+    // Stops every worker, recording the limit that stopped them if any
+    fn halt(&self, hit : Option<LimitHit>) {
+        if let Some(hit) = hit { self.stopped.lock().unwrap().get_or_insert(hit); }
+        self.stop.store(true, Ordering::SeqCst);
+        self.wake_all();
+    }
+
+    fn wake_all(&self) {
+        // a read-modify-write rather than a load, so that either it counts
+        // a worker registering in `wait` or that worker sees what changed
+        // before the call
+        if self.sleeping.fetch_add(0, Ordering::SeqCst) > 0 {
+            let _idle = self.idle.lock().unwrap();
+            self.wake.notify_all();
+        }
+    }
+
+    // Waits for a machine to step, or returns `None` once there is no work
+    // left or the search has stopped. The worker counts itself as sleeping
+    // before checking the queues, holding the lock it sleeps on, so that a
+    // wakeup can't fall between the check and the sleep.
+    fn wait(&self, id : usize) -> Option<Machine> {
+        let mut idle = self.idle.lock().unwrap();
+        self.sleeping.fetch_add(1, Ordering::SeqCst);
+        let m = loop {
+            if self.stop.load(Ordering::SeqCst) || self.pending.load(Ordering::SeqCst) == 0 { break None }
+            if let Some(m) = self.take(id) { break Some(m) }
+            idle = self.wake.wait(idle).unwrap();
+        };
+        self.sleeping.fetch_sub(1, Ordering::SeqCst);
+        m
+    }
+
+    fn take(&self, id : usize) -> Option<Machine> {
+        if let Some(m) = self.queues[id].lock().unwrap().pop_front() { return Some(m) }
+        let n = self.queues.len();
+        (1..n).find_map(|i| self.queues[(id + i) % n].lock().unwrap().pop_back())
+    }
+
+    // Records a branch that failed, giving the machines it steps to
+    fn fail(&self, steps : usize, error : RuntimeError) -> Vec<Machine> {
+        self.failures.lock().unwrap().push(Failure { steps, error });
+        if self.on_error == OnError::Abort { self.halt(None) }
+        vec![]
+    }
+
+    // Counts the step about to be taken on `m`, unless it would reach a limit
+    fn check_limits(&self, m : &Machine) -> Option<LimitHit> {
+        let steps = self.steps.fetch_add(1, Ordering::SeqCst);
+        let hit = self.limits.check(steps, self.pending.load(Ordering::SeqCst), m.lenv.size(), self.start);
+        if hit.is_some() { self.steps.fetch_sub(1, Ordering::SeqCst); }
+        hit
+    }
+
+    fn work(&self, id : usize, answers : mpsc::Sender<Machine>) {
+        while !self.stop.load(Ordering::SeqCst) {
+            let mut m = match self.take(id).or_else(|| self.wait(id)) {
+                Some(m) => m,
+                None => return,
+            };
+
+            if let Some(hit) = self.check_limits(&m) { return self.halt(Some(hit)) }
+
+            if let Some(n) = self.gc {
+                if m.steps > 0 && m.steps % n == 0 {
+                    let stats = m.collect();
+                    *self.gc_stats.lock().unwrap() += stats;
+                }
+            }
+
+            let steps = m.steps + 1;
+            let ms : Vec<Machine> = match &*m.comp {
+                MComputation::Table { .. } => self.tables.lock().unwrap().call(m),
+                _ => m.step(&mut NoTrace).unwrap_or_else(|error| self.fail(steps, error)),
+            };
+            let ms : Vec<Machine> = ms.into_iter().flat_map(|m| {
+                if !(m.done && m.producer.is_some()) { return vec![m] }
+                let steps = m.steps;
+                self.tables.lock().unwrap().answer(m).unwrap_or_else(|error| self.fail(steps, error))
+            }).collect();
+            // count the children before retiring their parent, so that
+            // `pending` never drops to zero while work remains
+            self.pending.fetch_add(ms.len(), Ordering::SeqCst);
+            let mut queue = self.queues[id].lock().unwrap();
+            let mut queued = false;
+            for m in ms {
+                if m.done {
+                    self.pending.fetch_sub(1, Ordering::SeqCst);
+                    let _ = answers.send(m);
+                } else {
+                    queue.push_back(m);
+                    queued = true
+                }
+            }
+            drop(queue);
+            // wake the idle workers to steal the new machines, or to finish
+            // once the last is done
+            if self.pending.fetch_sub(1, Ordering::SeqCst) == 1 || queued { self.wake_all() }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{machine::{output, search::Search, translate::translate, MComputation}, parser};
+    use super::*;
+
+    fn answer(m : Machine) -> String {
+        match &*m.comp {
+            MComputation::Return(v) => output(v.clone(), m.env.clone(), None, &[], &m.lenv, &m.senv).unwrap(),
+            _ => unreachable!()
+        }
+    }
+
+    fn sorted_answers(src : &str, threads : usize) -> Vec<String> {
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        let mut answers = vec![];
+        if threads > 1 {
+            ParSearch::new(Machine::new(comp, env), Limits::default(), threads).run(|m| { answers.push(answer(m)); true });
+        } else {
+            answers = Search::new(Machine::new(comp, env), Limits::default()).map(answer).collect();
+        }
+        answers.sort();
+        answers
+    }
+
+    #[test]
+    fn agrees_with_sequential() {
+        let src = "insert :: [Nat] -> [Nat]
+insert x xs = case xs of
+      [] -> [x]
+    | (z:zs) -> ((x : z : zs) <> (z : insert x zs)).
+
+perm :: [Nat] -> [Nat]
+perm xs = case xs of
+      [] -> []
+    | (z:zs) -> insert z (perm zs).
+
+add :: Nat -> Nat -> Nat
+add n m = case m of
+      Z -> n
+    | S z -> S (add n z).
+
+exists x :: Nat. exists y :: Nat. add x y =:= 4. ((perm [x, y, 1]), y).";
+
+        let expected = sorted_answers(src, 1);
+        assert_eq!(expected.len(), 30);
+        for threads in [2, 4] {
+            assert_eq!(sorted_answers(src, threads), expected);
+        }
+    }
+
+    #[test]
+    fn step_limit() {
+        let src = "poke :: Nat -> Nat
+poke n = case n of
+      Z -> 0
+    | S z -> S (poke z).
+
+exists x :: Nat. poke x.";
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        let limits = Limits { max_steps : Some(100), ..Limits::default() };
+        let search = ParSearch::new(Machine::new(comp, env), limits, 4);
+        search.run(|_| true);
+        assert_eq!(search.steps(), 100);
+        assert_eq!(search.limit_hit(), Some(LimitHit::Steps(100)));
+    }
+
+    #[test]
+    fn finishes_without_timeouts() {
+        // idle workers sleep until woken, so a missed wakeup would hang
+        let src = "exists x :: Nat. exists y :: Nat. (x <> S x) =:= (S y <> y). (x, y).";
+        let expected = sorted_answers(src, 1);
+        assert_eq!(expected.len(), 4);
+        for _ in 0..200 {
+            assert_eq!(sorted_answers(src, 4), expected);
+        }
+    }
+}
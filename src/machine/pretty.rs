@@ -0,0 +1,299 @@
+use super::{env::Env, mterms::{MComputation, MValue}, VClosure};
+
+// Lines longer than this are broken at the binding forms
+const WIDTH : usize = 80;
+
+// Precedences of computations: binding forms extend as far right as they
+// can, choices bind looser than applications
+const LOW : usize = 0;
+const CHOICE : usize = 1;
+const APP : usize = 2;
+
+// Precedences of values
+const CONS : usize = 0;
+const PREFIX : usize = 1;
+const ATOM : usize = 2;
+
+// Prints the intermediate representation. Variables are de Bruijn indices
+// `#i` and binders `_`, or with `names` set, binders are named after the
+// source where the IR records it (query variables, tabled functions and
+// the hints given to `Printer::hint`) and `x1`, `x2`, ... otherwise.
+pub struct Printer {
+    names : bool,
+    // the names of the variables in scope, innermost last
+    scope : Vec<String>,
+    fresh : usize,
+    // a name for the next binder
+    hint : Option<String>,
+}
+
+fn indent(s : &str, n : usize) -> String {
+    s.replace('\n', &format!("\n{}", " ".repeat(n)))
+}
+
+fn parens(s : String, cond : bool) -> String {
+    if !cond { s }
+    else if s.contains('\n') { format!("(\n  {}\n)", indent(&s, 2)) }
+    else { format!("({})", s) }
+}
+
+impl Printer {
+
+    pub fn new(names : bool) -> Printer { Printer { names, scope : vec![], fresh : 0, hint : None } }
+
+    // Brings variables into scope, outermost first
+    pub fn with_scope(self, scope : Vec<String>) -> Printer { Printer { scope, ..self } }
+
+    pub fn hint(&mut self, name : &str) { self.hint = Some(name.to_string()) }
+
+    fn var(&self, i : usize) -> String {
+        match self.scope.len().checked_sub(i + 1) {
+            Some(j) if self.names => self.scope[j].clone(),
+            _ => format!("#{}", i),
+        }
+    }
+
+    // Prints `body` under a new binder, returning the binder and the body
+    fn bind<T>(&mut self, name : Option<&String>, body : impl FnOnce(&mut Printer) -> T) -> (String, T) {
+        let name = match (self.hint.take(), name) {
+            _ if !self.names => "_".to_string(),
+            (Some(name), _) => name,
+            (None, Some(name)) => name.clone(),
+            (None, None) => { self.fresh += 1; format!("x{}", self.fresh) },
+        };
+        self.scope.push(name.clone());
+        let result = body(self);
+        self.scope.pop();
+        (name, result)
+    }
+
+    pub fn val(&mut self, val : &MValue, prec : usize) -> String {
+        if let Some(n) = nat(val) { return n.to_string() }
+        match val {
+            MValue::Var(i) => self.var(*i),
+            MValue::Unit => "()".to_string(),
+            MValue::Zero => "0".to_string(),
+            MValue::Succ(v) => parens(format!("S {}", self.val(v, ATOM)), prec > PREFIX),
+            MValue::Nil | MValue::Cons(_, _) => {
+                let mut xs = vec![];
+                let mut rest = val;
+                while let MValue::Cons(x, xs1) = rest {
+                    xs.push(x);
+                    rest = xs1
+                }
+                match rest {
+                    MValue::Nil => {
+                        let xs : Vec<String> = xs.into_iter().map(|x| self.val(x, CONS)).collect();
+                        format!("[{}]", xs.join(", "))
+                    },
+                    _ => {
+                        let mut xs : Vec<String> = xs.into_iter().map(|x| self.val(x, PREFIX)).collect();
+                        xs.push(self.val(rest, PREFIX));
+                        parens(xs.join(" : "), prec > CONS)
+                    },
+                }
+            },
+            MValue::Pair(v, w) => format!("({}, {})", self.val(v, CONS), self.val(w, CONS)),
+            MValue::Inl(v) => parens(format!("inl {}", self.val(v, ATOM)), prec > PREFIX),
+            MValue::Inr(v) => parens(format!("inr {}", self.val(v, ATOM)), prec > PREFIX),
+            MValue::Thunk(c) => {
+                let c = self.comp(c, LOW);
+                if c.contains('\n') { format!("thunk(\n  {}\n)", indent(&c, 2)) } else { format!("thunk({})", c) }
+            },
+        }
+    }
+
+    pub fn comp(&mut self, comp : &MComputation, prec : usize) -> String {
+        let (fresh, hint) = (self.fresh, self.hint.clone());
+        let flat = self.layout(comp, prec, true);
+        if flat.len() <= WIDTH { return flat }
+        (self.fresh, self.hint) = (fresh, hint);
+        self.layout(comp, prec, false)
+    }
+
+    // Prints a computation on one line if `flat`, otherwise breaking lines
+    // at its binding forms
+    fn layout(&mut self, comp : &MComputation, prec : usize, flat : bool) -> String {
+        let sub = |p : &mut Printer, c : &MComputation, prec : usize| if flat { p.layout(c, prec, true) } else { p.comp(c, prec) };
+        let sep = if flat { " " } else { "\n" };
+        let block = |s : String| if flat { format!(" {}", s) } else { format!("\n  {}", indent(&s, 2)) };
+        // once broken, a sequence is broken at every step
+        let rest = |p : &mut Printer, c : &MComputation| match c {
+            MComputation::Bind { .. } | MComputation::Exists { .. } | MComputation::Equate { .. } if !flat => p.layout(c, LOW, false),
+            _ => sub(p, c, LOW),
+        };
+        match comp {
+            MComputation::Return(v) => parens(format!("return {}", self.val(v, ATOM)), prec > APP),
+            MComputation::Force(v) => parens(format!("force {}", self.val(v, ATOM)), prec > APP),
+            MComputation::App { op, arg } => {
+                let op = sub(self, op, APP);
+                parens(format!("{} {}", op, self.val(arg, ATOM)), prec > APP)
+            },
+            MComputation::Bind { comp, cont } => {
+                let comp = sub(self, comp, CHOICE);
+                let (x, cont) = self.bind(None, |p| rest(p, cont));
+                parens(format!("{} to {}.{}{}", comp, x, sep, cont), prec > LOW)
+            },
+            MComputation::Lambda { body } => {
+                let (x, body) = self.bind(None, |p| sub(p, body, LOW));
+                parens(format!("λ{}.{}", x, block(body)), prec > LOW)
+            },
+            MComputation::Choice(cs) => {
+                let cs : Vec<String> = cs.iter().map(|c| indent(&sub(self, c, APP), 3)).collect();
+                let s = if flat { cs.join(" <> ") } else { format!("   {}", cs.join("\n<> ")) };
+                parens(s, prec > CHOICE)
+            },
+            MComputation::Exists { ptype, name, body } => {
+                let (x, body) = self.bind(name.as_ref(), |p| rest(p, body));
+                parens(format!("exists {} : {}.{}{}", x, ptype, sep, body), prec > LOW)
+            },
+            MComputation::Equate { lhs, rhs, body } => {
+                let (lhs, rhs) = (self.val(lhs, CONS), self.val(rhs, CONS));
+                let body = rest(self, body);
+                parens(format!("{} =:= {}.{}{}", lhs, rhs, sep, body), prec > LOW)
+            },
+            MComputation::Rec { body } => {
+                let (f, body) = self.bind(None, |p| sub(p, body, LOW));
+                parens(format!("rec {}.{}", f, block(body)), prec > LOW)
+            },
+            MComputation::Table { name, arity, body, .. } => {
+                let body = sub(self, body, LOW);
+                parens(format!("table {}/{}{}", name, arity, block(body)), prec > LOW)
+            },
+            MComputation::Builtin(f) => format!("builtin {}/{}", f.name, f.arity()),
+            MComputation::Code(addr) => format!("code @{}", addr),
+            MComputation::Ifz { num, zk, sk } => {
+                let num = self.val(num, CONS);
+                let zk = sub(self, zk, LOW);
+                let (x, sk) = self.bind(None, |p| sub(p, sk, LOW));
+                self.cases(num, vec![("Z".to_string(), zk), (format!("S {}", x), sk)], prec, flat)
+            },
+            MComputation::Match { list, nilk, consk } => {
+                let list = self.val(list, CONS);
+                let nilk = sub(self, nilk, LOW);
+                let (xs, (x, consk)) = self.bind(None, |p| p.bind(None, |p| sub(p, consk, LOW)));
+                self.cases(list, vec![("[]".to_string(), nilk), (format!("{} : {}", x, xs), consk)], prec, flat)
+            },
+            MComputation::Case { sum, inlk, inrk } => {
+                let sum = self.val(sum, CONS);
+                let (x, inlk) = self.bind(None, |p| sub(p, inlk, LOW));
+                let (y, inrk) = self.bind(None, |p| sub(p, inrk, LOW));
+                self.cases(sum, vec![(format!("inl {}", x), inlk), (format!("inr {}", y), inrk)], prec, flat)
+            },
+        }
+    }
+
+    fn cases(&self, scrutinee : String, arms : Vec<(String, String)>, prec : usize, flat : bool) -> String {
+        let s = if flat {
+            let arms : Vec<String> = arms.into_iter().map(|(pat, body)| format!("{} -> {}", pat, body)).collect();
+            format!("case {} of {}", scrutinee, arms.join(" | "))
+        } else {
+            let arms : String = arms.into_iter()
+                .map(|(pat, body)| if body.contains('\n') {
+                    format!("\n  {} ->\n    {}", pat, indent(&body, 4))
+                } else {
+                    format!("\n  {} -> {}", pat, body)
+                })
+                .collect();
+            format!("case {} of{}", scrutinee, arms)
+        };
+        parens(s, prec > LOW)
+    }
+}
+
+fn nat(val : &MValue) -> Option<usize> {
+    match val {
+        MValue::Zero => Some(0),
+        MValue::Succ(v) => Some(nat(v)? + 1),
+        _ => None
+    }
+}
+
+// The definitions of the global environment, oldest first, and the query.
+// With `names`, the globals are named after the functions they define.
+pub fn dump(comp : &MComputation, env : &Env, names : Option<&[String]>) -> String {
+    let mut p = Printer::new(names.is_some()).with_scope(globals(env, names));
+    definitions(env, names) + &p.comp(comp, LOW) + "\n"
+}
+
+fn globals(env : &Env, names : Option<&[String]>) -> Vec<String> {
+    match names {
+        Some(names) => names.to_vec(),
+        None => (0..env.iter().count()).rev().map(|i| format!("#{}", i)).collect(),
+    }
+}
+
+// The definitions alone, each followed by a blank line
+pub fn definitions(env : &Env, names : Option<&[String]>) -> String {
+    let globals = globals(env, names);
+    let mut out = String::new();
+    for (i, vclos) in env.iter().collect::<Vec<_>>().into_iter().rev().enumerate() {
+        let mut p = Printer::new(names.is_some()).with_scope(globals[..i].to_vec());
+        p.hint(&globals[i]);
+        let val = match vclos {
+            VClosure::Clos { val, .. } => match &**val {
+                // the function's own binder is its name
+                MValue::Thunk(c) => {
+                    let c = p.comp(c, LOW);
+                    if c.contains('\n') { format!("thunk(\n  {}\n)", indent(&c, 2)) } else { format!("thunk({})", c) }
+                },
+                val => { p.hint = None; p.val(val, CONS) },
+            },
+            VClosure::LogicVar { ident } => format!("?{}", ident),
+            VClosure::Susp { ident } => format!("susp {}", ident),
+        };
+        out += &format!("{} = {}\n\n", globals[i], val);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{machine::translate::{globals, translate}, parser};
+    use super::*;
+
+    #[test]
+    fn values() {
+        let mut p = Printer::new(false);
+        let succ = MValue::Succ(MValue::Var(0).into());
+        assert_eq!(p.val(&MValue::Succ(MValue::Succ(MValue::Zero.into()).into()), CONS), "2");
+        assert_eq!(p.val(&MValue::Cons(succ.clone().into(), MValue::Var(1).into()), CONS), "S #0 : #1");
+        assert_eq!(p.val(&MValue::Inl(succ.clone().into()), CONS), "inl (S #0)");
+        assert_eq!(p.val(&MValue::Cons(MValue::Zero.into(), MValue::Nil.into()), ATOM), "[0]");
+    }
+
+    #[test]
+    fn computations() {
+        let src = "double :: Nat -> Nat
+double n = case n of
+      Z -> 0
+    | S m -> S (S (double m)).
+
+exists x :: Nat. double x =:= 4. x <> 1.";
+        let ast = parser::parse(src).unwrap();
+        let names = globals(&ast);
+        let (comp, env) = translate(ast).unwrap();
+        assert_eq!(Printer::new(false).comp(&comp, LOW), "exists _ : Nat.
+(return #1 to _. return #1 to _. force #1 #0) to _.
+return 4 to _.
+#0 =:= #1.
+return #2 <> return 1");
+        assert_eq!(dump(&comp, &env, Some(&names)), "double = thunk(
+  rec double.
+    λx1.
+      return x1 to x2.
+      case x2 of
+        Z -> return 0
+        S x3 ->
+          ((return double to x4. return x3 to x5. force x4 x5) to x6. return (S x6)) to x7.
+          return (S x7)
+)
+
+exists x : Nat.
+(return double to x1. return x to x2. force x1 x2) to x3.
+return 4 to x4.
+x4 =:= x3.
+return x <> return 1
+");
+    }
+}
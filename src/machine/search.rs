@@ -0,0 +1,395 @@
+use std::{collections::VecDeque, sync::Arc, time::Instant};
+
+use im::Vector;
+
+use super::{builtin::Goal, close, env::Env, error::{Failure, OnError, RuntimeError}, limits::{LimitHit, Limits}, lvar::LogicEnv, mterms::{MComputation, MValue}, gc::GcStats, senv::{SuspAt, SuspEnv}, step::{eval_susp_then, Machine, Stack}, store::{LogicStore, SuspStore}, table::{Producer, Tables}, stats::Stats, term::Answer, trace::{Event, NoTrace, Tracer}, tree::SearchTree, value_type::ValueType, Ident, VClosure};
+
+// A machine the breadth-first search can step: the tree-walking `Machine`
+// or the bytecode `BMachine`. Both keep their stores persistent, so that
+// each branch of a step is built from a clone.
+pub trait Branch : Clone {
+    // The computation about to run
+    fn comp(&self) -> &MComputation;
+
+    // Every machine the machine steps to
+    fn step(self, tracer : &mut impl Tracer) -> Result<Vec<Self>, RuntimeError>;
+
+    fn done(&self) -> bool;
+
+    // Steps taken since the initial machine
+    fn steps(&self) -> usize;
+
+    // The node of the search tree the machine is in
+    fn node(&self) -> usize;
+
+    fn set_node(&mut self, node : usize);
+
+    fn env(&self) -> &Arc<Env>;
+
+    fn lenv(&self) -> &LogicEnv;
+
+    fn senv(&self) -> &SuspEnv;
+
+    // The named variables of the query created so far, oldest first
+    fn bindings(&self) -> &Vector<(String, Ident)>;
+
+    // The builtin calls waiting for their arguments
+    fn goals(&self) -> &Vector<Goal>;
+
+    // The table the machine produces answers for, if any
+    fn producer(&self) -> Option<&Producer>;
+
+    // Drops what the machine can no longer reach, see `gc`
+    fn collect(&mut self) -> GcStats;
+
+    // The value a finished machine returned
+    fn result(&self) -> VClosure;
+
+    // The machine at a tabled call running the body directly
+    fn enter(self) -> Self;
+
+    // The machine forcing the suspension it is blocked on
+    fn force(self, a : SuspAt) -> Self;
+
+    // A machine running the body of the tabled call the machine is at,
+    // alone in `env` and `lenv`, for the table `producer`
+    fn spawn(&self, env : Arc<Env>, lenv : LogicEnv, producer : Producer) -> Self;
+
+    // The machine returning `result` from the tabled call it is at
+    fn resume(&self, result : Arc<MValue>, env : Arc<Env>, lenv : LogicEnv) -> Self;
+
+    // The answer a finished machine prints, with the bindings of the
+    // query's variables if `bindings` is set and the builtin calls still
+    // waiting
+    fn answer(&self, ptype : Option<&ValueType>, bindings : bool) -> Result<Answer, RuntimeError> {
+        let bindings : Vec<_> = if bindings { self.bindings().iter().cloned().collect() } else { vec![] };
+        match self.result() {
+            VClosure::Clos { val, env } => close(val, env, ptype, &bindings, self.goals(), self.lenv(), self.senv()),
+            _ => unreachable!("machines return values"),
+        }
+    }
+}
+
+impl Branch for Machine {
+    fn comp(&self) -> &MComputation { &self.comp }
+
+    fn step(self, tracer : &mut impl Tracer) -> Result<Vec<Machine>, RuntimeError> { Machine::step(self, tracer) }
+
+    fn done(&self) -> bool { self.done }
+
+    fn steps(&self) -> usize { self.steps }
+
+    fn node(&self) -> usize { self.node }
+
+    fn set_node(&mut self, node : usize) { self.node = node }
+
+    fn env(&self) -> &Arc<Env> { &self.env }
+
+    fn lenv(&self) -> &LogicEnv { &self.lenv }
+
+    fn senv(&self) -> &SuspEnv { &self.senv }
+
+    fn bindings(&self) -> &Vector<(String, Ident)> { &self.bindings }
+
+    fn goals(&self) -> &Vector<Goal> { &self.goals }
+
+    fn producer(&self) -> Option<&Producer> { self.producer.as_ref() }
+
+    fn collect(&mut self) -> GcStats { Machine::collect(self) }
+
+    fn result(&self) -> VClosure {
+        match &*self.comp {
+            MComputation::Return(v) => VClosure::mk_clos(v, &self.env),
+            _ => unreachable!("machine hasn't finished"),
+        }
+    }
+
+    fn enter(self) -> Machine {
+        match &*self.comp {
+            MComputation::Table { body, .. } => Machine { comp : body.clone(), ..self },
+            _ => unreachable!("not a tabled call"),
+        }
+    }
+
+    fn force(self, a : SuspAt) -> Machine { eval_susp_then(self.scheduled(a), self) }
+
+    fn spawn(&self, env : Arc<Env>, lenv : LogicEnv, producer : Producer) -> Machine {
+        let body = match &*self.comp {
+            MComputation::Table { body, .. } => body.clone(),
+            _ => unreachable!("not a tabled call"),
+        };
+        Machine {
+            comp : body, stack : Stack::empty_stack(), env, lenv,
+            senv : SuspEnv::new().with_schedule(self.senv.schedule()),
+            done : false, producer : Some(producer), steps : self.steps, bindings : Vector::new(), goals : Vector::new(), node : self.node
+        }
+    }
+
+    fn resume(&self, result : Arc<MValue>, env : Arc<Env>, lenv : LogicEnv) -> Machine {
+        Machine { comp : MComputation::Return(result).into(), env, lenv, ..self.clone() }
+    }
+}
+
+// Breadth-first search over the machines produced by `Branch::step`,
+// yielding finished machines as they are found
+pub struct Search<T = NoTrace, M = Machine> {
+    frontier : VecDeque<M>,
+    next_frontier : VecDeque<M>,
+    answers : VecDeque<M>,
+    tables : Tables<M>,
+    limits : Limits,
+    gc : Option<usize>,
+    gc_stats : GcStats,
+    start : Instant,
+    steps : usize,
+    stopped : Option<LimitHit>,
+    on_error : OnError,
+    failures : Vec<Failure>,
+    tracer : T,
+    tree : Option<SearchTree>,
+    stats : Option<Stats>,
+}
+
+impl<M : Branch> Search<NoTrace, M> {
+
+    pub fn new(m : M, limits : Limits) -> Search<NoTrace, M> {
+        Search {
+            frontier : VecDeque::from([m]),
+            next_frontier : VecDeque::new(),
+            answers : VecDeque::new(),
+            tables : Tables::new(),
+            limits,
+            gc : None,
+            gc_stats : GcStats::default(),
+            start : Instant::now(),
+            steps : 0,
+            stopped : None,
+            on_error : OnError::default(),
+            failures : vec![],
+            tracer : NoTrace,
+            tree : None,
+            stats : None,
+        }
+    }
+}
+
+impl<T, M> Search<T, M> {
+
+    // Sweeps each machine every `interval` of its steps
+    pub fn with_gc(self, interval : Option<usize>) -> Search<T, M> { Search { gc : interval, ..self } }
+
+    pub fn with_on_error(self, on_error : OnError) -> Search<T, M> { Search { on_error, ..self } }
+
+    pub fn with_tracer<U : Tracer>(self, tracer : U) -> Search<U, M> {
+        let Search { frontier, next_frontier, answers, tables, limits, gc, gc_stats, start, steps, stopped, on_error, failures, tree, stats, .. } = self;
+        Search { frontier, next_frontier, answers, tables, limits, gc, gc_stats, start, steps, stopped, on_error, failures, tracer, tree, stats }
+    }
+
+    // Records the forks of the search and how its branches end
+    pub fn with_search_tree(self, tree : SearchTree) -> Search<T, M> { Search { tree : Some(tree), ..self } }
+
+    pub fn search_tree(&self) -> Option<&SearchTree> { self.tree.as_ref() }
+
+    // Counts what the search does
+    pub fn with_stats(self, stats : Stats) -> Search<T, M> { Search { stats : Some(stats), ..self } }
+
+    pub fn take_stats(&mut self) -> Option<Stats> { self.stats.take() }
+
+    pub fn steps(&self) -> usize { self.steps }
+
+    // The branches that ended in runtime errors
+    pub fn failures(&self) -> &[Failure] { &self.failures }
+
+    // Whether a runtime error stopped the search
+    pub fn aborted(&self) -> bool { self.on_error == OnError::Abort && !self.failures.is_empty() }
+
+    pub fn gc_stats(&self) -> GcStats { self.gc_stats }
+
+    // The limit that cut the search short, if any
+    pub fn limit_hit(&self) -> Option<LimitHit> { self.stopped }
+}
+
+impl<T : Tracer, M : Branch> Iterator for Search<T, M> {
+    type Item = M;
+
+    fn next(&mut self) -> Option<M> {
+        while self.stopped.is_none() && !self.aborted() {
+            if let Some(m) = self.answers.pop_front() { return Some(m) }
+
+            let mut m = match self.frontier.pop_front() {
+                Some(m) => m,
+                None if self.next_frontier.is_empty() => return None,
+                None => {
+                    std::mem::swap(&mut self.frontier, &mut self.next_frontier);
+                    continue
+                }
+            };
+
+            let frontier = self.frontier.len() + self.next_frontier.len() + 1;
+            if let Some(hit) = self.limits.check(self.steps, frontier, m.lenv().size(), self.start) {
+                self.stopped = Some(hit);
+                break
+            }
+
+            if let Some(n) = self.gc {
+                if m.steps() > 0 && m.steps() % n == 0 { self.gc_stats += m.collect() }
+            }
+
+            self.steps += 1;
+            let steps = m.steps() + 1;
+            self.tracer.event(m.steps(), Event::Step { comp : m.comp() });
+            if let Some(stats) = &mut self.stats {
+                stats.frontier(self.frontier.len() + self.next_frontier.len() + 1);
+                stats.event(m.steps(), Event::Step { comp : m.comp() })
+            }
+            let node = m.node();
+            let mut ms = match m.comp() {
+                MComputation::Table { .. } => self.tables.call(m),
+                _ => {
+                    let result = match (&mut self.tree, &mut self.stats) {
+                        (None, None) => m.step(&mut self.tracer),
+                        (tree, stats) => m.step(&mut (&mut self.tracer, (tree, stats))),
+                    };
+                    match result {
+                        Ok(ms) => ms,
+                        Err(error) => {
+                            if let Some(tree) = &mut self.tree { tree.error(node, steps, &error) }
+                            self.failures.push(Failure { steps, error });
+                            continue
+                        }
+                    }
+                },
+            };
+            if let Some(tree) = &mut self.tree { tree.step(node, steps, &mut ms) }
+            for m in ms {
+                if m.done() && m.producer().is_some() {
+                    let (node, steps) = (m.node(), m.steps());
+                    match self.tables.answer(m) {
+                        Ok(ms) => self.next_frontier.extend(ms),
+                        Err(error) => {
+                            if let Some(tree) = &mut self.tree { tree.error(node, steps, &error) }
+                            self.failures.push(Failure { steps, error })
+                        },
+                    }
+                } else if m.done() {
+                    if self.tracer.enabled() || self.tree.is_some() {
+                        if let Ok(answer) = m.answer(None, false) {
+                            self.tracer.event(m.steps(), Event::Answer { answer : &answer });
+                            if let Some(tree) = &mut self.tree { tree.answer(m.node(), m.steps(), &answer) }
+                        }
+                    }
+                    self.answers.push_back(m)
+                } else {
+                    self.next_frontier.push_back(m)
+                }
+            }
+        }
+        self.answers.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{machine::{error::RuntimeError, translate::translate}, parser};
+    use super::*;
+
+    fn search(src : &str, limits : Limits) -> Search {
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        Search::new(Machine::new(comp, env), limits)
+    }
+
+    const POKE : &str = "poke :: Nat -> Nat
+poke n = case n of
+    Z -> 0
+  | S z -> S (poke z).
+
+exists x :: Nat. poke x.";
+
+    #[test]
+    fn unbounded_search_exhausts() {
+        let mut s = search("1 <> 2 <> 3.", Limits::default());
+        assert_eq!(s.by_ref().count(), 3);
+        assert_eq!(s.limit_hit(), None);
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut s = search(POKE, Limits { max_steps : Some(500), ..Limits::default() });
+        assert!(s.by_ref().count() > 0);
+        assert_eq!(s.limit_hit(), Some(LimitHit::Steps(500)));
+        assert_eq!(s.steps(), 500);
+    }
+
+    #[test]
+    fn frontier_limit() {
+        let mut s = search(POKE, Limits { max_frontier : Some(1), ..Limits::default() });
+        s.by_ref().for_each(drop);
+        assert_eq!(s.limit_hit(), Some(LimitHit::Frontier(1)));
+    }
+
+    #[test]
+    fn logic_var_limit() {
+        let mut s = search(POKE, Limits { max_lvars : Some(10), ..Limits::default() });
+        s.by_ref().for_each(drop);
+        assert_eq!(s.limit_hit(), Some(LimitHit::LogicVars(10)));
+    }
+
+    const ILL_TYPED : &str = "f :: Nat -> Nat
+f x = case x of
+      [] -> 0
+    | (y:ys) -> y.
+
+(1 <> f 2) <> 3.";
+
+    #[test]
+    fn runtime_error_ends_branch() {
+        let mut s = search(ILL_TYPED, Limits::default());
+        assert_eq!(s.by_ref().count(), 2);
+        assert!(!s.aborted());
+        assert_eq!(s.failures().len(), 1);
+        match &s.failures()[0].error {
+            RuntimeError::Eliminate { expected, found } => {
+                assert_eq!(*expected, "a list");
+                assert_eq!(found.to_string(), "2");
+            },
+            e => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn runtime_error_aborts() {
+        let src = POKE.replace("exists x :: Nat. poke x.", "f :: Nat -> Nat\nf x = case x of [] -> 0 | (y:ys) -> y.\n\nf 2 <> (exists x :: Nat. poke x).");
+        let mut s = search(&src, Limits::default()).with_on_error(OnError::Abort);
+        assert_eq!(s.by_ref().count(), 0);
+        assert!(s.aborted());
+        assert_eq!(s.failures().len(), 1);
+    }
+
+    // cargo test --release -- --ignored --nocapture bench_perm2
+    //
+    // Measured on one core in release mode, three runs each:
+    // - with the baseline's `Vec` union-find swapped in: 62.3s to 68.5s
+    // - with the persistent union-find: 62.3s to 63.4s
+    // The difference is within noise: perm2.bll never identifies two
+    // variables, so `find` walks no paths, and its machines hold few
+    // variables, so copying them is cheap. `union_find::tests::bench_branching`
+    // isolates the clones.
+    #[test]
+    #[ignore]
+    fn bench_perm2() {
+        let src = std::fs::read_to_string("examples/perm2.bll").unwrap();
+        let start = std::time::Instant::now();
+        let mut s = search(&src, Limits::default());
+        assert_eq!(s.by_ref().count(), 5040);
+        println!("perm2.bll: {} steps in {:?}", s.steps(), start.elapsed());
+    }
+
+    #[test]
+    fn timeout() {
+        let mut s = search(POKE, Limits { timeout : Some(Duration::from_millis(50)), ..Limits::default() });
+        s.by_ref().for_each(drop);
+        assert_eq!(s.limit_hit(), Some(LimitHit::Timeout(Duration::from_millis(50))));
+    }
+}
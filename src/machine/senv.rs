@@ -1,58 +1,215 @@
-use std::rc::Rc;
+use std::{collections::HashSet, sync::Arc};
 
-use im::HashMap;
+use im::{OrdMap, OrdSet};
 
-use super::{env::Env, mterms::{MComputation, MValue}, union_find::UnionFind, Ident, VClosure};
+use super::{env::Env, store::SuspStore, mterms::{MComputation, MValue}, union_find::UnionFind, Ident, VClosure};
 
-type CClosure = (Rc<MComputation>, Rc<Env>);
+pub type CClosure = (Arc<MComputation>, Arc<Env>);
+
+// Which pending suspension to force, whenever a machine is blocked on one
+// or has nothing else to do
+#[derive(Clone, Copy, Debug, Default)]
+pub enum Schedule {
+    // in creation order, the default
+    #[default]
+    Oldest,
+    // most recently created first
+    Newest,
+    // the one a unification, case or force is blocked on, then the oldest
+    // of those the final value depends on, falling back to creation order.
+    // Forcing unrelated suspensions first can starve a search, which this
+    // avoids.
+    Demand,
+    // whichever the function picks; anything but an idle suspension falls
+    // back to the blocked one, then to creation order
+    Custom(fn(&Pending) -> Option<Ident>),
+}
+
+// What a schedule picks from
+pub struct Pending<'a> {
+    // the pending suspensions nothing is forcing yet, oldest first
+    pub idle : &'a [Ident],
+    // the suspension the machine is blocked on, if any
+    pub blocked : Option<Ident>,
+    // the pending suspensions the final value depends on, once the query
+    // has returned
+    pub demanded : &'a [Ident],
+}
+
+impl Schedule {
+    // The suspension to force among `pending`, oldest first, skipping
+    // those in `busy`
+    pub fn pick<'a>(self, pending : impl DoubleEndedIterator<Item = &'a Ident>, blocked : Option<Ident>, demanded : &[Ident], busy : &[Ident]) -> Option<Ident> {
+        let mut idle = pending.filter(|i| !busy.contains(i)).copied();
+        let blocked = blocked.filter(|i| !busy.contains(i));
+        match self {
+            Schedule::Oldest => idle.next(),
+            Schedule::Newest => idle.next_back(),
+            Schedule::Demand => blocked.or(demanded.iter().filter(|i| !busy.contains(i)).min().copied()).or_else(|| idle.next()),
+            Schedule::Custom(pick) => {
+                let idle : Vec<Ident> = idle.collect();
+                pick(&Pending { idle : &idle, blocked, demanded }).filter(|i| idle.contains(i))
+                    .or(blocked).or(idle.first().copied())
+            },
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct SuspEnv {
-    map : HashMap<Ident, Result<VClosure, CClosure>>,
+    map : OrdMap<Ident, Result<VClosure, CClosure>>,
+    pending : OrdSet<Ident>,
+    schedule : Schedule,
     next : usize
 }
 
 #[derive(Clone, Debug)]
 pub struct SuspAt {
     pub ident : Ident,
-    pub comp : Rc<MComputation>,
-    pub env : Rc<Env>
+    pub comp : Arc<MComputation>,
+    pub env : Arc<Env>
 }
 
-impl SuspEnv {
+impl SuspStore for SuspEnv {
 
-    pub fn new() -> SuspEnv {
+    fn new() -> SuspEnv {
         SuspEnv {
-            map : HashMap::new(),
+            map : OrdMap::new(),
+            pending : OrdSet::new(),
+            schedule : Schedule::default(),
             next : 0 
         }
     }
+
+    fn with_schedule(self, schedule : Schedule) -> SuspEnv { SuspEnv { schedule, ..self } }
+
+    fn schedule(&self) -> Schedule { self.schedule }
     
-    pub fn size(&self) -> usize { self.map.len() }
+    fn size(&self) -> usize { self.map.len() }
 
-    pub fn fresh(&mut self, comp : &Rc<MComputation>, env : &Rc<Env>) -> Ident {
+    fn fresh(&mut self, comp : &Arc<MComputation>, env : &Arc<Env>) -> Ident {
         let next = self.next;
         self.map.insert(next, Err((comp.clone(), env.clone())));
+        self.pending.insert(next);
         self.next = next + 1;
         next
     }
     
-    pub fn lookup(&self, ident : &Ident) -> Result<VClosure, SuspAt>{
+    fn lookup(&self, ident : &Ident) -> Result<VClosure, SuspAt>{
         match self.map.get(ident).expect("unknown suspension ident") {
             Ok(vclos) => Ok(vclos.clone()),
             Err((comp, env)) => Err(SuspAt { ident : *ident, comp : comp.clone(), env : env.clone() })
         }
     }
     
-    pub fn set(&mut self, ident : &Ident, val : &Rc<MValue>, env : &Rc<Env>) {
-        self.map.insert(*ident, Ok(VClosure::mk_clos(&val, &env)));
+    fn set(&mut self, ident : &Ident, val : &Arc<MValue>, env : &Arc<Env>) {
+        self.map.insert(*ident, Ok(VClosure::mk_clos(val, env)));
+        self.pending.remove(ident);
     }
     
-    pub fn next(&self) -> Option<SuspAt> {
-        if let Some((ident, Err((comp, env)))) = self.map.iter().find(|(_, w)| w.is_err()) {
-            Some(SuspAt { ident : *ident , comp : comp.clone(), env : env.clone() })
+    fn next(&self, blocked : Option<Ident>, demanded : &[Ident], busy : &[Ident]) -> Option<SuspAt> {
+        let ident = self.schedule.pick(self.pending.iter(), blocked, demanded, busy)?;
+        self.lookup(&ident).err()
+    }
+
+    // branches own their copies, so there is nothing to roll back
+    fn mark(&self) -> usize { 0 }
+
+    fn undo(&self, _ : usize) {}
+}
+
+impl SuspEnv {
+
+    // Forgets every forced suspension outside `live`, returning how many
+    // were dropped. Pending suspensions are kept, as they must still run.
+    pub fn retain(&mut self, live : &HashSet<Ident>) -> usize {
+        let dead : Vec<Ident> = self.map.keys()
+            .filter(|ident| !live.contains(ident) && !self.pending.contains(ident))
+            .cloned().collect();
+        dead.iter().for_each(|ident| { self.map.remove(ident); });
+        dead.len()
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &Ident> { self.pending.iter() }
+
+    // The value or pending computation of a suspension
+    pub fn entry(&self, ident : Ident) -> Option<&Result<VClosure, CClosure>> { self.map.get(&ident) }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{machine::{limits::Limits, output, search::Search, step::Machine, translate::translate}, parser};
+    use super::*;
+
+    fn search(src : &str, schedule : Schedule) -> (Vec<String>, usize) {
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        let mut search = Search::new(Machine::new(comp, env).with_schedule(schedule), Limits::default());
+        let answers = search.by_ref()
+            .map(|m| match &*m.comp {
+                MComputation::Return(v) => output(v.clone(), m.env.clone(), None, &[], &m.lenv, &m.senv).unwrap(),
+                _ => unreachable!()
+            })
+            .collect();
+        (answers, search.steps())
+    }
+
+    fn answers(src : &str, schedule : Schedule) -> Vec<String> { search(src, schedule).0 }
+
+    const SRC : &str = "let a = (1 <> 2) in let b = (3 <> 4) in (a, b).";
+
+    #[test]
+    fn oldest_first() {
+        assert_eq!(answers(SRC, Schedule::Oldest), vec!["(1, 3)", "(1, 4)", "(2, 3)", "(2, 4)"]);
+    }
+
+    #[test]
+    fn newest_first() {
+        assert_eq!(answers(SRC, Schedule::Newest), vec!["(1, 3)", "(2, 3)", "(1, 4)", "(2, 4)"]);
+    }
+
+    #[test]
+    fn demanded_first() {
+        let src = "let a = (1 <> 2) in let b = (3 <> 4) in b.";
+        assert_eq!(answers(src, Schedule::Oldest), vec!["3", "4", "3", "4"]);
+        assert_eq!(answers(src, Schedule::Demand), vec!["3", "3", "4", "4"]);
+    }
+
+    #[test]
+    fn blocked_first() {
+        // `b` fails the unification, so forcing `a` first is wasted work
+        let src = "let a = (1 <> 2) in let b = (3 <> 3) in b =:= 4. a.";
+        let (demand, oldest) = (search(src, Schedule::Demand), search(src, Schedule::Oldest));
+        assert!(demand.0.is_empty() && oldest.0.is_empty());
+        assert!(demand.1 < oldest.1);
+    }
+
+    #[test]
+    fn demand_skips_busy() {
+        let pending = [0, 1, 2, 3];
+        assert_eq!(Schedule::Demand.pick(pending.iter(), Some(1), &[2], &[]), Some(1));
+        assert_eq!(Schedule::Demand.pick(pending.iter(), Some(1), &[2], &[1]), Some(2));
+        assert_eq!(Schedule::Demand.pick(pending.iter(), Some(1), &[2], &[1, 2]), Some(0));
+    }
+
+    #[test]
+    fn default_is_oldest() {
+        assert!(matches!(Schedule::default(), Schedule::Oldest));
+    }
+
+    #[test]
+    fn custom() {
+        let newest = Schedule::Custom(|p| p.idle.last().copied());
+        assert_eq!(answers(SRC, newest), answers(SRC, Schedule::Newest));
+        // a pick that isn't idle falls back to creation order
+        assert_eq!(answers(SRC, Schedule::Custom(|_| Some(99))), answers(SRC, Schedule::Oldest));
+    }
+
+    #[test]
+    fn deterministic() {
+        let src = "let a = (1 <> 2) in let b = (3 <> 4) in let c = (5 <> 6) in let d = (7 <> 8) in (a, (b, (c, d))).";
+        let first = answers(src, Schedule::Oldest);
+        for _ in 0..10 {
+            assert_eq!(answers(src, Schedule::Oldest), first);
         }
-        else { None }
     }
-    
-}
\ No newline at end of file
+}
@@ -0,0 +1,101 @@
+use std::collections::HashMap;
+
+use super::{env::Env, mterms::{MComputation, MValue}, trace::{Event, Fork, Shape, Tracer, Unified}, VClosure};
+
+// What a search did, counted from the events of its steps. Every call of a
+// function, recursive or not, runs the `rec` its definition starts with, so
+// calls are counted by the computations run.
+pub struct Stats {
+    // pending machines, sampled before each step
+    pub peak_frontier : usize,
+    total_frontier : usize,
+    samples : usize,
+    pub choices : usize,
+    // narrowings of numbers, lists and sums
+    pub narrowings : [usize; 3],
+    pub unify_failures : usize,
+    pub occurs_failures : usize,
+    pub suspended : usize,
+    pub forced : usize,
+    // the function whose definition each `rec` is
+    functions : HashMap<*const MComputation, usize>,
+    // calls by function, in order of definition
+    pub calls : Vec<(String, usize)>,
+}
+
+impl Stats {
+
+    // Counts the calls of the functions defined in `env`, named by `names`
+    pub fn new(env : &Env, names : &[String]) -> Stats {
+        let mut functions = HashMap::new();
+        let mut calls = vec![];
+        for (i, vclos) in env.iter().collect::<Vec<_>>().into_iter().rev().enumerate() {
+            if let VClosure::Clos { val, .. } = vclos {
+                if let MValue::Thunk(comp) = &**val {
+                    functions.insert(std::sync::Arc::as_ptr(comp), calls.len());
+                    calls.push((names.get(i).cloned().unwrap_or_else(|| format!("#{}", i)), 0));
+                }
+            }
+        }
+        Stats {
+            peak_frontier : 0, total_frontier : 0, samples : 0, choices : 0, narrowings : [0; 3],
+            unify_failures : 0, occurs_failures : 0, suspended : 0, forced : 0, functions, calls,
+        }
+    }
+
+    pub fn frontier(&mut self, size : usize) {
+        self.peak_frontier = self.peak_frontier.max(size);
+        self.total_frontier += size;
+        self.samples += 1;
+    }
+
+    pub fn average_frontier(&self) -> f64 {
+        if self.samples == 0 { 0.0 } else { self.total_frontier as f64 / self.samples as f64 }
+    }
+}
+
+impl Tracer for Stats {
+    fn event(&mut self, _ : usize, event : Event) {
+        match event {
+            Event::Step { comp : comp @ MComputation::Rec { .. } } => {
+                if let Some(f) = self.functions.get(&(comp as *const MComputation)) { self.calls[*f].1 += 1 }
+            },
+            Event::Fork { cause : Fork::Choice, .. } => self.choices += 1,
+            Event::Fork { cause : Fork::Narrow(shape), .. } => self.narrowings[shape as usize] += 1,
+            Event::Unify { outcome : Unified::Fail } => self.unify_failures += 1,
+            Event::Unify { outcome : Unified::Occurs } => self.occurs_failures += 1,
+            Event::Suspend { .. } => self.suspended += 1,
+            Event::Force { .. } => self.forced += 1,
+            _ => (),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{machine::{limits::Limits, search::Search, step::Machine, translate::{globals, translate}}, parser};
+    use super::*;
+
+    #[test]
+    fn counts() {
+        let src = "add :: Nat -> Nat -> Nat
+add n m = case m of
+    Z -> n
+  | S z -> S (add n z).
+
+exists x :: Nat. exists y :: Nat. add x y =:= 2. (1 <> (add y 1)).";
+        let ast = parser::parse(src).unwrap();
+        let names = globals(&ast);
+        let (comp, env) = translate(ast).unwrap();
+        let stats = Stats::new(&env, &names);
+        let mut search = Search::new(Machine::new(comp, env), Limits { max_steps : Some(2000), ..Limits::default() }).with_stats(stats);
+        assert_eq!(search.by_ref().count(), 6);
+        let stats = search.take_stats().unwrap();
+        assert_eq!(stats.calls, [("add".to_string(), 9)]);
+        assert_eq!(stats.narrowings, [3, 0, 0]);
+        assert_eq!(stats.choices, 3);
+        assert_eq!(stats.unify_failures, 1);
+        assert_eq!(stats.peak_frontier, 4);
+        assert!(stats.forced > 0 && stats.forced <= stats.suspended);
+    }
+}
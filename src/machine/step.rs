@@ -1,66 +1,181 @@
-use std::rc::Rc;
-use crate::machine::{lvar, senv, senv::SuspAt, value_type::ValueType};
-use super::{lvar::LogicEnv, mterms::{MComputation, MValue}, senv::SuspEnv, unify::UnifyError, Env, Ident, VClosure};
+use std::sync::Arc;
+use im::Vector;
+use crate::machine::{lvar, senv, senv::{Schedule, SuspAt}, value_type::ValueType};
+use super::{builtin::{not_ground, ready, Goal, NotGround}, table::Producer, trace::{Event, Fork, Shape, Tracer, Unified}, lvar::LogicEnv, mterms::{MComputation, MValue}, senv::SuspEnv, store::{LogicStore, SuspStore}, error::RuntimeError, unify::UnifyError, vclosure::Seen, Env, Ident, VClosure};
 use crate::machine::unify::unify;
     
 #[derive(Clone, Debug)]
 enum StkFrame {
-    Value(Rc<MValue>),
-    To(Rc<MComputation>),
-    Set(Ident, Rc<MComputation>),
+    Value(Arc<MValue>),
+    To(Arc<MComputation>),
+    Set(Ident, Arc<MComputation>),
 }
 
 #[derive(Clone, Debug)]
 pub struct StkClosure {
     stk_frame: StkFrame,
-    stk_env: Rc<Env>,
+    stk_env: Arc<Env>,
 }
 
 #[derive(Clone, Debug)]
 pub enum Stack {
     Nil,
-    Cons(StkClosure, Rc<Stack>),
+    Cons(StkClosure, Arc<Stack>),
 }
 
 impl Stack {
-    pub fn empty_stack() -> Rc<Stack> { Rc::new(Stack::Nil) }
+    pub fn empty_stack() -> Arc<Stack> { Arc::new(Stack::Nil) }
 
-    fn push_closure(self: &Rc<Stack>, stk_frame : StkFrame, stk_env: Rc<Env>) -> Rc<Stack> {
+    fn push_closure(self: &Arc<Stack>, stk_frame : StkFrame, stk_env: Arc<Env>) -> Arc<Stack> {
         Stack::Cons(StkClosure { stk_frame, stk_env }, self.clone()).into()
     }
 
-    fn push_susp(self: &Rc<Stack>, ident: Ident, c: Rc<MComputation>, env: Rc<Env>) -> Rc<Stack> {
+    // The environments and values held by the frames of the stack
+    pub fn frames(&self) -> Vec<(Option<Arc<MValue>>, Arc<Env>)> {
+        let mut frames = vec![];
+        let mut stk = self;
+        while let Stack::Cons(StkClosure { stk_frame, stk_env }, tail) = stk {
+            let val = match stk_frame {
+                StkFrame::Value(v) => Some(v.clone()),
+                StkFrame::To(_) | StkFrame::Set(_, _) => None,
+            };
+            frames.push((val, stk_env.clone()));
+            stk = tail;
+        }
+        frames
+    }
+
+    // The suspensions being forced, whose values the stack waits for
+    pub fn forcing(&self) -> Vec<Ident> {
+        let mut idents = vec![];
+        let mut stk = self;
+        while let Stack::Cons(StkClosure { stk_frame, .. }, tail) = stk {
+            if let StkFrame::Set(ident, _) = stk_frame { idents.push(*ident) }
+            stk = tail;
+        }
+        idents
+    }
+
+    fn push_susp(self: &Arc<Stack>, ident: Ident, c: Arc<MComputation>, env: Arc<Env>) -> Arc<Stack> {
         Stack::push_closure(self, StkFrame::Set(ident, c), env)
     }
 }
 
 #[derive(Clone)]
-pub struct Machine {
-    pub comp : Rc<MComputation>,
-    pub stack: Rc<Stack>,
-    pub env  : Rc<Env>,
-    pub lenv : LogicEnv,
-    pub senv : SuspEnv,
-    pub done : bool
+pub struct Machine<L = LogicEnv, S = SuspEnv> {
+    pub comp : Arc<MComputation>,
+    pub stack: Arc<Stack>,
+    pub env  : Arc<Env>,
+    pub lenv : L,
+    pub senv : S,
+    pub done : bool,
+    // the table this machine produces answers for, if any
+    pub producer : Option<Producer>,
+    // steps taken since the initial machine
+    pub steps : usize,
+    // the named variables of the query created so far, oldest first
+    pub bindings : Vector<(String, Ident)>,
+    // the builtin calls waiting for their arguments, oldest first
+    pub goals : Vector<Goal>,
+    // the node of the search tree the machine is in, if one is recorded
+    pub node : usize,
 }
 
-fn eval_susp_then(a : SuspAt, m : Machine) -> Machine {
+pub fn eval_susp_then<L, S>(a : SuspAt, m : Machine<L, S>) -> Machine<L, S> {
     Machine { comp : a.comp, env : a.env, stack : m.stack.push_susp(a.ident, m.comp, m.env), ..m  }
 }
 
-impl Machine {
+fn force_susp<L, S>(a : SuspAt, m : Machine<L, S>, tracer : &mut impl Tracer) -> Machine<L, S> {
+    tracer.event(m.steps, Event::Force { susp : a.ident });
+    eval_susp_then(a, m)
+}
+
+// Forces the suspension the machine is blocked on, or whichever the
+// schedule prefers
+fn unblock<L : LogicStore, S : SuspStore>(a : SuspAt, m : Machine<L, S>, tracer : &mut impl Tracer) -> Machine<L, S> {
+    let a = m.scheduled(a);
+    force_susp(a, m, tracer)
+}
 
-    pub fn step(self) -> Vec<Machine> {
-        let m = self;
+impl<L : LogicStore, S : SuspStore> Machine<L, S> {
+
+    pub fn new(comp : MComputation, env : Arc<Env>) -> Machine<L, S> {
+        Machine { comp: comp.into(), env, stack: Stack::empty_stack(), lenv : L::new(), senv : S::new(), done: false, producer: None, steps: 0, bindings : Vector::new(), goals : Vector::new(), node : 0 }
+    }
+
+    pub fn with_schedule(self, schedule : Schedule) -> Machine<L, S> {
+        Machine { senv : self.senv.with_schedule(schedule), ..self }
+    }
+
+    pub fn with_occurs_check(self, occurs_check : bool) -> Machine<L, S> {
+        Machine { lenv : self.lenv.with_occurs_check(occurs_check), ..self }
+    }
+
+    // The suspension to force when the machine is blocked on `a`. The
+    // machine comes back to `a` once whichever it forces is set.
+    pub fn scheduled(&self, a : SuspAt) -> SuspAt {
+        match self.senv.schedule() {
+            // which always picks `a`, so don't walk the stack
+            Schedule::Demand => a,
+            _ => self.senv.next(Some(a.ident), &[], &self.stack.forcing()).unwrap_or(a),
+        }
+    }
+
+    // The pending suspensions `val` depends on, when the schedule asks for them
+    fn demanded_susps(&self, val : &Arc<MValue>) -> Vec<Ident> {
+        let mut out = vec![];
+        if matches!(self.senv.schedule(), Schedule::Demand) {
+            VClosure::mk_clos(val, &self.env).demanded_susps(&self.lenv, &self.senv, &mut Seen::default(), &mut out);
+        }
+        out
+    }
+
+    // Every machine the machine steps to. Each branch is built from a
+    // clone, so this only makes sense for persistent stores.
+    pub fn step(self, tracer : &mut impl Tracer) -> Result<Vec<Machine<L, S>>, RuntimeError> {
+        let (m, n) = self.clone().step_alt(0, tracer)?;
+        let mut ms : Vec<Machine<L, S>> = m.into_iter().collect();
+        for k in 1..n {
+            ms.extend(self.clone().step_alt(k, tracer)?.0)
+        }
+        Ok(ms)
+    }
+
+    // The `k`th machine the machine steps to, if that branch hasn't failed,
+    // along with how many branches there are. A fork is traced with its
+    // first branch.
+    pub fn step_alt(self, k : usize, tracer : &mut impl Tracer) -> Result<(Option<Machine<L, S>>, usize), RuntimeError> {
+        let steps = self.steps + 1;
+        let (m, n) = self.transition(k, tracer)?;
+        Ok((m.map(|m| Machine { steps, ..m }), n))
+    }
+
+    fn transition(self, k : usize, tracer : &mut impl Tracer) -> Result<(Option<Machine<L, S>>, usize), RuntimeError> {
+        let mut m = self;
+        let depth = m.steps;
         
-        match &*m.comp {
+        Ok(match &*m.comp {
 
             MComputation::Return(val) => {
                 match &*m.stack {
                     Stack::Nil => {
-                        match m.senv.next() {
-                            Some(a) => vec![ eval_susp_then(a, m) ],
-                            None => vec![Machine { done: true, ..m }],
+                        match m.senv.next(None, &m.demanded_susps(val), &[]) {
+                            Some(a) => (Some(force_susp(a, m, tracer)), 1),
+                            None => match ready(&m.goals, &m.lenv, &m.senv)? {
+                                // a waiting call binds its result to each of
+                                // its results, then the machine returns again
+                                Some((i, args)) => {
+                                    let mut goals = m.goals.clone();
+                                    let goal = goals.remove(i);
+                                    let env = m.env.extend_val(val.clone(), m.env.clone()).extend_lvar(goal.result);
+                                    let mut branches : Vec<Arc<MComputation>> = goal.builtin.call(&args)?.into_iter()
+                                        .map(|v| MComputation::Equate { lhs : MValue::Var(0).into(), rhs : v.into(), body : MComputation::Return(MValue::Var(1).into()).into() }.into())
+                                        .collect();
+                                    let comp = if branches.len() == 1 { branches.pop().unwrap() } else { MComputation::Choice(branches).into() };
+                                    (Some(Machine { comp, env, goals, ..m }), 1)
+                                },
+                                None => (Some(Machine { done: true, ..m }), 1),
+                            },
                         }
                     }
                     Stack::Cons(stk_clos, stk_tail) => {
@@ -69,12 +184,12 @@ impl Machine {
                             StkFrame::Value(_) => unreachable!("return throws value to a value"),
                             StkFrame::To(cont) => {
                                 let new_env = stk_env.extend_val(val.clone(), m.env.clone());
-                                vec![Machine { comp: cont.clone(), stack: stk_tail.clone(), env: new_env, ..m }]
+                                (Some(Machine { comp: cont.clone(), stack: stk_tail.clone(), env: new_env, ..m }), 1)
                             }
                             StkFrame::Set(i, cont) => {
                                 let mut senv = m.senv;
                                 senv.set(&i, val, &m.env);
-                                vec![Machine { comp: cont.clone(), stack: stk_tail.clone(), env: stk_env.clone(), senv, ..m }]
+                                (Some(Machine { comp: cont.clone(), stack: stk_tail.clone(), env: stk_env.clone(), senv, ..m }), 1)
                             }
                         }
                     }
@@ -85,35 +200,34 @@ impl Machine {
                 match &**comp {
                     MComputation::Return(v) => {
                         let env = m.env.extend_val(v.clone(), m.env.clone());
-                        vec![Machine { comp : cont.clone(), env, ..m }]
+                        (Some(Machine { comp : cont.clone(), env, ..m }), 1)
                     },
                     _ => {
                         let mut senv = m.senv;
                         let env = &m.env;
-                        let ident = senv.fresh(&comp, &m.env);
+                        let ident = senv.fresh(comp, &m.env);
+                        tracer.event(depth, Event::Suspend { susp : ident });
                         let env = env.extend_susp(ident);
-                        vec![Machine { comp : cont.clone(), env, senv : senv, ..m}]
+                        (Some(Machine { comp : cont.clone(), env, senv, ..m}), 1)
                     }
                 }
             },
 
             MComputation::Force(v) => {
                 let vclos = VClosure::Clos { val: v.clone(), env: m.env.clone() };
-                match vclos.close_head(&m.lenv, &m.senv) {
+                match vclos.close_head(&mut m.lenv, &m.senv) {
                     Ok(vclos) => 
                         match vclos {
                             VClosure::Clos { val, env } => {
                                 match &*val {
-                                    MValue::Thunk(t) => vec![Machine { comp : t.clone(), env : env.clone(), ..m}],
-                                _ => panic!("shouldn't be forcing a non-thunk value")
+                                    MValue::Thunk(t) => (Some(Machine { comp : t.clone(), env : env.clone(), ..m}), 1),
+                                _ => return Err(RuntimeError::ForceValue(val.clone()))
                                 } 
                             },
-                            VClosure::LogicVar { ident } => panic!("shouldn't be forcing a logic variable"),
+                            VClosure::LogicVar { ident } => return Err(RuntimeError::ForceLogicVar(m.lenv.get_type(ident))),
                             VClosure::Susp { ident } => unreachable!("shouldn't be forcing a suspension"),
                         }
-                    Err(a) => {
-                        vec![Machine { comp : a.comp, env : a.env, stack : m.stack.push_susp(a.ident, m.comp, m.env), ..m  }]
-                    },
+                    Err(a) => (Some(unblock(a, m, tracer)), 1),
                 }
             },
 
@@ -122,73 +236,88 @@ impl Machine {
                     Stack::Cons(StkClosure { stk_frame, stk_env }, tail) => {
                         if let StkFrame::Value(val) = stk_frame {
                             let env = m.env.extend_val(val.clone(), stk_env.clone());
-                            vec![Machine { comp: body.clone(), stack: tail.clone(), env, ..m }]
+                            (Some(Machine { comp: body.clone(), stack: tail.clone(), env, ..m }), 1)
                         } else {
-                            panic!("lambda but no value StkFrame in the stack")
+                            return Err(RuntimeError::NoArgument)
                         }
                     },
-                    Stack::Nil => panic!("lambda met with empty stack")
+                    Stack::Nil => return Err(RuntimeError::NoArgument)
                 }
             },
 
             MComputation::App { op, arg } =>
-                vec![Machine { comp: op.clone(), stack: m.stack.push_closure(StkFrame::Value(arg.clone()), m.env.clone()), ..m }],
-
-            MComputation::Choice(choices) => 
-              choices.iter().map(|c| Machine { comp: c.clone(), ..m.clone()}).collect(),
+                (Some(Machine { comp: op.clone(), stack: m.stack.push_closure(StkFrame::Value(arg.clone()), m.env.clone()), ..m }), 1),
+            MComputation::Choice(choices) => {
+                if k == 0 { tracer.event(depth, Event::Fork { cause : Fork::Choice, branches : choices.len() }) }
+                (choices.get(k).map(|c| Machine { comp: c.clone(), ..m }), choices.len())
+            },
 
-            MComputation::Exists { ptype, body } => {
+            MComputation::Exists { ptype, name, body } => {
                 let mut lenv = m.lenv;
                 let ident = lenv.fresh(ptype.clone());
-                vec![Machine { comp : body.clone(), env : m.env.extend_lvar(ident), lenv : lenv, ..m}]
+                let mut bindings = m.bindings;
+                if let Some(name) = name { bindings.push_back((name.clone(), ident)) }
+                (Some(Machine { comp : body.clone(), env : m.env.extend_lvar(ident), lenv, bindings, ..m}), 1)
             }
 
             MComputation::Equate { lhs, rhs, body } => {
                 let mut lenv = m.lenv;
-                match unify(&lhs, &rhs, &m.env, &mut lenv, &m.senv) {
-                    Ok(()) => vec![ Machine { comp : body.clone(), lenv : lenv, ..m } ],
-                    Err(UnifyError::Susp(a)) => vec![ eval_susp_then(a, Machine { lenv : lenv, ..m }) ],
-                    Err(_) => vec![]
+                let result = unify(lhs, rhs, &m.env, &mut lenv, &m.senv, &mut |lvar, value| tracer.event(depth, Event::Bind { lvar, value }));
+                let outcome = match result {
+                    Ok(()) => Some(Unified::Success),
+                    Err(UnifyError::Fail) => Some(Unified::Fail),
+                    Err(UnifyError::Occurs) => Some(Unified::Occurs),
+                    Err(_) => None,
+                };
+                if let Some(outcome) = outcome { tracer.event(depth, Event::Unify { outcome }) }
+                match result {
+                    Ok(()) => (Some(Machine { comp : body.clone(), lenv, ..m }), 1),
+                    Err(UnifyError::Susp(a)) => (Some(unblock(a, Machine { lenv, ..m }, tracer)), 1),
+                    Err(UnifyError::Runtime(e)) => return Err(e),
+                    Err(_) => (None, 1)
                 }
             },
 
             MComputation::Ifz { num, zk, sk } => {
                 let vclos = VClosure::mk_clos(num, &m.env);
-                match vclos.close_head(&m.lenv, &m.senv) {
-                    Err(a) => vec![ eval_susp_then(a, m) ],
+                match vclos.close_head(&mut m.lenv, &m.senv) {
+                    Err(a) => (Some(unblock(a, m, tracer)), 1),
                     Ok(VClosure::Clos { val, env }) => {
                         match &*val {
-                            MValue::Zero => vec![Machine { comp: zk.clone(), ..m}],
+                            MValue::Zero => (Some(Machine { comp: zk.clone(), ..m}), 1),
                             MValue::Succ(v) => {
                                 let env = m.env.extend_val(v.clone(), env.clone());
-                                vec![Machine { comp: sk.clone(), env, ..m}]
+                                (Some(Machine { comp: sk.clone(), env, ..m}), 1)
                             }
-                            _ => panic!("Ifz on {}", &*val)
+                            _ => return Err(RuntimeError::Eliminate { expected : "a number", found : val.clone() })
                         }
                     },
                     Ok(VClosure::LogicVar { ident }) => { // must be unresolved, by structure of close_head
-                        let m_zero  = {
+                        let m = if k == 0 {
+                            tracer.event(depth, Event::Fork { cause : Fork::Narrow(Shape::Nat), branches : 2 });
                             let mut lenv = m.lenv.clone(); // make a new logic env
-                            lenv.set_vclos(ident, VClosure::Clos { val: MValue::Zero.into(), env: Env::empty()});
+                            let value = VClosure::Clos { val: MValue::Zero.into(), env: Env::empty()};
+                            tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                            lenv.set_vclos(ident, value);
 
-                            Machine { comp: zk.clone(), lenv : lenv, ..m.clone()}
-                        };
-                        
-                        let m_succ = {
+                            Machine { comp: zk.clone(), lenv, ..m.clone()}
+                        } else {
                             let mut lenv = m.lenv.clone();
                             let ident_lvar_succ = lenv.fresh(ValueType::Nat);
                             
-                            lenv.set_vclos(ident, VClosure::Clos { 
-                                val : MValue::Succ(Rc::new(MValue::Var(0))).into(), 
+                            let value = VClosure::Clos { 
+                                val : MValue::Succ(Arc::new(MValue::Var(0))).into(), 
                                 env : Env::empty().extend_lvar(ident_lvar_succ)
-                            }.into());
+                            };
+                            tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                            lenv.set_vclos(ident, value);
                             
                             let new_env = m.env.extend_lvar(ident_lvar_succ);
 
-                            Machine { comp: sk.clone(), lenv : lenv, env : new_env, ..m.clone()}
+                            Machine { comp: sk.clone(), lenv, env : new_env, ..m.clone()}
                         };
 
-                        vec![m_zero, m_succ]
+                        (Some(m), 2)
                     },
                     Ok(VClosure::Susp { ident }) => unreachable!("shouldn't be encountering a suspension here")
                 }
@@ -196,52 +325,54 @@ impl Machine {
 
             MComputation::Match { list, nilk, consk } => {
                 let vclos = VClosure::mk_clos(list, &m.env);
-                let closed_list = vclos.close_head(&m.lenv, &m.senv);
+                let closed_list = vclos.close_head(&mut m.lenv, &m.senv);
                 match closed_list {
-                    Err(a) => vec![ eval_susp_then(a, m) ],
+                    Err(a) => (Some(unblock(a, m, tracer)), 1),
                     Ok(vclos) => 
                         match vclos {
                             VClosure::Clos { val, env } => {
                                 match &*val {
-                                    MValue::Nil => vec![Machine { comp: nilk.clone(), ..m}],
+                                    MValue::Nil => (Some(Machine { comp: nilk.clone(), ..m}), 1),
                                     MValue::Cons(v, w) => {
                                         let env = m.env.extend_val(v.clone(), env.clone()).extend_val(w.clone(), env.clone());
-                                        vec![Machine { comp: consk.clone(), env, ..m}]
+                                        (Some(Machine { comp: consk.clone(), env, ..m}), 1)
                                     },
-                                    _ => panic!("Match on non-list")
+                                    _ => return Err(RuntimeError::Eliminate { expected : "a list", found : val.clone() })
                                 }
                             },
                             VClosure::LogicVar { ident } => {  // must be unresolved, by structure of close_head
                                                               
                                 let ptype = match m.lenv.get_type(ident) {
                                     ValueType::List(t) => t,
-                                    _ => panic!("matching on a non-list logical variable")
+                                    found => return Err(RuntimeError::Narrow { expected : "a list", found })
                                 };
 
-                                let m_nil  = {
-                                    
+                                let m = if k == 0 {
+                                    tracer.event(depth, Event::Fork { cause : Fork::Narrow(Shape::List), branches : 2 });
                                     let mut lenv = m.lenv.clone();
-                                    lenv.set_vclos(ident, VClosure::mk_clos(&MValue::Nil.into(), &Env::empty().into()));
+                                    let value = VClosure::mk_clos(&MValue::Nil.into(), &Env::empty());
+                                    tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                                    lenv.set_vclos(ident, value);
 
                                     Machine { comp: nilk.clone(), lenv, ..m.clone()}
-                                };
-                                
-                                let m_cons = {
+                                } else {
                                     
                                     let mut lenv = m.lenv.clone();
                                     let head_ident = lenv.fresh(*ptype.clone());
                                     let tail_ident = lenv.fresh(ValueType::List(ptype));
                                     
-                                    lenv.set_vclos(ident, VClosure::mk_clos(
+                                    let value = VClosure::mk_clos(
                                          &MValue::Cons(MValue::Var(1).into(), MValue::Var(0).into()).into(),
-                                         &Env::empty().extend_lvar(head_ident).extend_lvar(tail_ident).into()
-                                    ));
+                                         &Env::empty().extend_lvar(head_ident).extend_lvar(tail_ident)
+                                    );
+                                    tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                                    lenv.set_vclos(ident, value);
                                     
                                     let env = m.env.extend_lvar(head_ident).extend_lvar(tail_ident);
 
                                     Machine { comp: consk.clone(), lenv, env, ..m.clone()}
                                 };
-                                vec![m_nil, m_cons]
+                                (Some(m), 2)
                             }
                             VClosure::Susp { ident } => unreachable!("shouldn't be matching on a suspension"),
                         }
@@ -249,9 +380,9 @@ impl Machine {
             },
             MComputation::Case { sum, inlk, inrk } => {
                 let vclos = VClosure::mk_clos(sum, &m.env);
-                let closed_sum = vclos.close_head(&m.lenv, &m.senv);
+                let closed_sum = vclos.close_head(&mut m.lenv, &m.senv);
                 match closed_sum {
-                    Err(a) => vec![ eval_susp_then(a, m) ],
+                    Err(a) => (Some(unblock(a, m, tracer)), 1),
                     Ok(vclos) => 
                         match vclos {
                             VClosure::Clos { val, env } => {
@@ -259,52 +390,55 @@ impl Machine {
                                     MValue::Inl(v) => {
                                         let old_env = env.clone();
                                         let new_env = m.env.extend_val(v.clone(), old_env.clone());
-                                        vec![Machine { comp: inlk.clone(), env : new_env, ..m}]
+                                        (Some(Machine { comp: inlk.clone(), env : new_env, ..m}), 1)
                                     },
                                     MValue::Inr(v) => {
                                         let old_env = env.clone();
                                         let new_env = m.env.extend_val(v.clone(), old_env.clone());
-                                        vec![Machine { comp: inrk.clone(), env : new_env, ..m}]
+                                        (Some(Machine { comp: inrk.clone(), env : new_env, ..m}), 1)
                                     },
-                                    _ => panic!("Match on non-list")
+                                    _ => return Err(RuntimeError::Eliminate { expected : "a sum", found : val.clone() })
                                 }
                             },
                             VClosure::LogicVar { ident } => {  // must be unresolved, by structure of close_head
                                                               
                                 let (ptype1, ptype2) = match m.lenv.get_type(ident) {
                                     ValueType::Sum(t1, t2) => (t1, t2),
-                                    _ => panic!("casing on a non-sum logical variable")
+                                    found => return Err(RuntimeError::Narrow { expected : "a sum", found })
                                 };
 
-                                let m_inl = {
+                                let m = if k == 0 {
+                                    tracer.event(depth, Event::Fork { cause : Fork::Narrow(Shape::Sum), branches : 2 });
                                     let mut lenv = m.lenv.clone();
                                     let inl_ident = lenv.fresh(*ptype1.clone());
 
-                                    lenv.set_vclos(ident, VClosure::mk_clos(
+                                    let value = VClosure::mk_clos(
                                             &MValue::Inl(MValue::Var(0).into()).into(),
                                             &Env::empty().extend_lvar(inl_ident)
-                                    ));
+                                    );
+                                    tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                                    lenv.set_vclos(ident, value);
 
                                     let env = m.env.extend_lvar(inl_ident);
 
                                     Machine { comp: inlk.clone(), lenv, env, ..m.clone()}
-                                };
-
-                                let m_inr = {
+                                } else {
                                     let mut lenv = m.lenv.clone();
                                     let inr_ident = lenv.fresh(*ptype2.clone());
 
-                                    lenv.set_vclos(ident, VClosure::mk_clos(
+                                    let value = VClosure::mk_clos(
                                             &MValue::Inr(MValue::Var(0).into()).into(),
                                             &Env::empty().extend_lvar(inr_ident)
-                                    ));
+                                    );
+                                    tracer.event(depth, Event::Bind { lvar : ident, value : &value });
+                                    lenv.set_vclos(ident, value);
 
                                     let env = m.env.extend_lvar(inr_ident);
 
                                     Machine { comp: inrk.clone(), lenv, env, ..m.clone()}
                                 };
 
-                                vec![m_inl, m_inr]
+                                (Some(m), 2)
                             }
                             VClosure::Susp { ident } => unreachable!("oops")
                         }
@@ -312,9 +446,40 @@ impl Machine {
             },
             MComputation::Rec { body } => {
                 let env = m.env.extend_val(m.comp.thunk(), m.env.clone());
-                vec![Machine { comp : body.clone(), env, ..m }] 
+                (Some(Machine { comp : body.clone(), env, ..m }), 1) 
             },
-        }
+
+            // searches memoise tabled calls themselves, see `table::Tables`
+            MComputation::Table { body, .. } => (Some(Machine { comp : body.clone(), ..m }), 1),
+
+            MComputation::Code(_) => unreachable!("compiled code runs on the bytecode machine"),
+
+            // the results are the branches of a choice, taken by the next step
+            MComputation::Builtin(f) => match f.args(&m.env, &m.lenv, &m.senv) {
+                Ok(args) => {
+                    let mut results : Vec<Arc<MComputation>> = f.call(&args)?.into_iter().map(|v| MComputation::Return(v.into()).into()).collect();
+                    let comp = if results.len() == 1 { results.pop().unwrap() } else { MComputation::Choice(results).into() };
+                    (Some(Machine { comp, ..m }), 1)
+                },
+                Err(NotGround::Susp(a)) => (Some(unblock(a, m, tracer)), 1),
+                // the call waits while other suspensions run, which may bind
+                // its arguments. With none left, it returns a variable for
+                // its result and runs once the rest of the branch has bound
+                // them.
+                Err(NotGround::Unbound) => match m.senv.next(None, &[], &m.stack.forcing()) {
+                    Some(a) => (Some(force_susp(a, m, tracer)), 1),
+                    None => {
+                        let mut lenv = m.lenv;
+                        let result = lenv.fresh(f.result.clone());
+                        let mut goals = m.goals;
+                        goals.push_back(Goal { builtin : f.clone(), env : m.env.clone(), result });
+                        let comp = MComputation::Return(MValue::Var(0).into()).into();
+                        (Some(Machine { comp, env : m.env.extend_lvar(result), lenv, goals, ..m }), 1)
+                    },
+                },
+                Err(e) => return Err(not_ground(f, e)),
+            },
+        })
     }
     
 }
\ No newline at end of file
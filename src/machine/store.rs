@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use super::{env::Env, mterms::{MComputation, MValue}, senv::{Schedule, SuspAt}, value_type::ValueType, Ident, VClosure};
+
+// Bindings of logic variables. `LogicEnv` is persistent, so each branch of
+// a search owns a copy; `TrailLogicEnv` is a single mutable store whose
+// changes a depth-first search undoes when it backtracks.
+pub trait LogicStore : Clone {
+    fn new() -> Self;
+
+    fn size(&self) -> usize;
+
+    // Without the occurs check, variables may be bound to terms containing
+    // themselves, giving rational trees
+    fn with_occurs_check(self, occurs_check : bool) -> Self;
+
+    fn occurs_check(&self) -> bool;
+
+    fn fresh(&mut self, ptype : ValueType) -> Ident;
+
+    // The binding of the variables identified with `ident`, if any
+    fn lookup(&self, ident : Ident) -> Option<VClosure>;
+
+    // `lookup`, also shortening the way from `ident` to its representative
+    // for the lookups after
+    fn resolve(&mut self, ident : Ident) -> Option<VClosure>;
+
+    // The representative of the variables identified with `ident`
+    fn root(&self, ident : Ident) -> Ident;
+
+    fn set_vclos(&mut self, ident : Ident, vclos : VClosure);
+
+    fn get_type(&self, ident : Ident) -> ValueType;
+
+    fn identify(&mut self, ident1 : Ident, ident2 : Ident);
+
+    // A point the store can later be rolled back to with `undo`
+    fn mark(&self) -> usize;
+
+    fn undo(&self, mark : usize);
+}
+
+// Suspended computations and the values they were forced to, with the
+// same two implementations as `LogicStore`
+pub trait SuspStore : Clone {
+    fn new() -> Self;
+
+    fn size(&self) -> usize;
+
+    fn with_schedule(self, schedule : Schedule) -> Self;
+
+    fn schedule(&self) -> Schedule;
+
+    fn fresh(&mut self, comp : &Arc<MComputation>, env : &Arc<Env>) -> Ident;
+
+    fn lookup(&self, ident : &Ident) -> Result<VClosure, SuspAt>;
+
+    fn set(&mut self, ident : &Ident, val : &Arc<MValue>, env : &Arc<Env>);
+
+    // The pending suspension to force next, as the schedule picks it.
+    // `blocked` is the one the machine is waiting on, if any, `demanded`
+    // lists those the final value depends on, and `busy` those already
+    // being forced.
+    fn next(&self, blocked : Option<Ident>, demanded : &[Ident], busy : &[Ident]) -> Option<SuspAt>;
+
+    fn mark(&self) -> usize;
+
+    fn undo(&self, mark : usize);
+}
@@ -0,0 +1,282 @@
+use std::{collections::{HashMap, HashSet}, sync::Arc};
+
+use super::{env::Env, error::RuntimeError, lvar::LogicEnv, mterms::{MComputation, MValue}, search::Branch, senv::{SuspAt, SuspEnv}, step::Machine, store::{LogicStore, SuspStore}, unify::unify_vclos, value_type::ValueType, Ident, VClosure};
+
+// A first-order value with its unbound logic variables replaced by
+// `MValue::Var(i)`, numbered in order of first occurrence. Two calls
+// whose arguments are variants of each other share a canonical form.
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct Canonical {
+    vals : Vec<MValue>,
+    types : Vec<ValueType>,
+}
+
+enum NotCanonical {
+    Susp(SuspAt),
+    Thunk,
+    // a rational tree, which has no finite canonical form
+    Cyclic,
+}
+
+// `path` holds the roots of the bound variables whose bindings are being
+// made canonical, so that a cyclic term is caught when one is met again
+fn canonical_val(vclos : VClosure, lenv : &LogicEnv, senv : &SuspEnv, vars : &mut Vec<Ident>, path : &mut Vec<Ident>) -> Result<MValue, NotCanonical> {
+    let depth = path.len();
+    let val = canonical_head(vclos, lenv, senv, vars, path);
+    path.truncate(depth);
+    val
+}
+
+fn canonical_head(vclos : VClosure, lenv : &LogicEnv, senv : &SuspEnv, vars : &mut Vec<Ident>, path : &mut Vec<Ident>) -> Result<MValue, NotCanonical> {
+    let mut vclos = vclos;
+    loop {
+        vclos = match &vclos {
+            VClosure::Clos { val, env } => match &**val {
+                MValue::Var(i) => env.lookup(*i).expect("index undefined in env"),
+                _ => break,
+            },
+            VClosure::LogicVar { ident } => {
+                let root = lenv.root(*ident);
+                match lenv.lookup(root) {
+                    Some(_) if path.contains(&root) => return Err(NotCanonical::Cyclic),
+                    Some(binding) => { path.push(root); binding },
+                    None => break,
+                }
+            },
+            VClosure::Susp { ident } => senv.lookup(ident).map_err(NotCanonical::Susp)?,
+        }
+    }
+    match vclos {
+        VClosure::Clos { val, env } => {
+            let mut arg = |v : &Arc<MValue>| -> Result<Arc<MValue>, NotCanonical> {
+                Ok(canonical_val(VClosure::mk_clos(v, &env), lenv, senv, vars, path)?.into())
+            };
+            Ok(match &*val {
+                MValue::Zero => MValue::Zero,
+                MValue::Unit => MValue::Unit,
+                MValue::Nil => MValue::Nil,
+                MValue::Succ(v) => MValue::Succ(arg(v)?),
+                MValue::Inl(v) => MValue::Inl(arg(v)?),
+                MValue::Inr(v) => MValue::Inr(arg(v)?),
+                MValue::Cons(v, w) => MValue::Cons(arg(v)?, arg(w)?),
+                MValue::Pair(v, w) => MValue::Pair(arg(v)?, arg(w)?),
+                MValue::Thunk(_) => return Err(NotCanonical::Thunk),
+                MValue::Var(_) => unreachable!("indirections were followed"),
+            })
+        },
+        VClosure::LogicVar { ident } => {
+            let root = lenv.root(ident);
+            let i = match vars.iter().position(|v| *v == root) {
+                Some(i) => i,
+                None => { vars.push(root); vars.len() - 1 }
+            };
+            Ok(MValue::Var(i))
+        },
+        VClosure::Susp { .. } => unreachable!("suspensions were forced"),
+    }
+}
+
+fn canonical(vcloss : Vec<VClosure>, lenv : &LogicEnv, senv : &SuspEnv) -> Result<Canonical, NotCanonical> {
+    let mut vars = vec![];
+    let vals = vcloss.into_iter()
+        .map(|vclos| canonical_val(vclos, lenv, senv, &mut vars, &mut vec![]))
+        .collect::<Result<_, _>>()?;
+    let types = vars.into_iter().map(|v| lenv.get_type(v)).collect();
+    Ok(Canonical { vals, types })
+}
+
+impl Canonical {
+    // Closures of the canonical values over fresh logic variables of `lenv`
+    fn instantiate(&self, lenv : &mut LogicEnv) -> (Vec<VClosure>, Arc<Env>) {
+        let idents : Vec<Ident> = self.types.iter().map(|t| lenv.fresh(t.clone())).collect();
+        let env = idents.iter().rev().fold(Env::empty(), |env, ident| env.extend_lvar(*ident));
+        let vcloss = self.vals.iter().map(|v| VClosure::mk_clos(&v.clone().into(), &env)).collect();
+        (vcloss, env)
+    }
+}
+
+// The table a machine produces answers for, and the arguments of the call
+// in that machine's logic environment
+#[derive(Clone)]
+pub struct Producer {
+    pub table : usize,
+    pub args : Arc<Vec<VClosure>>,
+}
+
+struct Table<M> {
+    name : String,
+    arity : usize,
+    // each answer is the arguments followed by the result
+    answers : Vec<Canonical>,
+    seen : HashSet<Canonical>,
+    // machines suspended at a call to this table
+    consumers : Vec<M>,
+}
+
+// Answer tables of the tabled functions called during a search. The first
+// call to a tabled function with given arguments spawns a producer machine
+// that evaluates the body in isolation; that call and every later call with
+// variant arguments become consumers, which are resumed once with each
+// distinct answer the producer finds. Left-recursive calls therefore wait
+// for answers rather than looping, and a finite answer set terminates.
+pub struct Tables<M = Machine> {
+    tables : Vec<Table<M>>,
+    index : HashMap<(usize, Canonical), usize>,
+}
+
+impl<M : Branch> Tables<M> {
+
+    pub fn new() -> Tables<M> { Tables { tables : vec![], index : HashMap::new() } }
+
+    // Steps a machine whose computation is `MComputation::Table`
+    pub fn call(&mut self, m : M) -> Vec<M> {
+        let (id, name, arity) = match m.comp() {
+            MComputation::Table { id, name, arity, .. } => (*id, name.clone(), *arity),
+            _ => unreachable!("not a tabled call"),
+        };
+
+        let args = (0..arity).map(|i| m.env().lookup(i).expect("index undefined in env")).collect();
+        let key = match canonical(args, m.lenv(), m.senv()) {
+            Ok(key) => key,
+            Err(NotCanonical::Susp(a)) => return vec![m.force(a)],
+            // higher-order and cyclic arguments can't be compared, so evaluate the call directly
+            Err(NotCanonical::Thunk | NotCanonical::Cyclic) => return vec![m.enter()],
+        };
+
+        let mut ms = vec![];
+        let t = match self.index.get(&(id, key.clone())) {
+            Some(t) => *t,
+            None => {
+                let mut lenv = LogicEnv::new().with_occurs_check(m.lenv().occurs_check());
+                let (args, _) = key.instantiate(&mut lenv);
+                let env = args.iter().enumerate().fold(m.env().clone(), |env, (i, arg)| env.set(i, arg.clone()));
+                let t = self.tables.len();
+                self.tables.push(Table { name, arity, answers : vec![], seen : HashSet::new(), consumers : vec![] });
+                self.index.insert((id, key), t);
+                ms.push(m.spawn(env, lenv, Producer { table : t, args : args.into() }));
+                t
+            }
+        };
+
+        let table = &mut self.tables[t];
+        ms.extend(table.answers.iter().filter_map(|answer| resume(&m, table.arity, answer)));
+        table.consumers.push(m);
+        ms
+    }
+
+    // Records the answer of a finished producer, resuming the consumers if
+    // it is new. A result the table can't hold fails the producer's branch.
+    pub fn answer(&mut self, m : M) -> Result<Vec<M>, RuntimeError> {
+        let producer = m.producer().expect("not a producer");
+        let table = &mut self.tables[producer.table];
+        if !m.goals().is_empty() { return Err(RuntimeError::Table { name : table.name.clone(), reason : "a value waiting on a builtin call" }) }
+        let mut vcloss = (*producer.args).clone();
+        vcloss.push(m.result());
+        let answer = match canonical(vcloss, m.lenv(), m.senv()) {
+            Ok(answer) => answer,
+            Err(NotCanonical::Susp(_)) => return Err(RuntimeError::Table { name : table.name.clone(), reason : "a suspension that never ran" }),
+            Err(NotCanonical::Thunk) => return Err(RuntimeError::Table { name : table.name.clone(), reason : "a thunk" }),
+            Err(NotCanonical::Cyclic) => return Err(RuntimeError::Table { name : table.name.clone(), reason : "a cyclic term" }),
+        };
+
+        if !table.seen.insert(answer.clone()) { return Ok(vec![]) }
+        let ms = table.consumers.iter().filter_map(|c| resume(c, table.arity, &answer)).collect();
+        table.answers.push(answer);
+        Ok(ms)
+    }
+}
+
+// Continues a consumer with an answer, binding its arguments to the answer's
+fn resume<M : Branch>(consumer : &M, arity : usize, answer : &Canonical) -> Option<M> {
+    let mut lenv = consumer.lenv().clone();
+    let (vcloss, env) = answer.instantiate(&mut lenv);
+    for (i, vclos) in vcloss.into_iter().take(arity).enumerate() {
+        let arg = consumer.env().lookup(i).expect("index undefined in env");
+        unify_vclos(arg, vclos, &mut lenv, consumer.senv(), &mut |_, _| ()).ok()?;
+    }
+    Some(consumer.resume(answer.vals[arity].clone().into(), env, lenv))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{machine::{limits::Limits, output, search::Search, translate::translate}, parser};
+    use super::*;
+
+    const REACH : &str = "edge :: Nat -> Nat
+edge x = case x of
+      Z -> 1
+    | S n -> (case n of
+          Z -> 2
+        | S m -> (case m of
+              Z -> (0 <> 3)
+            | S k -> (0 =:= 1. 0))).
+
+table reach
+reach :: Nat -> Nat
+reach x = edge x <> (let y = reach x in edge y).
+";
+
+    fn answers(query : &str) -> Vec<String> {
+        let (comp, env) = translate(parser::parse(&(REACH.to_owned() + query)).unwrap()).unwrap();
+        let mut answers : Vec<String> = Search::new(Machine::new(comp, env), Limits::default())
+            .map(|m| match &*m.comp {
+                MComputation::Return(v) => output(v.clone(), m.env.clone(), None, &[], &m.lenv, &m.senv).unwrap(),
+                _ => unreachable!()
+            })
+            .collect();
+        answers.sort();
+        answers
+    }
+
+    #[test]
+    fn left_recursion_terminates() {
+        assert_eq!(answers("reach 0."), vec!["0", "1", "2", "3"]);
+    }
+
+    #[test]
+    fn variant_calls_share_a_table() {
+        let answers = answers("exists x :: Nat. (x, (reach x)).");
+        assert_eq!(answers.len(), 12);
+        assert!(answers.contains(&"(1, 3)".to_string()));
+    }
+
+    #[test]
+    fn redefinitions_have_their_own_tables() {
+        let src = "table f
+f x = 1.
+g x = f x.
+f x = 2.
+((g 0), (f 0)).";
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        let answers : Vec<String> = Search::new(Machine::new(comp, env), Limits::default())
+            .map(|m| m.answer(None, false).unwrap().to_string())
+            .collect();
+        assert_eq!(answers, ["(1, 2)"]);
+    }
+
+    #[test]
+    fn thunk_result_fails_branch() {
+        let src = "table f
+f :: Nat -> Nat -> Nat
+f x = \\y. y.
+
+(let g = f 0 in g 1) <> 2.";
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        let mut search = Search::new(Machine::new(comp, env), Limits::default());
+        assert_eq!(search.by_ref().count(), 1);
+        assert_eq!(search.failures()[0].error, RuntimeError::Table { name : "f".to_string(), reason : "a thunk" });
+    }
+
+    #[test]
+    fn cyclic_result_fails_branch() {
+        let src = "table f
+f :: Nat -> Nat
+f x = exists y :: Nat. y =:= S y. y.
+
+f 0 <> 1.";
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        let mut search = Search::new(Machine::new(comp, env).with_occurs_check(false), Limits::default());
+        assert_eq!(search.by_ref().count(), 1);
+        assert_eq!(search.failures()[0].error, RuntimeError::Table { name : "f".to_string(), reason : "a cyclic term" });
+    }
+}
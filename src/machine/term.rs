@@ -0,0 +1,438 @@
+use std::{collections::{HashMap, HashSet}, fmt::Display, sync::Arc};
+
+use super::{builtin::Goal, error::RuntimeError, mterms::MValue, store::{LogicStore, SuspStore}, value_type::ValueType, Ident, VClosure};
+
+// A first-order value with its variables substituted away, as printed in
+// answers. `Var(i)` is the logic variable named `_i`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Term {
+    Unit,
+    Zero,
+    Succ(Box<Term>),
+    Nil,
+    Cons(Box<Term>, Box<Term>),
+    Pair(Box<Term>, Box<Term>),
+    Inl(Box<Term>),
+    Inr(Box<Term>),
+    Var(usize),
+}
+
+impl Term {
+    fn is_list(&self) -> bool {
+        match self {
+            Term::Nil => true,
+            Term::Cons(_, t) => t.is_list(),
+            _ => false
+        }
+    }
+
+    pub fn nat(&self) -> Option<usize> {
+        let (mut n, mut t) = (0, self);
+        while let Term::Succ(u) = t { (n, t) = (n + 1, u) }
+        matches!(t, Term::Zero).then_some(n)
+    }
+
+    // Whether the term prints without spaces outside brackets
+    fn atomic(&self, ptype : Option<&ValueType>) -> bool {
+        match self {
+            Term::Succ(_) => self.nat().is_some(),
+            Term::Cons(_, _) => self.is_list(),
+            Term::Inl(_) | Term::Inr(_) => ptype == Some(&ValueType::bool()),
+            _ => true,
+        }
+    }
+}
+
+impl Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(n) = self.nat() { return write!(f, "{}", n) }
+        match self {
+            Term::Unit => write!(f, "()"),
+            Term::Zero => write!(f, "0"),
+            Term::Succ(t) => match **t {
+                Term::Succ(_) | Term::Cons(_, _) | Term::Inl(_) | Term::Inr(_) => write!(f, "S ({})", t),
+                _ => write!(f, "S {}", t),
+            },
+            Term::Nil | Term::Cons(_, _) => {
+                let mut xs = vec![];
+                let mut t = self;
+                let proper = self.is_list();
+                while let Term::Cons(x, rest) = t {
+                    xs.push(match **x {
+                        Term::Cons(_, _) if !proper && !x.is_list() => format!("({})", x),
+                        _ => x.to_string(),
+                    });
+                    t = rest
+                }
+                match t {
+                    Term::Nil => write!(f, "[{}]", xs.join(", ")),
+                    _ => write!(f, "{} : {}", xs.join(" : "), t),
+                }
+            },
+            Term::Pair(t, u) => write!(f, "({}, {})", t, u),
+            Term::Inl(t) => write!(f, "inl({})", t),
+            Term::Inr(t) => write!(f, "inr({})", t),
+            Term::Var(i) => write!(f, "_{}", i),
+        }
+    }
+}
+
+// A term printed according to its type, if known: booleans as `true` and
+// `false`, other sums by their constructors `Left` and `Right`, and nested
+// pairs as flat tuples. Without a type the term prints by its shape alone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Typed {
+    pub term : Term,
+    pub ptype : Option<ValueType>,
+}
+
+struct Show<'a>(&'a Term, Option<&'a ValueType>);
+
+impl Show<'_> {
+    fn arg(&self) -> String {
+        if self.0.atomic(self.1) { self.to_string() } else { format!("({})", self) }
+    }
+}
+
+impl Display for Show<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.0, self.1) {
+            (Term::Var(_), _) | (_, None) | (_, Some(ValueType::Nat)) => write!(f, "{}", self.0),
+            (Term::Inl(_), Some(t)) if *t == ValueType::bool() => write!(f, "true"),
+            (Term::Inr(_), Some(t)) if *t == ValueType::bool() => write!(f, "false"),
+            (Term::Inl(x), Some(ValueType::Sum(t, _))) => write!(f, "Left {}", Show(x, Some(t)).arg()),
+            (Term::Inr(x), Some(ValueType::Sum(_, t))) => write!(f, "Right {}", Show(x, Some(t)).arg()),
+            (Term::Nil | Term::Cons(_, _), Some(ValueType::List(t))) => {
+                let mut xs = vec![];
+                let mut rest = self.0;
+                while let Term::Cons(x, xs1) = rest {
+                    xs.push(x);
+                    rest = xs1
+                }
+                match rest {
+                    Term::Nil => {
+                        let xs : Vec<String> = xs.into_iter().map(|x| Show(x, Some(t)).to_string()).collect();
+                        write!(f, "[{}]", xs.join(", "))
+                    },
+                    _ => {
+                        let xs : Vec<String> = xs.into_iter().map(|x| Show(x, Some(t)).arg()).collect();
+                        write!(f, "{} : {}", xs.join(" : "), rest)
+                    },
+                }
+            },
+            (Term::Pair(_, _), Some(ValueType::Product(_, _))) => {
+                let mut xs = vec![];
+                let (mut rest, mut ptype) = (self.0, self.1);
+                while let (Term::Pair(x, y), Some(ValueType::Product(t, u))) = (rest, ptype) {
+                    xs.push(Show(x, Some(t)).to_string());
+                    (rest, ptype) = (y, Some(&**u));
+                }
+                xs.push(Show(rest, ptype).to_string());
+                write!(f, "({})", xs.join(", "))
+            },
+            // the type doesn't fit the term
+            _ => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl Display for Typed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", Show(&self.term, self.ptype.as_ref()))
+    }
+}
+
+// The closed value of an answer. Unbound variables, and under
+// rational-tree unification the variables bound to terms containing
+// themselves, are named `_0`, `_1`, ... in order of first occurrence, and
+// `equations` give the bindings of the cyclic ones. `bindings` are those
+// of the query's variables, when asked for, and `residuals` the builtin
+// calls still waiting for their arguments, which constrain the variables.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Answer {
+    pub bindings : Vec<(String, Typed)>,
+    pub value : Typed,
+    pub equations : Vec<(usize, Typed)>,
+    pub residuals : Vec<Residual>,
+}
+
+// A waiting builtin call, whose value will be `result`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Residual {
+    pub name : String,
+    pub args : Vec<Typed>,
+    pub result : Typed,
+}
+
+impl Display for Residual {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name)?;
+        for arg in &self.args {
+            if arg.term.atomic(arg.ptype.as_ref()) { write!(f, " {}", arg)? } else { write!(f, " ({})", arg)? }
+        }
+        write!(f, " = {}", self.result)
+    }
+}
+
+impl Display for Answer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.bindings.is_empty() {
+            let bindings : Vec<String> = self.bindings.iter().map(|(var, term)| format!("{} = {}", var, term)).collect();
+            write!(f, "{{{}}} ", bindings.join(", "))?;
+        }
+        write!(f, "{}", self.value)?;
+        let equations = self.equations.iter().map(|(name, term)| format!("_{} = {}", name, term));
+        let residuals = self.residuals.iter().map(|residual| residual.to_string());
+        for (i, constraint) in equations.chain(residuals).enumerate() {
+            write!(f, "{} {}", if i == 0 { " where" } else { "," }, constraint)?;
+        }
+        Ok(())
+    }
+}
+
+struct Closer<'a, L, S> {
+    lenv : &'a L,
+    senv : &'a S,
+    // the bound variables whose bindings are being closed
+    active : HashSet<Ident>,
+    names : HashMap<Ident, usize>,
+    equations : Vec<(usize, Typed)>,
+}
+
+// The work left in closing a value. Terms are built on a stack of results,
+// so that closing a deep value takes no deeper recursion.
+enum Task {
+    Close(VClosure),
+    // replaces the top result by its successor
+    Succ,
+    // replaces the top two results by their constructor
+    Cons,
+    Pair,
+    Inl,
+    Inr,
+    // the top result is the binding of the variable, which is named if it
+    // turned out cyclic
+    Bound(Ident),
+}
+
+impl<L : LogicStore, S : SuspStore> Closer<'_, L, S> {
+
+    fn close(&mut self, vclos : &VClosure) -> Result<Term, RuntimeError> {
+        let mut tasks = vec![Task::Close(vclos.clone())];
+        let mut results : Vec<Term> = vec![];
+        while let Some(task) = tasks.pop() {
+            let term = match task {
+                Task::Close(vclos) => match self.step(vclos, &mut tasks)? {
+                    Some(term) => term,
+                    None => continue,
+                },
+                Task::Succ => Term::Succ(Box::new(results.pop().unwrap())),
+                Task::Inl => Term::Inl(Box::new(results.pop().unwrap())),
+                Task::Inr => Term::Inr(Box::new(results.pop().unwrap())),
+                Task::Cons | Task::Pair => {
+                    let (u, t) = (results.pop().unwrap(), results.pop().unwrap());
+                    if matches!(task, Task::Cons) { Term::Cons(Box::new(t), Box::new(u)) } else { Term::Pair(Box::new(t), Box::new(u)) }
+                },
+                Task::Bound(root) => {
+                    self.active.remove(&root);
+                    let term = results.pop().unwrap();
+                    match self.names.get(&root) {
+                        Some(name) => {
+                            let name = *name;
+                            self.equations.push((name, Typed { term, ptype : Some(self.lenv.get_type(root)) }));
+                            Term::Var(name)
+                        },
+                        None => term,
+                    }
+                },
+            };
+            results.push(term)
+        }
+        Ok(results.pop().unwrap())
+    }
+
+    // The term of `vclos` if it has no parts left to close, or else the
+    // tasks closing and building them
+    fn step(&mut self, vclos : VClosure, tasks : &mut Vec<Task>) -> Result<Option<Term>, RuntimeError> {
+        let mut vclos = vclos;
+        let (val, env) = loop {
+            vclos = match vclos {
+                VClosure::Clos { val, env } => match &*val {
+                    MValue::Var(i) => env.lookup(*i).expect("index undefined in env"),
+                    _ => break (val, env),
+                },
+                VClosure::LogicVar { ident } => return Ok(self.lvar(ident, tasks)),
+                VClosure::Susp { ident } => self.senv.lookup(&ident).map_err(|_| RuntimeError::CloseSusp)?,
+            }
+        };
+        let close = |v : &Arc<MValue>| Task::Close(VClosure::mk_clos(v, &env));
+        // the left part goes on top, to be named first
+        let (build, parts) = match &*val {
+            MValue::Var(_) => unreachable!("indirections were followed"),
+            MValue::Unit => return Ok(Some(Term::Unit)),
+            MValue::Zero => return Ok(Some(Term::Zero)),
+            MValue::Nil => return Ok(Some(Term::Nil)),
+            MValue::Succ(v) => (Task::Succ, vec![close(v)]),
+            MValue::Inl(v) => (Task::Inl, vec![close(v)]),
+            MValue::Inr(v) => (Task::Inr, vec![close(v)]),
+            MValue::Cons(v, w) => (Task::Cons, vec![close(w), close(v)]),
+            MValue::Pair(v, w) => (Task::Pair, vec![close(w), close(v)]),
+            MValue::Thunk(_) => return Err(RuntimeError::CloseThunk),
+        };
+        tasks.push(build);
+        tasks.extend(parts);
+        Ok(None)
+    }
+
+    fn name(&mut self, root : Ident) -> Term {
+        let next = self.names.len();
+        Term::Var(*self.names.entry(root).or_insert(next))
+    }
+
+    // The term of a variable if it is unbound or met again inside its own
+    // binding, or else the tasks closing its binding
+    fn lvar(&mut self, ident : Ident, tasks : &mut Vec<Task>) -> Option<Term> {
+        let root = self.lenv.root(ident);
+        let binding = match self.lenv.lookup(root) {
+            Some(vclos) => vclos,
+            // a unit variable has only the one value
+            None if self.lenv.get_type(root) == ValueType::Unit => return Some(Term::Unit),
+            None => return Some(self.name(root)),
+        };
+        // a variable met again inside its own binding is cyclic
+        if self.names.contains_key(&root) || self.active.contains(&root) { return Some(self.name(root)) }
+        self.active.insert(root);
+        tasks.push(Task::Bound(root));
+        tasks.push(Task::Close(binding));
+        None
+    }
+}
+
+// Closes the value of a finished machine, of type `ptype` if known, the
+// given query variables and the calls of `goals`, naming their free and
+// cyclic variables alike
+pub fn close<'a, L : LogicStore, S : SuspStore>(vclos : &VClosure, ptype : Option<&ValueType>, bindings : &[(String, Ident)], goals : impl IntoIterator<Item = &'a Goal>, lenv : &L, senv : &S) -> Result<Answer, RuntimeError> {
+    let mut closer = Closer { lenv, senv, active : HashSet::new(), names : HashMap::new(), equations : vec![] };
+    let bindings = bindings.iter()
+        .map(|(var, ident)| Ok((var.clone(), Typed { term : closer.close(&VClosure::LogicVar { ident : *ident })?, ptype : Some(lenv.get_type(*ident)) })))
+        .collect::<Result<_, RuntimeError>>()?;
+    let value = Typed { term : closer.close(vclos)?, ptype : ptype.cloned() };
+    let residuals = goals.into_iter().map(|goal| {
+        let f = &goal.builtin;
+        let args = f.params.iter().enumerate()
+            .map(|(i, ptype)| {
+                let arg = goal.env.lookup(f.arity() - 1 - i).expect("builtin argument undefined in env");
+                Ok(Typed { term : closer.close(&arg)?, ptype : Some(ptype.clone()) })
+            })
+            .collect::<Result<_, RuntimeError>>()?;
+        let result = Typed { term : closer.close(&VClosure::LogicVar { ident : goal.result })?, ptype : Some(f.result.clone()) };
+        Ok(Residual { name : f.name.clone(), args, result })
+    }).collect::<Result<_, RuntimeError>>()?;
+    let mut equations = closer.equations;
+    equations.sort_by_key(|(name, _)| *name);
+    Ok(Answer { bindings, value, equations, residuals })
+}
+
+#[cfg(test)]
+mod tests {
+    use im::Vector;
+    use crate::{machine::{env::Env, limits::Limits, lvar::LogicEnv, mterms::MComputation, output, search::Search, senv::SuspEnv, step::Machine, translate::{query_type, translate}}, parser};
+    use super::*;
+
+    fn answers(src : &str, occurs_check : bool) -> Vec<String> {
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        Search::new(Machine::new(comp, env).with_occurs_check(occurs_check), Limits::default())
+            .map(|m| match &*m.comp {
+                MComputation::Return(v) => output(v.clone(), m.env.clone(), None, &[], &m.lenv, &m.senv).unwrap(),
+                _ => unreachable!()
+            })
+            .collect()
+    }
+
+    fn succ(t : Term) -> Term { Term::Succ(Box::new(t)) }
+    fn cons(t : Term, u : Term) -> Term { Term::Cons(Box::new(t), Box::new(u)) }
+
+    #[test]
+    fn display() {
+        assert_eq!(succ(succ(Term::Zero)).to_string(), "2");
+        assert_eq!(succ(succ(Term::Var(0))).to_string(), "S (S _0)");
+        assert_eq!(cons(Term::Zero, cons(succ(Term::Zero), Term::Nil)).to_string(), "[0, 1]");
+        assert_eq!(cons(Term::Zero, cons(succ(Term::Zero), Term::Var(1))).to_string(), "0 : 1 : _1");
+        let untyped = |term| Typed { term, ptype : None };
+        let answer = Answer { bindings : vec![], value : untyped(Term::Pair(Box::new(Term::Var(0)), Box::new(Term::Zero))), equations : vec![(0, untyped(succ(Term::Var(0))))], residuals : vec![] };
+        assert_eq!(answer.to_string(), "(_0, 0) where _0 = S _0");
+    }
+
+    #[test]
+    fn deep_bindings() {
+        // each variable is the successor of the next, so the answer is
+        // as deep as the chain
+        let mut lenv = LogicEnv::new();
+        let idents : Vec<Ident> = (0..=20_000).map(|_| lenv.fresh(ValueType::Nat)).collect();
+        for pair in idents.windows(2) {
+            lenv.set_vclos(pair[0], VClosure::Clos { val : MValue::Succ(MValue::Var(0).into()).into(), env : Env::empty().extend_lvar(pair[1]) });
+        }
+        lenv.set_vclos(idents[20_000], VClosure::Clos { val : MValue::Zero.into(), env : Env::empty() });
+        let answer = close(&VClosure::LogicVar { ident : idents[0] }, None, &[], &Vector::new(), &lenv, &SuspEnv::new()).unwrap();
+        assert_eq!(answer.value.term.nat(), Some(20_000));
+    }
+
+    #[test]
+    fn free_variables() {
+        assert_eq!(answers("exists x :: Nat. x.", true), vec!["_0"]);
+        assert_eq!(answers("exists xs :: [Nat]. exists y :: Nat. exists z :: Nat. exists w :: Nat. y =:= w. (((y : xs), (z : w : [])), (S y)).", true),
+            vec!["((_0 : _1, [_2, _0]), S _0)"]);
+    }
+
+    #[test]
+    fn free_and_cyclic_variables() {
+        assert_eq!(answers("exists x :: [Nat]. exists y :: Nat. x =:= y : x. (y, x).", false), vec!["(_0, _1) where _1 = _0 : _1"]);
+    }
+
+    #[test]
+    fn bindings() {
+        let (comp, env) = translate(parser::parse("exists a :: Nat. exists b :: [Nat]. exists c :: Nat. a =:= 3. b =:= c : []. (a, c).").unwrap()).unwrap();
+        let answers : Vec<String> = Search::new(Machine::new(comp, env), Limits::default())
+            .map(|m| match &*m.comp {
+                MComputation::Return(v) => {
+                    let bindings : Vec<_> = m.bindings.iter().cloned().collect();
+                    output(v.clone(), m.env.clone(), None, &bindings, &m.lenv, &m.senv).unwrap()
+                },
+                _ => unreachable!()
+            })
+            .collect();
+        assert_eq!(answers, vec!["{a = 3, b = [_0], c = _0} (3, _0)"]);
+    }
+
+    fn typed_answers(src : &str) -> Vec<String> {
+        let ast = parser::parse(src).unwrap();
+        let ptype = query_type(&ast);
+        let (comp, env) = translate(ast).unwrap();
+        Search::new(Machine::new(comp, env), Limits::default())
+            .map(|m| match &*m.comp {
+                MComputation::Return(v) => output(v.clone(), m.env.clone(), ptype.as_ref(), &[], &m.lenv, &m.senv).unwrap(),
+                _ => unreachable!()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn typed() {
+        assert_eq!(typed_answers("exists b :: Bool. exists x :: Nat. (b =:= true. (b, (x, [false]))) <> (b =:= false. (b, (1, []))).")[..],
+            ["(false, 1, [])", "(true, _0, [false])"]);
+        assert_eq!(typed_answers("swap :: (Nat * Bool) -> (Bool * Nat)
+swap p = (true, 3).
+
+exists p :: Nat * Bool. swap p.")[..], ["(true, 3)"]);
+    }
+
+    #[test]
+    fn typed_sums() {
+        let sum = ValueType::Sum(Box::new(ValueType::Nat), Box::new(ValueType::List(Box::new(ValueType::Nat))));
+        let typed = |term| Typed { term, ptype : Some(sum.clone()) };
+        assert_eq!(typed(Term::Inl(Box::new(succ(Term::Var(0))))).to_string(), "Left (S _0)");
+        assert_eq!(typed(Term::Inr(Box::new(cons(Term::Zero, Term::Nil)))).to_string(), "Right [0]");
+        assert_eq!(sum.to_string(), "Nat + [Nat]");
+        assert_eq!(ValueType::Product(Box::new(ValueType::bool()), Box::new(sum)).to_string(), "Bool * (Nat + [Nat])");
+    }
+}
@@ -0,0 +1,250 @@
+use std::io::Write;
+
+use super::{format::json_string, mterms::{MComputation, MValue}, term::Answer, Ident, VClosure};
+
+// Why a step has several outcomes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fork {
+    Choice,
+    // an unbound logic variable taking each shape of its type
+    Narrow(Shape),
+}
+
+// The type of a narrowed variable
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shape {
+    Nat,
+    List,
+    Sum,
+}
+
+impl Shape {
+    fn name(self) -> &'static str {
+        match self {
+            Shape::Nat => "nat",
+            Shape::List => "list",
+            Shape::Sum => "sum",
+        }
+    }
+}
+
+// How a unification ended
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unified {
+    Success,
+    // the terms have different shapes
+    Fail,
+    // a variable would contain itself
+    Occurs,
+}
+
+// Something a machine did while stepping
+#[derive(Clone, Copy)]
+pub enum Event<'a> {
+    // about to run `comp`
+    Step { comp : &'a MComputation },
+    Fork { cause : Fork, branches : usize },
+    Unify { outcome : Unified },
+    // a computation left to run when its value is needed
+    Suspend { susp : Ident },
+    // a suspension run because its value is needed
+    Force { susp : Ident },
+    Bind { lvar : Ident, value : &'a VClosure },
+    // a finished machine
+    Answer { answer : &'a Answer },
+}
+
+// Receives the events of a search, each with the number of steps the
+// machine had taken. Searches are generic in their tracer, so with
+// `NoTrace` the events compile away.
+pub trait Tracer {
+    // Whether events are wanted; those that are costly to build are only
+    // built if so
+    fn enabled(&self) -> bool { true }
+
+    fn event(&mut self, depth : usize, event : Event);
+}
+
+pub struct NoTrace;
+
+impl Tracer for NoTrace {
+    #[inline(always)]
+    fn enabled(&self) -> bool { false }
+
+    #[inline(always)]
+    fn event(&mut self, _ : usize, _ : Event) {}
+}
+
+impl Tracer for Box<dyn Tracer> {
+    fn enabled(&self) -> bool { (**self).enabled() }
+
+    fn event(&mut self, depth : usize, event : Event) { (**self).event(depth, event) }
+}
+
+impl<T : Tracer> Tracer for &mut T {
+    fn enabled(&self) -> bool { (**self).enabled() }
+
+    fn event(&mut self, depth : usize, event : Event) { (**self).event(depth, event) }
+}
+
+// Events go to the tracer if there is one
+impl<T : Tracer> Tracer for Option<T> {
+    fn enabled(&self) -> bool { self.as_ref().is_some_and(T::enabled) }
+
+    fn event(&mut self, depth : usize, event : Event) {
+        if let Some(tracer) = self { tracer.event(depth, event) }
+    }
+}
+
+// Both tracers see every event
+impl<A : Tracer, B : Tracer> Tracer for (A, B) {
+    fn enabled(&self) -> bool { self.0.enabled() || self.1.enabled() }
+
+    fn event(&mut self, depth : usize, event : Event) {
+        self.0.event(depth, event);
+        self.1.event(depth, event)
+    }
+}
+
+// One line per event, for people
+pub struct TextTracer<W> { out : W }
+
+impl<W : Write> TextTracer<W> {
+    pub fn new(out : W) -> TextTracer<W> { TextTracer { out } }
+}
+
+impl<W : Write> Tracer for TextTracer<W> {
+    fn event(&mut self, depth : usize, event : Event) {
+        let line = match event {
+            Event::Step { comp } => format!("step {}", head(comp)),
+            Event::Fork { cause : Fork::Choice, branches } => format!("fork {} ways on a choice", branches),
+            Event::Fork { cause : Fork::Narrow(shape), branches } => format!("fork {} ways narrowing a {}", branches, shape.name()),
+            Event::Unify { outcome : Unified::Success } => "unify succeeded".to_string(),
+            Event::Unify { outcome : Unified::Fail } => "unify failed".to_string(),
+            Event::Unify { outcome : Unified::Occurs } => "unify failed the occurs check".to_string(),
+            Event::Suspend { susp } => format!("suspend as {}", susp),
+            Event::Force { susp } => format!("force suspension {}", susp),
+            Event::Bind { lvar, value } => format!("bind ?{} := {}", lvar, show(value, false)),
+            Event::Answer { answer } => format!("answer {}", answer),
+        };
+        let _ = writeln!(self.out, "[{}] {}", depth, line);
+    }
+}
+
+// One JSON object per event and line
+pub struct JsonTracer<W> { out : W }
+
+impl<W : Write> JsonTracer<W> {
+    pub fn new(out : W) -> JsonTracer<W> { JsonTracer { out } }
+}
+
+impl<W : Write> Tracer for JsonTracer<W> {
+    fn event(&mut self, depth : usize, event : Event) {
+        let fields = match event {
+            Event::Step { comp } => format!("\"step\",\"comp\":\"{}\"", head(comp)),
+            Event::Fork { cause : Fork::Choice, branches } => format!("\"fork\",\"cause\":\"choice\",\"branches\":{}", branches),
+            Event::Fork { cause : Fork::Narrow(shape), branches } =>
+                format!("\"fork\",\"cause\":\"narrow\",\"shape\":\"{}\",\"branches\":{}", shape.name(), branches),
+            Event::Unify { outcome : Unified::Occurs } => "\"unify\",\"success\":false,\"occurs\":true".to_string(),
+            Event::Unify { outcome } => format!("\"unify\",\"success\":{}", outcome == Unified::Success),
+            Event::Suspend { susp } => format!("\"suspend\",\"susp\":{}", susp),
+            Event::Force { susp } => format!("\"force\",\"susp\":{}", susp),
+            Event::Bind { lvar, value } => format!("\"bind\",\"lvar\":{},\"value\":{}", lvar, json_string(&show(value, false))),
+            Event::Answer { answer } => format!("\"answer\",\"answer\":{}", json_string(&answer.to_string())),
+        };
+        let _ = writeln!(self.out, "{{\"depth\":{},\"event\":{}}}", depth, fields);
+    }
+}
+
+// The kind of computation a machine is about to run
+fn head(comp : &MComputation) -> &'static str {
+    match comp {
+        MComputation::Return(_) => "return",
+        MComputation::Bind { .. } => "bind",
+        MComputation::Force(_) => "force",
+        MComputation::Lambda { .. } => "lambda",
+        MComputation::App { .. } => "app",
+        MComputation::Choice(_) => "choice",
+        MComputation::Exists { .. } => "exists",
+        MComputation::Equate { .. } => "equate",
+        MComputation::Ifz { .. } => "ifz",
+        MComputation::Match { .. } => "match",
+        MComputation::Case { .. } => "case",
+        MComputation::Rec { .. } => "rec",
+        MComputation::Table { .. } => "table",
+        MComputation::Builtin(_) => "builtin",
+        MComputation::Code(_) => "code",
+    }
+}
+
+// A value with its variables looked up in its environment, logic
+// variables shown as `?i` and left unresolved
+pub(super) fn show(vclos : &VClosure, atom : bool) -> String {
+    let (val, env) = match vclos {
+        VClosure::LogicVar { ident } => return format!("?{}", ident),
+        VClosure::Susp { ident } => return format!("<suspension {}>", ident),
+        VClosure::Clos { val, env } => (val, env),
+    };
+    let sub = |v, atom| show(&VClosure::mk_clos(v, env), atom);
+    let parens = |s : String| if atom { format!("({})", s) } else { s };
+    match &**val {
+        MValue::Var(i) => show(&env.lookup(*i).expect("index undefined in env"), atom),
+        MValue::Unit => "()".to_string(),
+        MValue::Zero => "0".to_string(),
+        MValue::Nil => "[]".to_string(),
+        MValue::Succ(v) => match sub(v, true) {
+            n if n.parse::<usize>().is_ok() => (n.parse::<usize>().unwrap() + 1).to_string(),
+            v => parens(format!("S {}", v)),
+        },
+        MValue::Cons(v, w) => parens(format!("{} : {}", sub(v, true), sub(w, false))),
+        MValue::Pair(v, w) => format!("({}, {})", sub(v, false), sub(w, false)),
+        MValue::Inl(v) => parens(format!("inl {}", sub(v, true))),
+        MValue::Inr(v) => parens(format!("inr {}", sub(v, true))),
+        MValue::Thunk(_) => "<thunk>".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{machine::{limits::Limits, search::Search, step::Machine, translate::translate}, parser};
+    use super::*;
+
+    // A writer whose output the test can read back
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf : &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+        fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+    }
+
+    fn trace(src : &str, json : bool) -> Vec<String> {
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        let out = Shared::default();
+        let tracer : Box<dyn Tracer> = if json { Box::new(JsonTracer::new(out.clone())) } else { Box::new(TextTracer::new(out.clone())) };
+        Search::new(Machine::new(comp, env), Limits::default()).with_tracer(tracer).for_each(drop);
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        out.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn events() {
+        let lines = trace("exists x :: Nat. x =:= S (1 <> 2). x.", false);
+        assert!(lines.contains(&"[0] step exists".to_string()), "{:?}", lines);
+        assert!(lines.iter().any(|l| l.ends_with("fork 2 ways on a choice")), "{:?}", lines);
+        assert!(lines.contains(&"[3] force suspension 0".to_string()), "{:?}", lines);
+        assert!(lines.contains(&"[9] bind ?0 := S <suspension 1>".to_string()), "{:?}", lines);
+        assert_eq!(lines.iter().filter(|l| l.contains("unify succeeded")).count(), 2);
+        assert_eq!(lines[lines.len() - 3..], ["[11] answer 2", "[10] step return", "[11] answer 3"]);
+    }
+
+    #[test]
+    fn narrowing() {
+        let lines = trace("exists n :: Nat. case n of Z -> 0 | S m -> m.", true);
+        assert!(lines.contains(&r#"{"depth":2,"event":"fork","cause":"narrow","shape":"nat","branches":2}"#.to_string()), "{:?}", lines);
+        assert!(lines.contains(&r#"{"depth":2,"event":"bind","lvar":0,"value":"0"}"#.to_string()), "{:?}", lines);
+        assert!(lines.contains(&r#"{"depth":2,"event":"bind","lvar":0,"value":"S ?1"}"#.to_string()), "{:?}", lines);
+    }
+}
@@ -0,0 +1,192 @@
+use std::{cell::RefCell, collections::BTreeSet, rc::Rc, sync::Arc};
+
+use super::{env::Env, mterms::{MComputation, MValue}, senv::{CClosure, Schedule, SuspAt}, store::{LogicStore, SuspStore}, value_type::ValueType, Ident, VClosure};
+
+// Mutable stores in the style of the WAM: every machine of a depth-first
+// search shares one store, and each change is logged on a trail so that
+// backtracking to a choice point can undo the changes made since.
+
+struct LogicVar {
+    ptype : ValueType,
+    binding : Option<VClosure>,
+    parent : Option<Ident>,
+    rank : usize,
+}
+
+enum LogicChange {
+    Fresh,
+    Bind(Ident),
+    // the first variable was linked under the second, whose rank may have grown
+    Link(Ident, Ident, bool),
+}
+
+struct LogicTrail {
+    vars : Vec<LogicVar>,
+    trail : Vec<LogicChange>,
+    occurs_check : bool,
+}
+
+#[derive(Clone)]
+pub struct TrailLogicEnv {
+    store : Rc<RefCell<LogicTrail>>
+}
+
+impl LogicStore for TrailLogicEnv {
+
+    fn new() -> TrailLogicEnv {
+        TrailLogicEnv { store : Rc::new(RefCell::new(LogicTrail { vars : vec![], trail : vec![], occurs_check : true })) }
+    }
+
+    fn size(&self) -> usize { self.store.borrow().vars.len() }
+
+    fn with_occurs_check(self, occurs_check : bool) -> TrailLogicEnv {
+        self.store.borrow_mut().occurs_check = occurs_check;
+        self
+    }
+
+    fn occurs_check(&self) -> bool { self.store.borrow().occurs_check }
+
+    fn fresh(&mut self, ptype : ValueType) -> Ident {
+        let mut store = self.store.borrow_mut();
+        store.vars.push(LogicVar { ptype, binding : None, parent : None, rank : 0 });
+        store.trail.push(LogicChange::Fresh);
+        store.vars.len() - 1
+    }
+
+    fn lookup(&self, ident : Ident) -> Option<VClosure> {
+        let root = self.root(ident);
+        self.store.borrow().vars[root].binding.clone()
+    }
+
+    // compressing would need undoing too, so union by rank alone keeps the
+    // paths short
+    fn resolve(&mut self, ident : Ident) -> Option<VClosure> { self.lookup(ident) }
+
+    fn root(&self, mut ident : Ident) -> Ident {
+        let store = self.store.borrow();
+        while let Some(p) = store.vars[ident].parent { ident = p }
+        ident
+    }
+
+    fn set_vclos(&mut self, ident : Ident, vclos : VClosure) {
+        let root = self.root(ident);
+        let mut store = self.store.borrow_mut();
+        store.vars[root].binding = Some(vclos);
+        store.trail.push(LogicChange::Bind(root));
+    }
+
+    fn get_type(&self, ident : Ident) -> ValueType {
+        self.store.borrow().vars[ident].ptype.clone()
+    }
+
+    fn identify(&mut self, ident1 : Ident, ident2 : Ident) {
+        let (a, b) = (self.root(ident1), self.root(ident2));
+        if a == b { return }
+        let mut store = self.store.borrow_mut();
+        let (child, parent) = if store.vars[a].rank > store.vars[b].rank { (b, a) } else { (a, b) };
+        let grows = store.vars[child].rank == store.vars[parent].rank;
+        store.vars[child].parent = Some(parent);
+        if grows { store.vars[parent].rank += 1 }
+        store.trail.push(LogicChange::Link(child, parent, grows));
+    }
+
+    fn mark(&self) -> usize { self.store.borrow().trail.len() }
+
+    fn undo(&self, mark : usize) {
+        let mut store = self.store.borrow_mut();
+        while store.trail.len() > mark {
+            match store.trail.pop().unwrap() {
+                LogicChange::Fresh => { store.vars.pop(); },
+                LogicChange::Bind(ident) => store.vars[ident].binding = None,
+                LogicChange::Link(child, parent, grew) => {
+                    store.vars[child].parent = None;
+                    if grew { store.vars[parent].rank -= 1 }
+                }
+            }
+        }
+    }
+}
+
+enum SuspChange {
+    Fresh,
+    Set(Ident, CClosure),
+}
+
+struct SuspTrail {
+    susps : Vec<Result<VClosure, CClosure>>,
+    pending : BTreeSet<Ident>,
+    schedule : Schedule,
+    trail : Vec<SuspChange>,
+}
+
+#[derive(Clone)]
+pub struct TrailSuspEnv {
+    store : Rc<RefCell<SuspTrail>>
+}
+
+impl SuspStore for TrailSuspEnv {
+
+    fn new() -> TrailSuspEnv {
+        let store = SuspTrail { susps : vec![], pending : BTreeSet::new(), schedule : Schedule::default(), trail : vec![] };
+        TrailSuspEnv { store : Rc::new(RefCell::new(store)) }
+    }
+
+    fn size(&self) -> usize { self.store.borrow().susps.len() }
+
+    fn with_schedule(self, schedule : Schedule) -> TrailSuspEnv {
+        self.store.borrow_mut().schedule = schedule;
+        self
+    }
+
+    fn schedule(&self) -> Schedule { self.store.borrow().schedule }
+
+    fn fresh(&mut self, comp : &Arc<MComputation>, env : &Arc<Env>) -> Ident {
+        let mut store = self.store.borrow_mut();
+        let ident = store.susps.len();
+        store.susps.push(Err((comp.clone(), env.clone())));
+        store.pending.insert(ident);
+        store.trail.push(SuspChange::Fresh);
+        ident
+    }
+
+    fn lookup(&self, ident : &Ident) -> Result<VClosure, SuspAt> {
+        match &self.store.borrow().susps[*ident] {
+            Ok(vclos) => Ok(vclos.clone()),
+            Err((comp, env)) => Err(SuspAt { ident : *ident, comp : comp.clone(), env : env.clone() })
+        }
+    }
+
+    fn set(&mut self, ident : &Ident, val : &Arc<MValue>, env : &Arc<Env>) {
+        let mut store = self.store.borrow_mut();
+        let old = std::mem::replace(&mut store.susps[*ident], Ok(VClosure::mk_clos(val, env)));
+        store.pending.remove(ident);
+        if let Err(cclos) = old { store.trail.push(SuspChange::Set(*ident, cclos)) }
+    }
+
+    fn next(&self, blocked : Option<Ident>, demanded : &[Ident], busy : &[Ident]) -> Option<SuspAt> {
+        let ident = {
+            let store = self.store.borrow();
+            store.schedule.pick(store.pending.iter(), blocked, demanded, busy)?
+        };
+        self.lookup(&ident).err()
+    }
+
+    fn mark(&self) -> usize { self.store.borrow().trail.len() }
+
+    fn undo(&self, mark : usize) {
+        let mut store = self.store.borrow_mut();
+        while store.trail.len() > mark {
+            match store.trail.pop().unwrap() {
+                SuspChange::Fresh => {
+                    store.susps.pop();
+                    let ident = store.susps.len();
+                    store.pending.remove(&ident);
+                },
+                SuspChange::Set(ident, cclos) => {
+                    store.susps[ident] = Err(cclos);
+                    store.pending.insert(ident);
+                }
+            }
+        }
+    }
+}
@@ -1,97 +1,334 @@
-use std::{collections::HashMap, rc::Rc};
-use crate::{machine::value_type::ValueType, parser::{arg::{self, Arg}, bexpr::BExpr, cases::CasesType, decl::Decl, expr::Expr, stm::Stm, r#type::Type}};
-use super::{mterms::{MComputation, MValue}, Env, VClosure};
+use std::{collections::HashMap, fmt::Display, sync::Arc};
+use crate::{machine::value_type::ValueType, parser::{arg::{self, Arg}, bexpr::BExpr, cases::CasesType, decl::Decl, expr::Expr, format::print_type, stm::Stm, r#type::Type}};
+use super::{builtin::Builtin, mterms::{MComputation, MValue}, Env, VClosure};
 
 type Idx = usize;
-struct TEnv { env : Vec<String> } 
+// `query` is set while translating the query, whose existential variables
+// keep their names. The first error is kept in `error`, a variable found
+// unbound being translated as `#0` and an unsupported construct as a
+// failure, so that translation can finish.
+#[derive(Clone)]
+struct TEnv { env : Vec<String>, query : bool, error : Option<TranslateError> }
 
 impl TEnv {
-    fn new() -> TEnv { TEnv { env: vec![] } }
-    fn find(&self, v : &String) -> usize {
-        self.env.iter().rev().position(|x| x == v).expect(&("Variable ".to_owned() + v + " not found in environment"))
+    fn new() -> TEnv { TEnv { env: vec![], query : false, error : None } }
+    fn find(&mut self, v : &String) -> usize {
+        match self.env.iter().rev().position(|x| x == v) {
+            Some(i) => i,
+            None => { self.error.get_or_insert_with(|| Unbound(v.clone()).into()); 0 },
+        }
+    }
+    // Records `what` as unsupported, returning what stands for it
+    fn unsupported(&mut self, what : String) -> MComputation {
+        self.error.get_or_insert(TranslateError::Unsupported(what));
+        MComputation::Choice(vec![])
+    }
+    fn check(&mut self) -> Result<(), TranslateError> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
     }
-    fn bind(&mut self, v : &String) {
-        self.env.push(v.clone())
+    fn bind(&mut self, v : &str) {
+        self.env.push(v.to_string())
     }
     fn unbind(&mut self) {
         self.env.pop();
     }
-    fn to_string(&self) -> String {
-        "[ ".to_owned() + &self.env.join(" ") + " ]"
+}
+
+// A variable used where nothing of its name is in scope
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unbound(pub String);
+
+impl Display for Unbound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Variable {} not found in environment", self.0)
     }
 }
 
-pub fn translate(ast: Vec<Decl>) -> (MComputation, Rc<Env>) {
-    
-    let mut env = Env::empty();
-    let mut tenv = TEnv::new();
-    let mut main = None;
-
-    ast.into_iter()
-        .for_each(|decl| match decl {
-            Decl::FuncType { name: _, r#type: _ } => (),
-            Decl::Func { name, args, body } => {
-                let result : Rc<MValue> = translate_func(&name, args, body, &mut tenv).into();
-                // println!("[DEBUG] definition: {} = {}", name, *result);
-                tenv.bind(&name);
-                env = env.extend_val(result.clone(), env.clone())
-            },
-            Decl::Stm(stm) => {
-                let stmt = translate_stm(stm, &mut tenv);
-                // println!("[DEBUG] final stmt : {}", stmt);
-                // println!("[DEBUG] in env : {:?}", tenv.to_string());
-                main = Some(stmt)
+// Why a program can't be translated
+#[derive(Clone, Debug, PartialEq)]
+pub enum TranslateError {
+    Unbound(Unbound),
+    // a construct the parser accepts but the machine can't yet run, such
+    // as "an if statement"
+    Unsupported(String),
+    // the program has no statement to run as its query
+    NoQuery,
+}
+
+impl From<Unbound> for TranslateError {
+    fn from(e : Unbound) -> TranslateError { TranslateError::Unbound(e) }
+}
+
+impl Display for TranslateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TranslateError::Unbound(e) => write!(f, "{}", e),
+            TranslateError::Unsupported(what) => write!(f, "Translating {} isn't supported yet", what),
+            TranslateError::NoQuery => write!(f, "The program has no query."),
+        }
+    }
+}
+
+// The program's last statement, translated as its query, and its functions
+pub fn translate(ast: Vec<Decl>) -> Result<(MComputation, Arc<Env>), TranslateError> {
+    let mut globals = Globals::new();
+    let main = globals.define(ast)?.pop().ok_or(TranslateError::NoQuery)?;
+    Ok((globals.query(main)?, globals.env()))
+}
+
+// The translated functions of a program, which grows by batches of
+// declarations, and against which queries are translated
+#[derive(Clone)]
+pub struct Globals {
+    env : Arc<Env>,
+    tenv : TEnv,
+    // the functions declared tabled
+    tabled : Vec<String>,
+    // the number of tabled definitions so far, each of which has its own
+    // table
+    tables : usize,
+    // the declared types of the functions
+    types : Vec<Decl>,
+}
+
+impl Default for Globals {
+    fn default() -> Globals { Globals::new() }
+}
+
+impl Globals {
+
+    pub fn new() -> Globals {
+        Globals { env : Env::empty(), tenv : TEnv::new(), tabled : vec![], tables : 0, types : vec![] }
+    }
+
+    // Translates the functions among `decls`, returning its statements. A
+    // function defined again shadows the old definition for what follows.
+    // After an error the functions are defined part-way.
+    pub fn define(&mut self, decls : Vec<Decl>) -> Result<Vec<Stm>, TranslateError> {
+        self.tabled.extend(decls.iter()
+            .filter_map(|decl| match decl {
+                Decl::Table { name } => Some(name.clone()),
+                _ => None
+            }));
+
+        let mut stms = vec![];
+        for decl in decls {
+            match decl {
+                Decl::FuncType { .. } => self.types.push(decl),
+                Decl::Table { name: _ } => (),
+                Decl::Func { name, args, body } => {
+                    let table = self.tabled.contains(&name).then(|| {
+                        self.tables += 1;
+                        self.tables - 1
+                    });
+                    let result : Arc<MValue> = translate_func(&name, args, body, table, &mut self.tenv).into();
+                    self.tenv.check()?;
+                    self.tenv.bind(&name);
+                    self.env = self.env.extend_val(result.clone(), self.env.clone())
+                },
+                Decl::Stm(stm) => stms.push(stm),
             }
-        });
-    (main.expect("empty program"), env)
+        }
+        Ok(stms)
+    }
+
+    // Defines the host function `builtin`, declared to have type `r#type`
+    pub fn builtin(&mut self, builtin : Builtin, r#type : Type) {
+        self.types.push(Decl::FuncType { name : builtin.name.clone(), r#type });
+        self.tenv.bind(&builtin.name);
+        let result : Arc<MValue> = function(builtin.arity(), MComputation::Builtin(builtin.into())).into();
+        self.env = self.env.extend_val(result, self.env.clone())
+    }
+
+    pub fn query(&self, stm : Stm) -> Result<MComputation, TranslateError> {
+        let mut tenv = TEnv { query : true, ..self.tenv.clone() };
+        let comp = translate_stm(stm, &mut tenv);
+        tenv.check()?;
+        Ok(comp)
+    }
+
+    // The call of the function `name` on the closed value `arg`
+    pub fn call(&self, name : &str, arg : MValue) -> Result<MComputation, Unbound> {
+        let i = self.tenv.env.iter().rev().position(|x| x == name).ok_or_else(|| Unbound(name.to_string()))?;
+        Ok(MComputation::App { op : MComputation::Force(MValue::Var(i).into()).into(), arg : arg.into() })
+    }
+
+    // The declared type of the function `name`, if any
+    pub fn function_type(&self, name : &str) -> Option<&Type> {
+        self.types.iter().rev().find_map(|decl| match decl {
+            Decl::FuncType { name : f, r#type } if f == name => Some(r#type),
+            _ => None,
+        })
+    }
+
+    // The type of the query's answers, see `query_type`
+    pub fn query_type(&self, stm : &Stm) -> Option<ValueType> {
+        let mut ast = self.types.clone();
+        ast.push(Decl::Stm(stm.clone()));
+        query_type(&ast)
+    }
+
+    pub fn env(&self) -> Arc<Env> { self.env.clone() }
+
+    // The names of the functions, oldest first
+    pub fn names(&self) -> &[String] { &self.tenv.env }
 }
 
-fn translate_func(name : &String, args: Vec<Arg>, body: Stm, env : &mut TEnv) -> MValue {
+// The names of the functions of the global environment, oldest first
+pub fn globals(ast : &[Decl]) -> Vec<String> {
+    ast.iter()
+        .filter_map(|decl| match decl {
+            Decl::Func { name, .. } => Some(name.clone()),
+            _ => None
+        }).collect()
+}
+
+// The type of the query's answers, as far as it follows from the declared
+// types of functions and existential variables
+pub fn query_type(ast : &[Decl]) -> Option<ValueType> {
+    let mut env : Vec<(String, Option<Type>)> = ast.iter()
+        .filter_map(|decl| match decl {
+            Decl::FuncType { name, r#type } => Some((name.clone(), Some(r#type.clone()))),
+            _ => None
+        }).collect();
+    ast.iter().rev()
+        .find_map(|decl| match decl {
+            Decl::Stm(stm) => Some(stm),
+            _ => None
+        })
+        .and_then(|stm| stm_type(stm, &mut env))
+        .as_ref()
+        .and_then(value_type)
+}
+
+// The value type `ptype` names, if it is first-order
+pub fn value_type(ptype : &Type) -> Option<ValueType> {
+    match ptype {
+        Type::Ident(s) if s == "Nat" => Some(ValueType::Nat),
+        Type::Ident(s) if s == "Bool" => Some(ValueType::bool()),
+        Type::List(t) => Some(ValueType::List(Box::new(value_type(t)?))),
+        Type::Product(t1, t2) => Some(ValueType::Product(Box::new(value_type(t1)?), Box::new(value_type(t2)?))),
+        _ => None
+    }
+}
+
+fn bound_type(var : &str, ptype : Option<Type>, body : impl FnOnce(&mut Vec<(String, Option<Type>)>) -> Option<Type>, env : &mut Vec<(String, Option<Type>)>) -> Option<Type> {
+    env.push((var.to_string(), ptype));
+    let result = body(env);
+    env.pop();
+    result
+}
+
+fn stm_type(stm : &Stm, env : &mut Vec<(String, Option<Type>)>) -> Option<Type> {
+    match stm {
+        Stm::If { then, r#else, .. } => stm_type(then, env).or_else(|| stm_type(r#else, env)),
+        Stm::Let { var, val, body } => {
+            let ptype = stm_type(val, env);
+            bound_type(var, ptype, |env| stm_type(body, env), env)
+        },
+        Stm::Exists { var, r#type, body } => bound_type(var, Some(r#type.clone()), |env| stm_type(body, env), env),
+        Stm::Equate { body, .. } => stm_type(body, env),
+        Stm::Choice(exprs) => exprs.iter().find_map(|e| expr_type(e, env)),
+        Stm::Case { expr, cases } => match cases.r#type.as_ref()? {
+            CasesType::Nat => {
+                let nat_case = cases.nat_case.as_ref()?;
+                nat_case.zk.as_ref().and_then(|zk| expr_type(zk, env)).or_else(|| {
+                    let succ_case = nat_case.sk.as_ref()?;
+                    bound_type(&succ_case.var, Some(Type::Ident("Nat".to_string())), |env| expr_type(&succ_case.expr, env), env)
+                })
+            },
+            CasesType::List => {
+                let list_case = cases.list_case.as_ref()?;
+                list_case.nilk.as_ref().and_then(|nilk| expr_type(nilk, env)).or_else(|| {
+                    let cons_case = list_case.consk.as_ref()?;
+                    let elem = match expr_type(expr, env) {
+                        Some(Type::List(t)) => Some(*t),
+                        _ => None
+                    };
+                    let list = elem.clone().map(|t| Type::List(Box::new(t)));
+                    bound_type(&cons_case.x, elem, |env| bound_type(&cons_case.xs, list, |env| expr_type(&cons_case.expr, env), env), env)
+                })
+            },
+        },
+        Stm::Expr(e) => expr_type(e, env),
+    }
+}
+
+fn expr_type(expr : &Expr, env : &mut Vec<(String, Option<Type>)>) -> Option<Type> {
+    match expr {
+        Expr::Zero | Expr::Succ(_) | Expr::Nat(_) => Some(Type::Ident("Nat".to_string())),
+        Expr::Bool(_) | Expr::BExpr(_) => Some(Type::Ident("Bool".to_string())),
+        Expr::Nil | Expr::Lambda(_, _) => None,
+        Expr::Cons(x, xs) => expr_type(x, env).map(|t| Type::List(Box::new(t))).or_else(|| expr_type(xs, env)),
+        Expr::List(elems) => elems.iter().find_map(|e| expr_type(e, env)).map(|t| Type::List(Box::new(t))),
+        Expr::Pair(lhs, rhs) => Some(Type::Product(Box::new(expr_type(lhs, env)?), Box::new(expr_type(rhs, env)?))),
+        Expr::Ident(s) => env.iter().rev().find(|(v, _)| v == s)?.1.clone(),
+        Expr::App(op, _) => match expr_type(op, env)? {
+            Type::Arrow(_, result) => Some(*result),
+            _ => None
+        },
+        Expr::Stm(s) => stm_type(s, env),
+    }
+}
+
+fn translate_func(name : &str, args: Vec<Arg>, body: Stm, table : Option<usize>, env : &mut TEnv) -> MValue {
     
     env.bind(name);
 
-    let mut vars : Vec<String> = args.iter().map(|arg| match arg {
+    let vars : Vec<String> = args.iter().map(|arg| match arg {
         Arg::Ident(var) => var.clone(),
-        _ => todo!()
+        Arg::Pair(_, _) => "_pair".to_string(),
     }).collect();
     
     let arg_no = vars.len();
     
     vars.iter().for_each(|s| env.bind(s));
-    let mbody = translate_stm(body, env);
+    let mut mbody = match args.iter().any(|arg| matches!(arg, Arg::Pair(_, _))) {
+        true => env.unsupported("a pair argument".to_string()),
+        false => translate_stm(body, env),
+    };
     vars.iter().for_each(|s| env.unbind());
+
+    if let Some(id) = table {
+        mbody = MComputation::Table { id, name : name.to_string(), arity : arg_no, body : mbody.into() };
+    }
     
     env.unbind();
-    
-    if arg_no > 0 {
-        let mut c : MComputation = MComputation::Lambda { body : mbody.into()}.into();
-        while vars.len() > 1 {
-            c = MComputation::Lambda { body : MComputation::Return(MValue::Thunk(c.into()).into()).into() }.into();
-            vars.pop();
+
+    function(arg_no, mbody)
+}
+
+// The curried function of `arity` arguments computing `body`, which has
+// the arguments innermost in its environment and the function itself next
+fn function(arity : usize, body : MComputation) -> MValue {
+    let mut c = body;
+    if arity > 0 {
+        c = MComputation::Lambda { body : c.into() };
+        for _ in 1..arity {
+            c = MComputation::Lambda { body : MComputation::Return(MValue::Thunk(c.into()).into()).into() };
         }
-        MValue::Thunk(MComputation::Rec { body: c.into() }.into())
-    }
-    else {
-        MValue::Thunk(MComputation::Rec { body: mbody.into() }.into())
     }
+    MValue::Thunk(MComputation::Rec { body: c.into() }.into())
 }
 
-fn translate_vtype(ptype : Type) -> ValueType { 
+// The type of an existential variable, if it can have it
+fn translate_vtype(ptype : &Type) -> Option<ValueType> {
     match ptype {
-        Type::Arrow(_, _) => panic!("don't translate thunks"),
-        Type::Ident(s) => 
-            if s == "Nat" { ValueType::Nat } else { todo!() },
-        Type::List(t) => ValueType::List(Box::new(translate_vtype(*t))),
-        Type::Product(t1, t2) => ValueType::Product(Box::new(translate_vtype(*t1)), Box::new(translate_vtype(*t2)))
+        Type::Ident(s) if s == "Nat" => Some(ValueType::Nat),
+        Type::Ident(s) if s == "Bool" => Some(ValueType::bool()),
+        Type::Ident(_) | Type::Arrow(_, _) => None,
+        Type::List(t) => Some(ValueType::List(Box::new(translate_vtype(t)?))),
+        Type::Product(t1, t2) => Some(ValueType::Product(Box::new(translate_vtype(t1)?), Box::new(translate_vtype(t2)?)))
     }
 }
 
 fn translate_stm(stm: Stm, env : &mut TEnv) -> MComputation {
     match stm {
-        Stm::If { cond, then, r#else } => MComputation::Bind {
-            comp : translate_stm(*cond, env).into(),
-            cont : todo!() // need sums to complete this
-        },
+        // needs sums to complete this
+        Stm::If { .. } => env.unsupported("an if statement".to_string()),
         Stm::Let { var, val, body } => {
             let comp = translate_stm(*val, env).into();
             env.bind(&var);
@@ -100,17 +337,21 @@ fn translate_stm(stm: Stm, env : &mut TEnv) -> MComputation {
             MComputation::Bind { comp, cont }
         },
         Stm::Exists { var, r#type, body } => {
+            let ptype = match translate_vtype(&r#type) {
+                Some(ptype) => ptype,
+                None => return env.unsupported(format!("the type {} of the variable {}", print_type(&r#type), var)),
+            };
             env.bind(&var);
-            let body: Rc<MComputation> = translate_stm(*body, env).into();
+            let body: Arc<MComputation> = translate_stm(*body, env).into();
             env.unbind();
-            let ptype = translate_vtype(r#type);
-            MComputation::Exists { ptype, body: body }
+            let name = env.query.then_some(var);
+            MComputation::Exists { ptype, name, body }
         },
         Stm::Equate { lhs, rhs, body } => {
             let lhs_comp = translate_expr(lhs, env).into();
-            env.bind(&"_foo".to_string());
+            env.bind("_foo");
             let rhs_comp = translate_expr(rhs, env).into();
-            env.bind(&"_foo2".to_string());
+            env.bind("_foo2");
             let body_comp = translate_stm(*body, env).into();
             env.unbind();
             env.unbind();
@@ -124,35 +365,34 @@ fn translate_stm(stm: Stm, env : &mut TEnv) -> MComputation {
                         body : body_comp
                     }.into()
                 }.into()
-            }.into()
+            }
         },
         Stm::Choice(exprs) => MComputation::Choice(
             exprs.into_iter()
                 .map(|e| translate_expr(e, env).into()).collect()
         ),
         Stm::Case { expr, cases } => {
-            env.bind(&"_foo".to_string());
-            let cont = match cases.r#type.unwrap() {
-                CasesType::Nat => {
-                    let nat_case = cases.nat_case.unwrap();
-                    let zk = translate_expr(nat_case.zk.unwrap(), env).into();
-                    let succ_case = nat_case.sk.unwrap();
+            env.bind("_foo");
+            let nat_case = cases.nat_case.and_then(|case| case.zk.zip(case.sk));
+            let list_case = cases.list_case.and_then(|case| case.nilk.zip(case.consk));
+            let cont = match (cases.r#type, nat_case, list_case) {
+                (Some(CasesType::Nat), Some((zk, succ_case)), _) => {
+                    let zk = translate_expr(zk, env).into();
                     env.bind(&succ_case.var);
                     let sk = translate_expr(succ_case.expr, env).into();
                     env.unbind();
                     MComputation::Ifz { num: MValue::Var(0).into(), zk, sk }
                 },
-                CasesType::List => {
-                    let list_case = cases.list_case.unwrap();
-                    let nilk = translate_expr(list_case.nilk.unwrap(), env).into();
-                    let cons_case = list_case.consk.unwrap();
+                (Some(CasesType::List), _, Some((nilk, cons_case))) => {
+                    let nilk = translate_expr(nilk, env).into();
                     env.bind(&cons_case.x);
                     env.bind(&cons_case.xs);
                     let consk = translate_expr(cons_case.expr, env).into();
                     env.unbind();
                     env.unbind();
                     MComputation::Match { list: MValue::Var(0).into(), nilk, consk }
-                }
+                },
+                _ => env.unsupported("a case without a branch for each constructor".to_string()),
             }.into();
             env.unbind();
 
@@ -177,7 +417,7 @@ fn translate_expr(expr: Expr, env : &mut TEnv) -> MComputation {
         Expr::Nil => MComputation::Return(MValue::Nil.into()),
         Expr::Cons(x, xs) => {
             let comp_head = translate_expr(*x, env).into();
-            env.bind(&"_foo".to_string());
+            env.bind("_foo");
             let comp_tail = translate_expr(*xs, env).into();
             env.unbind();
             MComputation::Bind { 
@@ -197,13 +437,13 @@ fn translate_expr(expr: Expr, env : &mut TEnv) -> MComputation {
 
                     MComputation::Return(MValue::Thunk(MComputation::Lambda { body: body.into() }.into()).into())
                 },
-                arg::Arg::Pair(arg, arg1) => todo!(),
+                arg::Arg::Pair(_, _) => env.unsupported("a pair argument".to_string()),
             }
         },
         Expr::App(op, arg) => {
             let comp_op = translate_expr(*op, env).into();
             
-            env.bind(&"_foo".to_string());
+            env.bind("_foo");
             let comp_arg = translate_expr(*arg, env).into();
             env.unbind();
             
@@ -222,20 +462,24 @@ fn translate_expr(expr: Expr, env : &mut TEnv) -> MComputation {
         Expr::List(mut elems) => translate_list(elems, env),
         Expr::Ident(s) => MComputation::Return(MValue::Var(env.find(&s)).into()),
         Expr::Nat(n) => translate_nat(n),
-        Expr::Bool(b) => todo!("no bools yet"),
+        Expr::Bool(b) => {
+            let unit = MValue::Unit.into();
+            MComputation::Return(if b { MValue::Inl(unit) } else { MValue::Inr(unit) }.into())
+        },
         Expr::Pair(lhs, rhs) => translate_pair(*lhs, *rhs, env),
         Expr::Stm(s) => translate_stm(*s, env)
     }
 }
 
-fn translate_bexpr(bexpr: BExpr, env : &TEnv) -> MComputation {
-    match bexpr {
-        BExpr::Eq(lhs, rhs) => todo!(),
-        BExpr::NEq(lhs, rhs) => todo!(),
-        BExpr::And(lhs, rhs) => todo!(),
-        BExpr::Or(lhs, rhs) => todo!(),
-        BExpr::Not(e) => todo!()
-    }
+fn translate_bexpr(bexpr: BExpr, env : &mut TEnv) -> MComputation {
+    let op = match bexpr {
+        BExpr::Eq(_, _) => "==",
+        BExpr::NEq(_, _) => "!=",
+        BExpr::And(_, _) => "&&",
+        BExpr::Or(_, _) => "||",
+        BExpr::Not(_) => "!",
+    };
+    env.unsupported(format!("the operator {}", op))
 }
 
 fn translate_list(elems: Vec<Expr>, env : &mut TEnv) -> MComputation {
@@ -243,7 +487,7 @@ fn translate_list(elems: Vec<Expr>, env : &mut TEnv) -> MComputation {
         [] => MComputation::Return(MValue::Nil.into()),
         [head , tail@ ..] => {
             let chead = translate_expr(head.clone(), env);
-            env.bind(&"_foo".to_string());
+            env.bind("_foo");
             let ctail = translate_list(tail.to_vec(), env);
             env.unbind();
             MComputation::Bind {
@@ -261,12 +505,12 @@ fn translate_nat(n: usize) -> MComputation {
     for i in (0..n) {
         nat_val = MValue::Succ(nat_val).into();
     }
-    MComputation::Return(nat_val.into())
+    MComputation::Return(nat_val)
 }
 
 fn translate_pair(fst: Expr, snd: Expr, env : &mut TEnv) -> MComputation {
     let fst_comp = translate_expr(fst, env).into();
-    env.bind(&"_foo".to_string());
+    env.bind("_foo");
     let snd_comp = translate_expr(snd, env).into();
     env.unbind();
     MComputation::Bind { 
@@ -275,5 +519,38 @@ fn translate_pair(fst: Expr, snd: Expr, env : &mut TEnv) -> MComputation {
             comp : snd_comp,
             cont : MComputation::Return(MValue::Pair(MValue::Var(1).into(), MValue::Var(0).into()).into()).into()
         }.into()
-    }.into()
-}
\ No newline at end of file
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::parser;
+    use super::*;
+
+    #[test]
+    fn untranslatable() {
+        let ast = parser::parse("f :: Nat -> Nat\nf x = g x.\n\nf 1.").unwrap();
+        assert_eq!(translate(ast).err(), Some(TranslateError::Unbound(Unbound("g".to_string()))));
+        let ast = parser::parse("exists x :: Nat. y.").unwrap();
+        assert_eq!(translate(ast).err(), Some(TranslateError::Unbound(Unbound("y".to_string()))));
+        let ast = parser::parse("f :: Nat -> Nat\nf x = x.").unwrap();
+        assert_eq!(translate(ast).err(), Some(TranslateError::NoQuery));
+    }
+
+    #[test]
+    fn unsupported() {
+        let unsupported = |src : &str| match translate(parser::parse(src).unwrap()).err() {
+            Some(TranslateError::Unsupported(what)) => what,
+            e => panic!("expected an unsupported construct, found {:?}", e),
+        };
+        assert_eq!(unsupported("exists x :: Int. x."), "the type Int of the variable x");
+        assert_eq!(unsupported("exists f :: Nat -> Nat. 1."), "the type Nat -> Nat of the variable f");
+        assert_eq!(unsupported("exists xs :: [Int]. xs."), "the type [Int] of the variable xs");
+        assert_eq!(unsupported("exists x :: Nat. if x == 0 then 1 else 2."), "an if statement");
+        assert_eq!(unsupported("exists x :: Nat. x == 0."), "the operator ==");
+        assert_eq!(unsupported("exists x :: Bool. !x."), "the operator !");
+        assert_eq!(unsupported("fst (x, y) = x.\n\nfst (1, 2)."), "a pair argument");
+        assert_eq!(unsupported("(\\(x, y). x) (1, 2)."), "a pair argument");
+        assert_eq!(unsupported("case 1 of S n -> n."), "a case without a branch for each constructor");
+        assert_eq!(unsupported("case [] of x : xs -> x."), "a case without a branch for each constructor");
+    }
+}
@@ -0,0 +1,167 @@
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path};
+
+use super::{error::RuntimeError, search::Branch, term::Answer, trace::{show, Event, Fork, Tracer, Unified}};
+
+enum Kind {
+    // the query, or a branch of a fork labelled with the decision taken
+    Branch(String),
+    // a branch whose unification failed, or failed the occurs check
+    Failed(Unified),
+    Error(String),
+    Answer(String),
+    // the forks below a node deeper than the cap
+    Pruned,
+}
+
+struct Node {
+    parent : Option<usize>,
+    kind : Kind,
+    // steps the branch had taken
+    steps : usize,
+    // forks above the node
+    depth : usize,
+    pruned : bool,
+}
+
+// The forks of a search and how its branches ended, for drawing with
+// Graphviz. The search gives each machine the node it is in and reports
+// each step, while the events of the step say whether and how it forked.
+// Forks deeper than `max_depth` aren't recorded, nor anything below them.
+pub struct SearchTree {
+    nodes : Vec<Node>,
+    max_depth : usize,
+    // what the step being recorded did
+    fork : Option<Fork>,
+    decisions : Vec<String>,
+    unified : Option<Unified>,
+}
+
+impl SearchTree {
+
+    pub fn new(max_depth : usize) -> SearchTree {
+        let root = Node { parent : None, kind : Kind::Branch("query".to_string()), steps : 0, depth : 0, pruned : false };
+        SearchTree { nodes : vec![root], max_depth, fork : None, decisions : vec![], unified : None }
+    }
+
+    fn add(&mut self, parent : usize, kind : Kind, steps : usize) -> usize {
+        let depth = self.nodes[parent].depth + 1;
+        self.nodes.push(Node { parent : Some(parent), kind, steps, depth, pruned : false });
+        self.nodes.len() - 1
+    }
+
+    // Records a step of a machine in `node` to `ms`, which go in a node
+    // each if the step forked
+    pub fn step(&mut self, node : usize, steps : usize, ms : &mut [impl Branch]) {
+        let (fork, unified) = (self.fork.take(), self.unified.take());
+        let decisions = std::mem::take(&mut self.decisions);
+        if self.nodes[node].pruned { return }
+        match fork {
+            Some(_) if self.nodes[node].depth >= self.max_depth => {
+                self.nodes[node].pruned = true;
+                self.add(node, Kind::Pruned, steps);
+            },
+            Some(cause) => for (k, m) in ms.iter_mut().enumerate() {
+                let decision = match cause {
+                    Fork::Choice => format!("choice {}", k + 1),
+                    Fork::Narrow(_) => decisions.get(k).cloned().unwrap_or_default(),
+                };
+                m.set_node(self.add(node, Kind::Branch(decision), steps));
+            },
+            None => if let (true, Some(outcome @ (Unified::Fail | Unified::Occurs))) = (ms.is_empty(), unified) {
+                self.add(node, Kind::Failed(outcome), steps);
+            },
+        }
+    }
+
+    pub fn error(&mut self, node : usize, steps : usize, error : &RuntimeError) {
+        (self.fork, self.unified) = (None, None);
+        self.decisions.clear();
+        if !self.nodes[node].pruned { self.add(node, Kind::Error(error.to_string()), steps); }
+    }
+
+    pub fn answer(&mut self, node : usize, steps : usize, answer : &Answer) {
+        if !self.nodes[node].pruned { self.add(node, Kind::Answer(answer.to_string()), steps); }
+    }
+
+    pub fn write_dot(&self, out : &mut impl Write) -> io::Result<()> {
+        writeln!(out, "digraph search {{")?;
+        writeln!(out, "    node [shape=box, fontname=monospace];")?;
+        for (i, node) in self.nodes.iter().enumerate() {
+            let (label, style) = match &node.kind {
+                Kind::Branch(decision) => (decision.clone(), ""),
+                Kind::Failed(Unified::Occurs) => ("occurs check".to_string(), ", style=filled, fillcolor=lightpink"),
+                Kind::Failed(_) => ("fail".to_string(), ", style=filled, fillcolor=lightpink"),
+                Kind::Error(error) => (format!("error: {}", error), ", style=filled, fillcolor=orange"),
+                Kind::Answer(answer) => (format!("answer {}", answer), ", style=filled, fillcolor=palegreen"),
+                Kind::Pruned => ("...".to_string(), ", style=dashed"),
+            };
+            writeln!(out, "    n{} [label=\"{}\\nstep {}\"{}];", i, escape(&label), node.steps, style)?;
+            if let Some(parent) = node.parent { writeln!(out, "    n{} -> n{};", parent, i)? }
+        }
+        writeln!(out, "}}")
+    }
+
+    pub fn save(&self, file : &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(file)?);
+        self.write_dot(&mut out)?;
+        out.flush()
+    }
+}
+
+// Keeps the events that say how the step being recorded forked
+impl Tracer for SearchTree {
+    fn event(&mut self, _ : usize, event : Event) {
+        match event {
+            Event::Fork { cause, .. } => self.fork = Some(cause),
+            // the binds of a narrowing step are its decisions
+            Event::Bind { lvar, value } if self.fork.is_some() => self.decisions.push(format!("?{} := {}", lvar, show(value, false))),
+            Event::Unify { outcome } => self.unified = Some(outcome),
+            _ => (),
+        }
+    }
+}
+
+fn escape(s : &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{machine::{limits::Limits, search::Search, step::Machine, translate::translate}, parser};
+    use super::*;
+
+    fn dot(src : &str, limits : Limits, max_depth : usize) -> String {
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        let mut search = Search::new(Machine::new(comp, env), limits).with_search_tree(SearchTree::new(max_depth));
+        search.by_ref().for_each(drop);
+        let mut out = vec![];
+        search.search_tree().unwrap().write_dot(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn forks_and_leaves() {
+        let out = dot("1 <> (exists x :: Nat. x =:= S x. x) <> (exists y :: Nat. y =:= 2. y).", Limits::default(), 10);
+        assert!(out.starts_with("digraph search {\n"), "{}", out);
+        assert!(out.contains("n0 [label=\"query\\nstep 0\"];"), "{}", out);
+        assert!(out.contains("n1 [label=\"choice 1\\nstep 1\"];\n    n0 -> n1;"), "{}", out);
+        assert!(out.contains("[label=\"answer 1\\nstep 2\", style=filled, fillcolor=palegreen];\n    n1 -> n4;"), "{}", out);
+        assert!(out.contains("[label=\"occurs check\\nstep "), "{}", out);
+        assert!(out.contains("answer 2"), "{}", out);
+    }
+
+    #[test]
+    fn narrowing_is_capped() {
+        let src = "poke :: Nat -> Nat
+poke n = case n of
+    Z -> 0
+  | S z -> S (poke z).
+
+exists x :: Nat. poke x.";
+        let out = dot(src, Limits { max_steps : Some(500), ..Limits::default() }, 3);
+        assert!(out.contains("[label=\"?0 := 0\\nstep "), "{}", out);
+        assert!(out.contains("[label=\"?0 := S ?1\\nstep "), "{}", out);
+        assert_eq!(out.matches("label=\"...").count(), 1, "{}", out);
+        assert_eq!(out.matches("answer").count(), 3, "{}", out);
+    }
+}
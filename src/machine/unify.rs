@@ -1,62 +1,141 @@
-use std::{collections::VecDeque, rc::Rc};
+use std::{collections::{HashSet, VecDeque}, sync::Arc};
 
-use super::{env::Env, lvar::LogicEnv, mterms::MValue, senv::SuspEnv, senv::SuspAt, VClosure};
+use super::{env::Env, error::RuntimeError, mterms::MValue, senv::SuspAt, store::{LogicStore, SuspStore}, Ident, VClosure};
 
 pub enum UnifyError {
     Occurs,
     Fail,
     Susp(SuspAt),
+    Runtime(RuntimeError),
 }
 
-pub fn unify(lhs : &Rc<MValue>, rhs : &Rc<MValue>, env : &Rc<Env>, lenv : &mut LogicEnv, senv : &SuspEnv) -> Result<(),UnifyError> { 
+// `on_bind` is told of each logic variable bound, for tracing
+pub fn unify<L : LogicStore, S : SuspStore>(lhs : &Arc<MValue>, rhs : &Arc<MValue>, env : &Arc<Env>, lenv : &mut L, senv : &S, on_bind : &mut impl FnMut(Ident, &VClosure)) -> Result<(),UnifyError> { 
+    unify_vclos(VClosure::mk_clos(lhs, env), VClosure::mk_clos(rhs, env), lenv, senv, on_bind)
+}
+
+pub fn unify_vclos<L : LogicStore, S : SuspStore>(lhs : VClosure, rhs : VClosure, lenv : &mut L, senv : &S, on_bind : &mut impl FnMut(Ident, &VClosure)) -> Result<(),UnifyError> { 
 
     let mut q : VecDeque<(VClosure, VClosure)> = VecDeque::new();
-    let lhs_clos = VClosure::mk_clos(lhs, env);
-    let rhs_clos = VClosure::mk_clos(rhs, env);
-    q.push_back((lhs_clos, rhs_clos));
+    q.push_back((lhs, rhs));
+    // the pairs of closures already unified; on rational trees meeting a
+    // pair again means the unification has gone round a cycle
+    let mut seen : HashSet<[usize; 4]> = HashSet::new();
+    let occurs_check = lenv.occurs_check();
 
     while let Some((lhs, rhs)) = q.pop_front() {
 
         // close the LHS and RHS to find what their head is
-        let lhs = lhs.close_head(&lenv, senv).map_err(UnifyError::Susp)?;
-        let rhs = rhs.close_head(&lenv, senv).map_err(UnifyError::Susp)?;
+        let lhs = lhs.close_head(lenv, senv).map_err(UnifyError::Susp)?;
+        let rhs = rhs.close_head(lenv, senv).map_err(UnifyError::Susp)?;
 
-        // println!("[DEBUG] about to unify {} and {}", lhs.val(), rhs.val());
         match (&lhs, &rhs) {
             (VClosure::LogicVar { ident : ident_lhs}, VClosure::LogicVar { ident : ident_rhs}) => { 
                 // both are variables, so make them equal in the logic env
+                on_bind(*ident_lhs, &rhs);
                 lenv.identify(*ident_lhs, *ident_rhs);
             },
             (VClosure::LogicVar { ident }, _) => { 
                 // the LHS is a logic variable
-                if rhs.occurs_lvar(&lenv, senv, *ident).map_err(UnifyError::Susp)? { return Err(UnifyError::Occurs) }
+                if occurs_check && rhs.occurs_lvar(lenv, senv, *ident)? { return Err(UnifyError::Occurs) }
+                on_bind(*ident, &rhs);
                 lenv.set_vclos(*ident, rhs);
             },
             (_, VClosure::LogicVar { ident }) => { 
                 // the RHS is a logic variable
-                if lhs.occurs_lvar(&lenv, senv, *ident).map_err(UnifyError::Susp)? { return Err(UnifyError::Occurs) }
+                if occurs_check && lhs.occurs_lvar(lenv, senv, *ident)? { return Err(UnifyError::Occurs) }
+                on_bind(*ident, &lhs);
                 lenv.set_vclos(*ident, lhs);
             },
-            (VClosure::Clos { val : lhs_val, env: lhs_env}, VClosure::Clos { val : rhs_val, env : rhs_env }) =>
+            (VClosure::Clos { val : lhs_val, env: lhs_env}, VClosure::Clos { val : rhs_val, env : rhs_env }) => {
+                let key = [Arc::as_ptr(lhs_val) as usize, Arc::as_ptr(lhs_env) as usize, Arc::as_ptr(rhs_val) as usize, Arc::as_ptr(rhs_env) as usize];
+                if !occurs_check && !seen.insert(key) { continue }
                 match (&**lhs_val, &**rhs_val) {
+                    (MValue::Unit, MValue::Unit) => continue,
+                    (MValue::Unit, _) => { return Err(UnifyError::Fail) },
                     (MValue::Zero, MValue::Zero) => continue,
                     (MValue::Zero, _) => { return Err(UnifyError::Fail) },
                     (MValue::Succ(v), MValue::Succ(w)) => {
-                        q.push_back((VClosure::mk_clos(v, &lhs_env), VClosure::mk_clos(w, &rhs_env)));
+                        q.push_back((VClosure::mk_clos(v, lhs_env), VClosure::mk_clos(w, rhs_env)));
                     }
                     (MValue::Succ(_), _) => { return Err(UnifyError::Fail) }
                     (MValue::Nil, MValue::Nil) => continue,
                     (MValue::Nil, _) => { return Err(UnifyError::Fail) },
                     (MValue::Cons(x, xs), MValue::Cons(y, ys)) => { 
-                        q.push_back((VClosure::mk_clos(x, &lhs_env), VClosure::mk_clos(y, &rhs_env)));
-                        q.push_back((VClosure::mk_clos(xs, &lhs_env), VClosure::mk_clos(ys, &rhs_env)));
+                        q.push_back((VClosure::mk_clos(x, lhs_env), VClosure::mk_clos(y, rhs_env)));
+                        q.push_back((VClosure::mk_clos(xs, lhs_env), VClosure::mk_clos(ys, rhs_env)));
                     }
                     (MValue::Cons(_, _), _) => { return Err(UnifyError::Fail) }
-                    _ => { panic!("tried to unify a thunk") }
+                    (MValue::Pair(x, y), MValue::Pair(z, w)) => {
+                        q.push_back((VClosure::mk_clos(x, lhs_env), VClosure::mk_clos(z, rhs_env)));
+                        q.push_back((VClosure::mk_clos(y, lhs_env), VClosure::mk_clos(w, rhs_env)));
+                    }
+                    (MValue::Pair(_, _), _) => { return Err(UnifyError::Fail) }
+                    (MValue::Inl(v), MValue::Inl(w)) | (MValue::Inr(v), MValue::Inr(w)) => {
+                        q.push_back((VClosure::mk_clos(v, lhs_env), VClosure::mk_clos(w, rhs_env)));
+                    }
+                    (MValue::Inl(_), _) | (MValue::Inr(_), _) => { return Err(UnifyError::Fail) }
+                    _ => { return Err(UnifyError::Runtime(RuntimeError::UnifyThunk)) }
                 }
+            },
             (VClosure::Susp { ident }, _) => unreachable!("tried to unify a suspension"),
             (_, VClosure::Susp { ident }) => unreachable!("tried to unify a suspension"),
         }
     }
-    return Ok(())
+    Ok(())
 } 
+
+#[cfg(test)]
+mod tests {
+    use crate::{machine::{limits::Limits, mterms::MComputation, output, search::Search, step::Machine, translate::translate}, parser};
+
+    fn answers(src : &str) -> Vec<String> {
+        answers_with(src, true)
+    }
+
+    fn answers_with(src : &str, occurs_check : bool) -> Vec<String> {
+        let (comp, env) = translate(parser::parse(src).unwrap()).unwrap();
+        Search::new(Machine::new(comp, env).with_occurs_check(occurs_check), Limits::default())
+            .map(|m| match &*m.comp {
+                MComputation::Return(v) => output(v.clone(), m.env.clone(), None, &[], &m.lenv, &m.senv).unwrap(),
+                _ => unreachable!()
+            })
+            .collect()
+    }
+
+    const SUCC : &str = "succ :: Nat -> Nat
+succ n = S n.
+
+";
+
+    #[test]
+    fn occurs_through_identified_variables() {
+        assert!(answers("exists x :: Nat. exists y :: Nat. x =:= y. x =:= S y. 0.").is_empty());
+    }
+
+    #[test]
+    fn occurs_through_suspension() {
+        assert!(answers(&(SUCC.to_owned() + "exists x :: Nat. let y = succ x in x =:= S y. 0.")).is_empty());
+    }
+
+    #[test]
+    fn suspension_without_occurrence() {
+        assert_eq!(answers(&(SUCC.to_owned() + "exists x :: Nat. let y = succ 3 in x =:= S y. x.")), vec!["5"]);
+    }
+
+    #[test]
+    fn rational_trees() {
+        let src = "exists x :: Nat. x =:= S x. x.";
+        assert!(answers(src).is_empty());
+        assert_eq!(answers_with(src, false), vec!["_0 where _0 = S _0"]);
+    }
+
+    #[test]
+    fn rational_trees_unify() {
+        assert_eq!(answers_with("exists x :: Nat. exists y :: Nat. x =:= S x. y =:= S (S y). x =:= y. (x, y).", false),
+            vec!["(_0, _1) where _0 = S _0, _1 = S (S _1)"]);
+        assert_eq!(answers_with("exists xs :: [Nat]. xs =:= 1 : 2 : xs. (xs, xs).", false),
+            vec!["(_0, _0) where _0 = 1 : 2 : _0"]);
+        assert!(answers_with("exists x :: Nat. exists y :: Nat. x =:= S y. y =:= S x. x =:= 0. x.", false).is_empty());
+    }
+}
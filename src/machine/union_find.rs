@@ -1,49 +1,197 @@
+use std::collections::HashSet;
+
+use im::HashMap;
 
 #[derive(Clone)]
 struct Node {
-    depth : usize,
+    rank : usize,
     parent : Option<usize>
 }
 
 impl Node {
-    fn new() -> Node { Node { depth : 0, parent : None } }
+    fn new() -> Node { Node { rank : 0, parent : None } }
 }
 
+// Persistent union-find: clones share structure, so each narrowing branch
+// costs O(1) to create rather than O(variables). Union by rank bounds the
+// paths seen by `find`, and `find_compress`, which the machines look
+// variables up with, points the nodes it walks at their representative.
+// Only the nodes changed are copied, so sibling machines never see it.
 #[derive(Clone)]
 pub struct UnionFind {
-    array : Vec<Node>
+    nodes : HashMap<usize, Node>
 }
 
 impl UnionFind {
-    
-    pub fn new() -> UnionFind { UnionFind { array : vec![] } }
+
+    pub fn new() -> UnionFind { UnionFind { nodes : HashMap::new() } }
+
+    pub fn size(&self) -> usize { self.nodes.len() }
 
     pub fn find(&self, mut i : usize) -> usize {
-        assert!(i < self.array.len());
-        while let Some(p) = self.array[i].parent { i = p; }
+        while let Some(p) = self.nodes[&i].parent { i = p; }
         i
     }
-    
+
+    // `i` followed by its ancestors, ending with its representative
+    pub fn path(&self, mut i : usize) -> Vec<usize> {
+        let mut path = vec![i];
+        while let Some(p) = self.nodes[&i].parent { path.push(p); i = p; }
+        path
+    }
+
+    // Finds the representative of `i`, pointing every node on the way at it
+    pub fn find_compress(&mut self, i : usize) -> usize {
+        let root = self.find(i);
+        let mut j = i;
+        while let Some(p) = self.nodes[&j].parent {
+            if p != root { self.nodes.get_mut(&j).unwrap().parent = Some(root) }
+            j = p;
+        }
+        root
+    }
+
     pub fn register(&mut self, i : usize) {
-        assert!(i >= self.array.len());
-        self.array.resize_with(i+1, || { Node::new() });
+        assert!(!self.nodes.contains_key(&i));
+        self.nodes.insert(i, Node::new());
     }
-    
+
     pub fn union(&mut self, i : usize, j : usize) {
-        let a = self.find(i);
-        let b = self.find(j);
-        
+        let a = self.find_compress(i);
+        let b = self.find_compress(j);
+
         if a != b {
-            if self.array[a].depth > self.array[b].depth {
-                self.array[b].parent = Some(a)
+            let rank_a = self.nodes[&a].rank;
+            let rank_b = self.nodes[&b].rank;
+            if rank_a > rank_b {
+                self.nodes.get_mut(&b).unwrap().parent = Some(a)
             }
-            else if self.array[a].depth < self.array[b].depth {
-                self.array[a].parent = Some(b)
+            else if rank_a < rank_b {
+                self.nodes.get_mut(&a).unwrap().parent = Some(b)
             }
             else {
-                self.array[a].parent = Some(b);
-                self.array[b].depth += 1;
+                self.nodes.get_mut(&a).unwrap().parent = Some(b);
+                self.nodes.get_mut(&b).unwrap().rank += 1;
             }
         }
     }
-}
\ No newline at end of file
+
+    // Drops every node outside `live`, which must be closed under parents.
+    // Only the paths to the dropped nodes are copied, so the survivors stay
+    // shared with sibling machines.
+    pub fn retain(&mut self, live : &HashSet<usize>) {
+        let dead : Vec<usize> = self.nodes.keys().filter(|i| !live.contains(i)).cloned().collect();
+        dead.iter().for_each(|i| { self.nodes.remove(i); });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn union_and_find() {
+        let mut uf = UnionFind::new();
+        (0..6).for_each(|i| uf.register(i));
+        uf.union(0, 1);
+        uf.union(2, 3);
+        uf.union(1, 3);
+        assert_eq!(uf.find(0), uf.find(2));
+        assert_ne!(uf.find(0), uf.find(4));
+        uf.union(4, 5);
+        assert_eq!(uf.find(4), uf.find(5));
+        assert_ne!(uf.find(5), uf.find(3));
+    }
+
+    #[test]
+    fn compression_flattens_paths() {
+        let mut uf = UnionFind::new();
+        (0..8).for_each(|i| uf.register(i));
+        for i in [0, 2, 4, 6] { uf.union(i, i + 1) }
+        uf.union(0, 2);
+        uf.union(4, 6);
+        uf.union(0, 4);
+        let root = uf.find(0);
+        assert!(uf.path(0).len() > 2);
+        uf.find_compress(0);
+        assert_eq!(uf.path(0), vec![0, root]);
+    }
+
+    #[test]
+    fn clones_are_independent() {
+        let mut uf = UnionFind::new();
+        (0..4).for_each(|i| uf.register(i));
+        let mut branch = uf.clone();
+        branch.union(0, 1);
+        uf.union(2, 3);
+        assert_eq!(branch.find(0), branch.find(1));
+        assert_ne!(uf.find(0), uf.find(1));
+        assert_ne!(branch.find(2), branch.find(3));
+    }
+
+    #[test]
+    fn retain_leaves_clones_alone() {
+        let mut uf = UnionFind::new();
+        (0..6).for_each(|i| uf.register(i));
+        uf.union(0, 1);
+        let mut swept = uf.clone();
+        swept.retain(&[0, 1, 2].into_iter().collect());
+        assert_eq!(swept.size(), 3);
+        assert_eq!(swept.find(0), swept.find(1));
+        assert_eq!(uf.size(), 6);
+        uf.union(4, 5);
+        assert_eq!(uf.find(4), uf.find(5));
+    }
+
+    // The union-find of the baseline, a `Vec` copied whole by each clone
+    #[derive(Clone)]
+    struct VecUnionFind { array : Vec<Node> }
+
+    impl VecUnionFind {
+        fn find(&self, mut i : usize) -> usize {
+            while let Some(p) = self.array[i].parent { i = p }
+            i
+        }
+
+        fn union(&mut self, i : usize, j : usize) {
+            let (a, b) = (self.find(i), self.find(j));
+            if a == b { return }
+            let (child, parent) = if self.array[a].rank > self.array[b].rank { (b, a) } else { (a, b) };
+            if self.array[child].rank == self.array[parent].rank { self.array[parent].rank += 1 }
+            self.array[child].parent = Some(parent);
+        }
+    }
+
+    // cargo test --release -- --ignored --nocapture bench_branching
+    //
+    // Each narrowing branch clones the union-find, binds a variable and
+    // looks it up, against the baseline's union-find doing the same.
+    #[test]
+    #[ignore]
+    fn bench_branching() {
+        const VARS : usize = 20_000;
+        const BRANCHES : usize = 5_000;
+
+        let baseline = VecUnionFind { array : vec![Node::new(); VARS] };
+        let start = std::time::Instant::now();
+        for b in 0..BRANCHES {
+            let mut branch = baseline.clone();
+            branch.union(b, b + 1);
+            std::hint::black_box(branch.find(b));
+        }
+        let before = start.elapsed();
+
+        let mut uf = UnionFind::new();
+        (0..VARS).for_each(|i| uf.register(i));
+        let start = std::time::Instant::now();
+        for b in 0..BRANCHES {
+            let mut branch = uf.clone();
+            branch.union(b, b + 1);
+            std::hint::black_box(branch.find_compress(b));
+        }
+        let after = start.elapsed();
+
+        println!("{} branches over {} variables: baseline {:?}, persistent {:?}", BRANCHES, VARS, before, after);
+        assert!(after < before);
+    }
+}
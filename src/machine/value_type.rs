@@ -1,7 +1,8 @@
 use std::fmt::Display;
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum ValueType {
+    Unit,
     Nat,
     Product(Box<ValueType>, Box<ValueType>),
     Sum(Box<ValueType>, Box<ValueType>),
@@ -9,19 +10,34 @@ pub enum ValueType {
     Thunk(Box<ComputationType>)
 }
 
+impl ValueType {
+    // Booleans are the sum of two units, `true` on the left
+    pub fn bool() -> ValueType { ValueType::Sum(Box::new(ValueType::Unit), Box::new(ValueType::Unit)) }
+}
+
 impl Display for ValueType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // products and sums to the left of `*` or `+` need parentheses
+        let operand = |t : &ValueType| match t {
+            ValueType::Product(_, _) | ValueType::Sum(_, _) if *t != ValueType::bool() => format!("({})", t),
+            _ => t.to_string(),
+        };
         match self {
+            ValueType::Unit => write!(f, "()"),
             ValueType::Nat => write!(f, "Nat"),
             ValueType::List(value_type) => write!(f, "[{}]", value_type),
             ValueType::Thunk(computation_type) => write!(f, "THONK"),
-            ValueType::Product(value_type, value_type1) => todo!(),
-            ValueType::Sum(value_type, value_type1) => todo!(),
+            t if *t == ValueType::bool() => write!(f, "Bool"),
+            ValueType::Product(value_type, value_type1) => match **value_type1 {
+                ValueType::Sum(_, _) if **value_type1 != ValueType::bool() => write!(f, "{} * ({})", operand(value_type), value_type1),
+                _ => write!(f, "{} * {}", operand(value_type), value_type1),
+            },
+            ValueType::Sum(value_type, value_type1) => write!(f, "{} + {}", operand(value_type), value_type1),
         }
     }
 }
 
-#[derive(PartialEq, Clone, Debug)]
+#[derive(PartialEq, Eq, Hash, Clone, Debug)]
 pub enum ComputationType {
     Return(Box<ValueType>),
     Arrow(Box<ValueType>, Box<ComputationType>)
@@ -1,49 +1,95 @@
-use std::rc::Rc;
+use std::{collections::HashSet, sync::Arc};
 
 use crate::machine::senv::SuspAt;
 
-use super::{env::Env, lvar::LogicEnv, mterms::{MComputation, MValue}, senv::SuspEnv, Ident};
+use super::{env::Env, error::RuntimeError, unify::UnifyError, mterms::{MComputation, MValue}, store::{LogicStore, SuspStore}, Ident};
 
 #[derive(Clone, Debug)]
 pub enum VClosure {
-    Clos { val : Rc<MValue>, env : Rc<Env> },
+    Clos { val : Arc<MValue>, env : Arc<Env> },
     LogicVar { ident : Ident },
     Susp { ident : Ident }
 }
 
+// The suspensions and logic variables already visited by `demanded_susps`
+#[derive(Default)]
+pub struct Seen {
+    susps : HashSet<Ident>,
+    lvars : HashSet<Ident>,
+}
+
 impl VClosure {
 
     pub fn val(&self) -> String { 
         match self {
-            VClosure::Clos { val, env } => format!("val({})", val.to_string()),
+            VClosure::Clos { val, env } => format!("val({})", val),
             VClosure::LogicVar { ident } => format!("lvar({})", ident),
             VClosure::Susp { ident } => format!("susp({})", ident),
         }
     }
 
-    pub fn mk_clos(val : &Rc<MValue>, env : &Rc<Env>) -> VClosure {
+    pub fn mk_clos(val : &Arc<MValue>, env : &Arc<Env>) -> VClosure {
         VClosure::Clos { val : val.clone(), env : env.clone() }
     }
 
-    pub fn occurs_lvar(&self, lenv : &LogicEnv, senv : &SuspEnv, ident : Ident) -> Result<bool, SuspAt> {
-        match self.clone().close_head(lenv, &senv)? {
+    // Whether the logic variable `ident`, or one identified with it, occurs
+    // in the value. A pending suspension in the way is returned as
+    // `UnifyError::Susp`, so that it is forced and the unification retried
+    // rather than the check guessing at its value.
+    pub fn occurs_lvar<L : LogicStore, S : SuspStore>(&self, lenv : &mut L, senv : &S, ident : Ident) -> Result<bool, UnifyError> {
+        match self.clone().close_head(lenv, senv).map_err(UnifyError::Susp)? {
             VClosure::Clos { val, env } => {
                 match &*val {
                     MValue::Succ(v) => VClosure::mk_clos(v, &env).occurs_lvar(lenv, senv, ident),
-                    MValue::Cons(v, w) => 
+                    MValue::Cons(v, w) | MValue::Pair(v, w) => 
                         Ok(VClosure::Clos { val : v.clone(), env: env.clone()}.occurs_lvar(lenv, senv, ident)?
                         || VClosure::Clos { val : w.clone(), env : env.clone()}.occurs_lvar(lenv, senv, ident)?),
+                    MValue::Inl(v) | MValue::Inr(v) => VClosure::mk_clos(v, &env).occurs_lvar(lenv, senv, ident),
                     MValue::Var(_) => unreachable!("value should be head-closed in occurs check"),
-                    MValue::Thunk(_) => panic!("mustn't be doing occurs to a computation"),
+                    MValue::Thunk(_) => Err(UnifyError::Runtime(RuntimeError::UnifyThunk)),
                     _ => Ok(false)
                 }
             },
-            VClosure::LogicVar { ident : ident2 } => Ok(ident == ident2),
-            VClosure::Susp { ident } => todo!()
+            VClosure::LogicVar { ident : ident2 } => Ok(lenv.root(ident) == lenv.root(ident2)),
+            VClosure::Susp { .. } => unreachable!("suspension should be head-closed in occurs check"),
         }
     }
     
-    pub fn close_head(self, lenv : &LogicEnv, senv : &SuspEnv) -> Result<VClosure, SuspAt> {
+    // Collects the pending suspensions the value depends on, without
+    // looking inside thunks. The parts left to visit are kept on a stack,
+    // as values can be deeper than the call stack.
+    pub fn demanded_susps<L : LogicStore, S : SuspStore>(&self, lenv : &L, senv : &S, seen : &mut Seen, out : &mut Vec<Ident>) {
+        let mut todo = vec![self.clone()];
+        while let Some(vclos) = todo.pop() {
+            match vclos {
+                VClosure::Clos { val, env } => {
+                    match &*val {
+                        MValue::Var(i) => todo.push(env.lookup(*i).expect("index undefined in env")),
+                        MValue::Succ(v) | MValue::Inl(v) | MValue::Inr(v) => todo.push(VClosure::mk_clos(v, &env)),
+                        MValue::Cons(v, w) | MValue::Pair(v, w) => {
+                            todo.push(VClosure::mk_clos(w, &env));
+                            todo.push(VClosure::mk_clos(v, &env))
+                        },
+                        MValue::Unit | MValue::Zero | MValue::Nil | MValue::Thunk(_) => ()
+                    }
+                },
+                VClosure::LogicVar { ident } => {
+                    // a bound variable is visited once, as it may be cyclic
+                    if !seen.lvars.insert(lenv.root(ident)) { continue }
+                    if let Some(vclos) = lenv.lookup(ident) { todo.push(vclos) }
+                },
+                VClosure::Susp { ident } => {
+                    if !seen.susps.insert(ident) { continue }
+                    match senv.lookup(&ident) {
+                        Ok(vclos) => todo.push(vclos),
+                        Err(_) => out.push(ident),
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn close_head<L : LogicStore, S : SuspStore>(self, lenv : &mut L, senv : &S) -> Result<VClosure, SuspAt> {
         let mut vclos = self;
         loop {
             vclos = match &vclos {
@@ -54,7 +100,7 @@ impl VClosure {
                     }
                 },
                 VClosure::LogicVar { ident } => {
-                    match lenv.lookup(*ident) {
+                    match lenv.resolve(*ident) {
                         Some(vclos) => vclos,
                         None => break,
                     }
@@ -65,35 +111,4 @@ impl VClosure {
         Ok(vclos)
     }
 
-    pub fn close(&self, lenv : &LogicEnv, senv : &SuspEnv) -> Option<MValue> {
-        match self {
-            VClosure::Clos { val,  env } => {
-                // println!("[DEBUG] CLOSING {:?} in env of size {}", val, env.size());
-                match &**val {
-                    MValue::Var(i) => env.lookup(*i)?.close(lenv, senv),
-                    MValue::Zero => Some(MValue::Zero),
-                    MValue::Succ(v) => Some(MValue::Succ(VClosure::mk_clos(v, env).close(lenv, senv)?.into())),
-                    MValue::Nil => Some(MValue::Nil),
-                    MValue::Cons(v, w) => 
-                        Some(MValue::Cons(
-                            VClosure::mk_clos(v, env).close(lenv, senv)?.into(),
-                            VClosure::mk_clos(w, env).close(lenv, senv)?.into()
-                        )),
-                    MValue::Pair(fst, snd) => 
-                        Some(MValue::Pair(
-                            VClosure::mk_clos(fst, env).close(lenv, senv)?.into(),
-                            VClosure::mk_clos(snd, env).close(lenv, senv)?.into(),
-                        )),
-                    MValue::Inl(v) =>
-                        Some(MValue::Inl(VClosure::mk_clos(v, env).close(lenv, senv)?.into())),
-                    MValue::Inr(v) => 
-                        Some(MValue::Inr(VClosure::mk_clos(v, env).close(lenv, senv)?.into())),
-                    MValue::Thunk(t) => panic!("tried to close thunk: {}", *t),
-                }
-            },
-            VClosure::LogicVar { ref ident } => lenv.lookup(*ident)?.close(lenv, senv),
-            VClosure::Susp { ident } => senv.lookup(ident).expect("unexpected suspension").close(lenv, senv)
-        }
-    }
-
 }
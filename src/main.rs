@@ -1,23 +1,190 @@
 use std::env;
 use std::process;
-use std::fs::File;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
 use std::io::{self, Read};
+use std::time::Duration;
 
-use crate::machine::translate::translate;
+use flp_poc::{machine, parser, repl, Program};
+use flp_poc::machine::{Config, Engine, Format, OnError, Schedule};
+use flp_poc::machine::pretty;
+use flp_poc::machine::compiled::Compiled;
 
-mod parser;
-mod machine;
+enum Command {
+    // run the program's query
+    Run,
+    // print the program in the canonical layout
+    Fmt,
+    // read definitions and queries interactively
+    Repl,
+    // translate the program and save it to a file
+    Compile,
+    // run a program saved by `compile`
+    Exec,
+}
+
+struct Options {
+    command : Command,
+    file_name : Option<String>,
+    config : Config,
+    // print the translated program instead of running it, with source
+    // names if set
+    dump_ir : Option<bool>,
+    // where `compile` saves the program
+    output : Option<String>,
+}
+
+fn usage(prog : &str) -> String {
+    format!("Usage: {} [--max-steps N] [--timeout SECS] [--max-frontier N] [--max-lvars N] [--engine bfs|dfs|bytecode] [--threads N] [--schedule oldest|newest|demand] [--gc N] [--on-error keep|abort] [--rational] [--bindings] [--format text|json|sexp] [--trace text|json] [--search-tree FILE] [--tree-depth N] [--stats] [--dump-ir indices|names] source_file\n       {} fmt source_file\n       {} [--repl] [options] [source_file]\n       {} compile [-o program.flpc] source_file\n       {} run [options] program.flpc", prog, prog, prog, prog, prog)
+}
+
+fn parse_args(args : &[String]) -> Result<Options, String> {
+    let (mut command, args) = match args.first().map(String::as_str) {
+        Some("fmt") => (Command::Fmt, &args[1..]),
+        Some("compile") => (Command::Compile, &args[1..]),
+        Some("run") => (Command::Exec, &args[1..]),
+        _ => (Command::Run, args),
+    };
+    let mut file_name = None;
+    let mut config = Config::default();
+    let mut dump_ir = None;
+    let mut output = None;
+    let (mut tree_file, mut tree_depth) = (None, 12);
+    let limits = &mut config.limits;
+    let mut args = args.iter();
+
+    fn value<'a>(flag : &str, args : &mut impl Iterator<Item = &'a String>) -> Result<&'a String, String> {
+        args.next().ok_or(format!("Error: Expected a value after '{}'.", flag))
+    }
+
+    fn count(flag : &str, v : &str) -> Result<usize, String> {
+        v.parse().map_err(|_| format!("Error: Expected a number after '{}', but got '{}'.", flag, v))
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--max-steps" => limits.max_steps = Some(count(arg, value(arg, &mut args)?)?),
+            "--max-frontier" => limits.max_frontier = Some(count(arg, value(arg, &mut args)?)?),
+            "--max-lvars" => limits.max_lvars = Some(count(arg, value(arg, &mut args)?)?),
+            "--gc" => config.gc = Some(count(arg, value(arg, &mut args)?)?).filter(|n| *n > 0),
+            "--engine" => config.engine = match value(arg, &mut args)?.as_str() {
+                "bfs" => Engine::Breadth,
+                "dfs" => Engine::Depth,
+                "bytecode" => Engine::Bytecode,
+                v => return Err(format!("Error: Unknown engine '{}'.", v)),
+            },
+            "--threads" => config.threads = count(arg, value(arg, &mut args)?)?,
+            "--schedule" => config.schedule = match value(arg, &mut args)?.as_str() {
+                "oldest" => Schedule::Oldest,
+                "newest" => Schedule::Newest,
+                "demand" => Schedule::Demand,
+                v => return Err(format!("Error: Unknown schedule '{}'.", v)),
+            },
+            "--on-error" => config.on_error = match value(arg, &mut args)?.as_str() {
+                "keep" => OnError::Keep,
+                "abort" => OnError::Abort,
+                v => return Err(format!("Error: Unknown error policy '{}'.", v)),
+            },
+            "--repl" => command = Command::Repl,
+            "-o" => output = Some(value(arg, &mut args)?.clone()),
+            "--rational" => config.occurs_check = false,
+            "--bindings" => config.bindings = true,
+            "--stats" => config.stats = true,
+            "--dump-ir" => dump_ir = match value(arg, &mut args)?.as_str() {
+                "indices" => Some(false),
+                "names" => Some(true),
+                v => return Err(format!("Error: Unknown IR naming '{}'.", v)),
+            },
+            "--format" => config.format = match value(arg, &mut args)?.as_str() {
+                "text" => Format::Text,
+                "json" => Format::Json,
+                "sexp" => Format::Sexp,
+                v => return Err(format!("Error: Unknown format '{}'.", v)),
+            },
+            "--trace" => config.trace = match value(arg, &mut args)?.as_str() {
+                "text" => Some(Format::Text),
+                "json" => Some(Format::Json),
+                v => return Err(format!("Error: Unknown trace format '{}'.", v)),
+            },
+            "--search-tree" => tree_file = Some(PathBuf::from(value(arg, &mut args)?)),
+            "--tree-depth" => tree_depth = count(arg, value(arg, &mut args)?)?,
+            "--timeout" => {
+                let v = value(arg, &mut args)?;
+                let timeout = v.parse().ok().and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                    .ok_or_else(|| format!("Error: Expected a number of seconds after '{}', but got '{}'.", arg, v))?;
+                limits.timeout = Some(timeout);
+            },
+            flag if flag.starts_with("--") => return Err(format!("Error: Unknown option '{}'.", flag)),
+            _ if file_name.is_none() => file_name = Some(arg.clone()),
+            _ => return Err(format!("Error: Unexpected argument '{}'.", arg)),
+        }
+    }
+
+    if config.engine != Engine::Breadth && config.threads > 1 {
+        return Err("Error: Only the breadth-first engine runs on several threads.".to_string());
+    }
+
+    if config.trace.is_some() && config.threads > 1 {
+        return Err("Error: Only searches on one thread can be traced.".to_string());
+    }
+
+    if config.stats && config.threads > 1 {
+        return Err("Error: Only searches on one thread collect statistics.".to_string());
+    }
+
+    if tree_file.is_some() && (config.engine == Engine::Depth || config.threads > 1) {
+        return Err("Error: Only the bfs and bytecode engines on one thread record a search tree.".to_string());
+    }
+    config.search_tree = tree_file.map(|file| (file, tree_depth));
+
+    if output.is_some() && !matches!(command, Command::Compile) {
+        return Err("Error: Only compile takes '-o'.".to_string());
+    }
+
+    match (command, file_name) {
+        (Command::Fmt | Command::Compile, None) => Err("Error: Expected a source file.".to_string()),
+        (Command::Exec, None) => Err("Error: Expected a compiled program.".to_string()),
+        (Command::Run, None) => Ok(Options { command : Command::Repl, file_name : None, config, dump_ir, output }),
+        (command, file_name) => Ok(Options { command, file_name, config, dump_ir, output }),
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-     if args.len() != 2 {
-        eprintln!("Error: Expected one argument, but got {}.", args.len() - 1);
-        eprintln!("Usage: {} source_file", args[0]);
-        process::exit(1);
-    }
+    let opts = match parse_args(&args[1..]) {
+        Ok(opts) => opts,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            eprintln!("{}", usage(&args[0]));
+            process::exit(1);
+        }
+    };
 
-    let file_name = &args[1];
+    let file_name = match opts.file_name {
+        Some(ref file_name) if !matches!(opts.command, Command::Repl) => file_name,
+        _ => {
+            let stdin = io::stdin();
+            return repl::Repl::new(stdin.lock(), io::stdout(), opts.config).run(opts.file_name)
+        }
+    };
+
+    if let Command::Exec = opts.command {
+        let bytes = match fs::read(file_name) {
+            Ok(bytes) => bytes,
+            Err(error) => {
+                eprintln!("Error: Could not read file '{}': {}", file_name, error);
+                process::exit(1);
+            }
+        };
+        match Compiled::decode(&bytes) {
+            Ok(program) => return execute(program, opts.config, opts.dump_ir),
+            Err(error) => {
+                eprintln!("Error: Could not load file '{}': {}", file_name, error);
+                process::exit(1);
+            }
+        }
+    }
 
     let mut file = match File::open(file_name) {
         Ok(file) => file,
@@ -31,7 +198,31 @@ fn main() {
 
     // Try to read the file contents
     match file.read_to_string(&mut src) {
-        Ok(_) => { interpret(&mut src); }
+        Ok(_) => match opts.command {
+            Command::Run => execute(compile(file_name, &src), opts.config, opts.dump_ir),
+            Command::Compile => {
+                let output = opts.output.map(PathBuf::from).unwrap_or_else(|| Path::new(file_name).with_extension("flpc"));
+                let bytes = match compile(file_name, &src).encode() {
+                    Ok(bytes) => bytes,
+                    Err(error) => {
+                        eprintln!("Error: Could not compile file '{}': {}", file_name, error);
+                        process::exit(1);
+                    }
+                };
+                if let Err(error) = fs::write(&output, bytes) {
+                    eprintln!("Error: Could not write file '{}': {}", output.display(), error);
+                    process::exit(1);
+                }
+            },
+            Command::Repl | Command::Exec => unreachable!(),
+            Command::Fmt => match parser::format::format(&src) {
+                Ok(out) => print!("{}", out),
+                Err(error) => {
+                    eprintln!("Error: Could not parse file '{}': {}", file_name, error);
+                    process::exit(1);
+                }
+            },
+        },
         Err(error) => {
             eprintln!("Error: Could not read file '{}': {}", file_name, error);
             process::exit(1);
@@ -39,9 +230,21 @@ fn main() {
     };
 }
 
-fn interpret(src: &mut String) {
+fn compile(file_name : &str, src : &str) -> Compiled {
+    match Program::compile(src).and_then(|program| program.compiled()) {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("Error: Could not compile file '{}': {}", file_name, error);
+            process::exit(1);
+        }
+    }
+}
 
-    let ast = parser::parse(src).unwrap();
-    let (main, env) = translate(ast);
-    machine::eval(main, env.into());
+fn execute(program : Compiled, config : Config, dump_ir : Option<bool>) {
+    let Compiled { query, env, ptype, names } = program;
+    if let Some(with_names) = dump_ir {
+        print!("{}", pretty::dump(&query, &env, with_names.then_some(&names[..])));
+        return
+    }
+    machine::eval(query, env, ptype, &names, config);
 }
@@ -1,6 +1,6 @@
 use crate::parser::expr::Expr;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub struct Cases {
     pub r#type: Option<CasesType>,
     pub nat_case: Option<CasesNat>,
@@ -40,50 +40,45 @@ impl Cases {
         Cases { r#type: None, nat_case: None, list_case: None }
     }
 
-    fn initialize_nat_case(&mut self) {
-        if self.nat_case.is_none() {
-            self.nat_case = Some(CasesNat::new());
+    pub fn set_type_or_check(&mut self, r#type: CasesType) -> Result<(), String> {
+        match &self.r#type {
+            Some(t) if *t != r#type => Err("a case has arms for both numbers and lists".to_string()),
+            Some(_) => Ok(()),
+            None => { self.r#type = Some(r#type); Ok(()) },
         }
     }
 
-    fn initialize_list_case(&mut self) {
-        if self.list_case.is_none() {
-            self.list_case = Some(CasesList::new());
-        }
+    pub fn set_nat_zero(&mut self, expr: Expr) -> Result<(), String> {
+        let case = self.nat_case.get_or_insert_with(CasesNat::new);
+        if case.zk.is_some() { return Err(twice("Z")) }
+        case.zk = Some(expr);
+        Ok(())
     }
 
-    pub fn set_type_or_check(&mut self, r#type: CasesType) {
-        if self.r#type.is_some() {
-            let t = self.r#type.as_mut().unwrap();
-            if *t != r#type { panic!("bad cases") }
-        } else {
-            self.r#type = Some(r#type);
-        }
+    pub fn set_nat_succ(&mut self, var: String, expr: Expr) -> Result<(), String> {
+        let case = self.nat_case.get_or_insert_with(CasesNat::new);
+        if case.sk.is_some() { return Err(twice("S")) }
+        case.sk = Some(CasesNatSucc { var, expr });
+        Ok(())
     }
 
-    pub fn set_nat_zero(&mut self, expr: Expr) {
-        self.initialize_nat_case();
-        if self.nat_case.as_ref().unwrap().zk.is_some() { panic!("zero case already set") }
-        self.nat_case.as_mut().unwrap().zk = Some(expr);
+    pub fn set_list_nil(&mut self, expr: Expr) -> Result<(), String> {
+        let case = self.list_case.get_or_insert_with(CasesList::new);
+        if case.nilk.is_some() { return Err(twice("[]")) }
+        case.nilk = Some(expr);
+        Ok(())
     }
 
-    pub fn set_nat_succ(&mut self, var: String, expr: Expr) {
-        self.initialize_nat_case();
-        if self.nat_case.as_ref().unwrap().sk.is_some() { panic!("succ case already set") }
-        self.nat_case.as_mut().unwrap().sk = Some(CasesNatSucc { var, expr })
-    }
-
-    pub fn set_list_nil(&mut self, expr: Expr) {
-        self.initialize_list_case();
-        if self.list_case.as_ref().unwrap().nilk.is_some() { panic!("nil case already set") }
-        self.list_case.as_mut().unwrap().nilk = Some(expr);
+    pub fn set_list_cons(&mut self, x: String, xs: String, expr: Expr) -> Result<(), String> {
+        let case = self.list_case.get_or_insert_with(CasesList::new);
+        if case.consk.is_some() { return Err(twice(":")) }
+        case.consk = Some(CasesListCons { x, xs, expr });
+        Ok(())
     }
+}
 
-    pub fn set_list_cons(&mut self, x: String, xs: String, expr: Expr) {
-        self.initialize_list_case();
-        if self.list_case.as_ref().unwrap().consk.is_some() { panic!("cons case already set") }
-        self.list_case.as_mut().unwrap().consk = Some(CasesListCons { x, xs, expr })
-    }
+fn twice(constructor: &str) -> String {
+    format!("a case has two arms for {}", constructor)
 }
 
 impl CasesNat {
@@ -12,5 +12,8 @@ pub enum Decl {
         args: Vec<Arg>,
         body: Stm
     },
+    Table {
+        name: String
+    },
     Stm(Stm)
 }
\ No newline at end of file
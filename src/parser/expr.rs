@@ -24,12 +24,10 @@ impl Expr {
             match e {
                 Expr::Stm(stm) => match *stm {
                     Stm::Expr(expr) => e = expr,
-                    stm => e = Expr::Stm(Box::new(stm))
+                    stm => return Expr::Stm(Box::new(stm))
                 },
-                _ => break
+                e => return e
             }
         }
-
-        e
     }
 }
\ No newline at end of file
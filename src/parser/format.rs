@@ -0,0 +1,249 @@
+use pest::error::Error;
+
+use super::{arg::Arg, bexpr::BExpr, cases::Cases, decl::Decl, expr::Expr, parse_spanned, r#type::Type, stm::Stm, Rule};
+
+// Declarations longer than this are broken after every step of their
+// statement
+const WIDTH: usize = 80;
+
+// Re-emits a program in the canonical layout: the declarations of a function
+// (its table, type and definition) form one paragraph, statements are on one
+// line unless too long, and case arms go on their own lines with their arrows
+// aligned. Comments are kept: one after the end of a declaration stays at
+// the end of its last line, and one inside it moves above it, since its
+// lines are laid out anew.
+pub fn format(src: &str) -> Result<String, Box<Error<Rule>>> {
+    let decls = parse_spanned(src)?;
+    let mut comments = comments(src).into_iter().peekable();
+
+    let mut paragraphs: Vec<String> = vec![];
+    let mut last = None;
+    for (end, decl) in &decls {
+        let mut text = String::new();
+        let mut trailing = None;
+        // a comment belongs to the declaration it is in or ends the line of
+        while let Some((at, comment)) = comments.next_if(|(at, _)| at < end || !src[*end..*at].contains('\n')) {
+            if at < *end {
+                text += comment;
+                text += "\n";
+            } else {
+                trailing = Some(comment)
+            }
+        }
+        text += &declaration(decl);
+        if let Some(comment) = trailing {
+            text += "  ";
+            text += comment
+        }
+
+        let name = name(decl);
+        match paragraphs.last_mut() {
+            Some(paragraph) if name.is_some() && name == last => {
+                *paragraph += "\n";
+                *paragraph += &text
+            },
+            _ => paragraphs.push(text)
+        }
+        last = name;
+    }
+
+    let rest: Vec<&str> = comments.map(|(_, comment)| comment).collect();
+    if !rest.is_empty() {
+        paragraphs.push(rest.join("\n"))
+    }
+
+    Ok(paragraphs.join("\n\n") + "\n")
+}
+
+// The `--` comments of a source with their offsets. The language has no
+// strings, so every `--` starts one.
+fn comments(src: &str) -> Vec<(usize, &str)> {
+    let mut comments = vec![];
+    let mut offset = 0;
+    for line in src.split_inclusive('\n') {
+        if let Some(at) = line.find("--") {
+            comments.push((offset + at, line[at..].trim_end()))
+        }
+        offset += line.len();
+    }
+    comments
+}
+
+// The function a declaration belongs to
+fn name(decl: &Decl) -> Option<&str> {
+    match decl {
+        Decl::FuncType { name, .. } | Decl::Func { name, .. } | Decl::Table { name } => Some(name),
+        Decl::Stm(_) => None
+    }
+}
+
+fn declaration(decl: &Decl) -> String {
+    match decl {
+        Decl::Table { name } => format!("table {}", name),
+        Decl::FuncType { name, r#type } => format!("{} :: {}", name, print_type(r#type)),
+        Decl::Func { name, args, body } => {
+            let mut head = vec![name.clone()];
+            head.extend(args.iter().map(argument));
+            format!("{} = {}.", head.join(" "), body_layout(body, head.join(" ").len() + 3, 4))
+        },
+        Decl::Stm(stm) => format!("{}.", body_layout(stm, 0, 0)),
+    }
+}
+
+// A statement on one line if it fits after `column`, otherwise broken after
+// each step and continued at `indent`
+fn body_layout(stm: &Stm, column: usize, indent: usize) -> String {
+    let flat = statement(stm, 0, None);
+    if !flat.contains('\n') && column + flat.len() < WIDTH { flat } else { statement(stm, 0, Some(indent)) }
+}
+
+fn argument(arg: &Arg) -> String {
+    match arg {
+        Arg::Ident(x) => x.clone(),
+        Arg::Pair(a, b) => format!("({}, {})", argument(a), argument(b))
+    }
+}
+
+pub(crate) fn print_type(t: &Type) -> String {
+    match t {
+        Type::Arrow(a, b) => format!("{} -> {}", primary_type(a), print_type(b)),
+        Type::Product(a, b) => format!("{} * {}", primary_type(a), print_type(b)),
+        Type::Ident(x) => x.clone(),
+        Type::List(t) => format!("[{}]", print_type(t))
+    }
+}
+
+fn primary_type(t: &Type) -> String {
+    match t {
+        Type::Arrow(_, _) | Type::Product(_, _) => format!("({})", print_type(t)),
+        _ => print_type(t)
+    }
+}
+
+// Prints a statement starting on a line indented by `indent`. With `steps`,
+// the steps of exists, equations and lets go on lines of their own, indented
+// by it.
+fn statement(stm: &Stm, indent: usize, steps: Option<usize>) -> String {
+    let (sep, next) = match steps {
+        Some(n) => (format!("\n{}", " ".repeat(n)), n),
+        None => (" ".to_string(), indent)
+    };
+
+    match stm {
+        Stm::If { cond, then, r#else } => format!("if {} then {} else {}",
+            statement(cond, indent, None), statement(then, indent, None), statement(r#else, indent, None)),
+        Stm::Let { var, val, body } =>
+            format!("let {} = {} in{}{}", var, statement(val, indent, None), sep, statement(body, next, steps)),
+        Stm::Exists { var, r#type, body } =>
+            format!("exists {} :: {}.{}{}", var, print_type(r#type), sep, statement(body, next, steps)),
+        Stm::Equate { lhs, rhs, body } =>
+            format!("{} =:= {}.{}{}", expression(lhs, indent), expression(rhs, indent), sep, statement(body, next, steps)),
+        Stm::Choice(exprs) => {
+            let exprs: Vec<String> = exprs.iter().map(|e| expression(e, indent)).collect();
+            exprs.join(" <> ")
+        },
+        Stm::Case { expr, cases } => format!("case {} of{}", expression(expr, indent), arms(cases, indent)),
+        Stm::Expr(e) => expression(e, indent)
+    }
+}
+
+// The arms of a case, one per line with the arrows aligned. Nat cases come
+// in the order `Z`, `S`, list cases `[]`, `:`.
+fn arms(cases: &Cases, indent: usize) -> String {
+    let mut arms = vec![];
+    if let Some(nat) = &cases.nat_case {
+        if let Some(zk) = &nat.zk { arms.push(("Z".to_string(), zk)) }
+        if let Some(sk) = &nat.sk { arms.push((format!("S {}", sk.var), &sk.expr)) }
+    }
+    if let Some(list) = &cases.list_case {
+        if let Some(nilk) = &list.nilk { arms.push(("[]".to_string(), nilk)) }
+        if let Some(consk) = &list.consk { arms.push((format!("({} : {})", consk.x, consk.xs), &consk.expr)) }
+    }
+
+    let width = arms.iter().map(|(pat, _)| pat.len()).max().unwrap_or(0);
+    let mut out = String::new();
+    for (i, (pat, body)) in arms.iter().enumerate() {
+        let bar = if i == 0 { "  " } else { "| " };
+        out += &format!("\n{}{}{:width$} -> {}", " ".repeat(indent + 2), bar, pat, expression(body, indent + 4), width = width);
+    }
+    out
+}
+
+fn expression(e: &Expr, indent: usize) -> String {
+    match e {
+        Expr::Zero => "Z".to_string(),
+        Expr::Succ(e) => format!("S {}", expression(e, indent)),
+        Expr::Nil => "[]".to_string(),
+        Expr::Cons(x, xs) => format!("{} : {}", expression(x, indent), expression(xs, indent)),
+        Expr::App(f, x) => format!("{} {}", expression(f, indent), expression(x, indent)),
+        Expr::BExpr(b) => match b {
+            BExpr::Eq(l, r) => format!("{} == {}", expression(l, indent), expression(r, indent)),
+            BExpr::NEq(l, r) => format!("{} != {}", expression(l, indent), expression(r, indent)),
+            BExpr::And(l, r) => format!("{} && {}", expression(l, indent), expression(r, indent)),
+            BExpr::Or(l, r) => format!("{} || {}", expression(l, indent), expression(r, indent)),
+            BExpr::Not(e) => format!("!{}", expression(e, indent))
+        },
+        Expr::List(xs) => {
+            let xs: Vec<String> = xs.iter().map(|x| expression(x, indent)).collect();
+            format!("[{}]", xs.join(", "))
+        },
+        Expr::Lambda(arg, body) => format!("\\{}. {}", argument(arg), statement(body, indent, None)),
+        Expr::Ident(x) => x.clone(),
+        Expr::Nat(n) => n.to_string(),
+        Expr::Bool(b) => b.to_string(),
+        Expr::Pair(a, b) => format!("({}, {})", expression(a, indent), expression(b, indent)),
+        // the parser keeps parentheses as nested statements
+        Expr::Stm(stm) => format!("({})", statement(stm, indent, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::parser::parse;
+    use super::*;
+
+    #[test]
+    fn layout() {
+        let src = "-- addition
+add :: Nat -> Nat -> Nat
+add n m = case m of S z -> S (add n z) | Z -> n.   -- by recursion on m
+table add
+double :: Nat -> Nat
+double n = case n of -- on n
+  Z -> Z | S m -> S (S (double m)).
+exists x::Nat. exists y :: Nat. add x y =:= 100. (x, y).
+-- the end";
+        assert_eq!(format(src).unwrap(), "-- addition
+add :: Nat -> Nat -> Nat
+add n m = case m of
+    Z   -> n
+  | S z -> S (add n z).  -- by recursion on m
+table add
+
+double :: Nat -> Nat
+-- on n
+double n = case n of
+    Z   -> Z
+  | S m -> S (S (double m)).
+
+exists x :: Nat. exists y :: Nat. add x y =:= 100. (x, y).
+
+-- the end
+");
+    }
+
+    #[test]
+    fn examples() {
+        for entry in fs::read_dir("examples").unwrap() {
+            let path = entry.unwrap().path();
+            let src = fs::read_to_string(&path).unwrap();
+            // some examples use syntax the parser doesn't have
+            if parse(&src).is_err() { continue }
+            let formatted = format(&src).unwrap();
+            assert_eq!(parse(&formatted).unwrap(), parse(&src).unwrap(), "{}", path.display());
+            assert_eq!(format(&formatted).unwrap(), formatted, "{}", path.display());
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use pest::{error::Error, Parser};
+use pest::{error::{Error, ErrorVariant}, iterators::Pair, Parser};
 use pest_derive::Parser;
 
 use crate::parser::{arg::Arg, bexpr::BExpr, cases::{Cases, CasesType}, decl::Decl, expr::Expr, stm::Stm, r#type::Type};
@@ -10,63 +10,95 @@ pub mod r#type;
 pub mod stm;
 pub mod expr;
 pub mod bexpr;
+pub mod format;
 
 #[derive(Parser)]
 #[grammar = "parser/lang.pest"]
 struct FLPParser;
 
-pub fn parse(src: &str) -> Result<Vec<Decl>, Error<Rule>> {
+pub fn parse(src: &str) -> Result<Vec<Decl>, Box<Error<Rule>>> {
+    Ok(parse_spanned(src)?.into_iter().map(|(_, decl)| decl).collect())
+}
+
+// Parses a program, pairing each declaration with the offset of its end in
+// the source
+pub fn parse_spanned(src: &str) -> Result<Vec<(usize, Decl)>, Box<Error<Rule>>> {
     let mut prog = vec![];
 
     let pairs = FLPParser::parse(Rule::program, src)?;
     for pair in pairs {
-        match pair.as_rule() {
-            Rule::declaration => {
-                let pair: pest::iterators::Pair<Rule> = pair.into_inner().next().unwrap();
-
-                prog.push(match pair.as_rule() {
-                    Rule::function_type => {
-                        let mut pair = pair.into_inner();
+        if pair.as_rule() == Rule::declaration {
+            let end = pair.as_span().end();
+            let pair: pest::iterators::Pair<Rule> = pair.into_inner().next().unwrap();
 
-                        let name = pair.next().unwrap().as_str();
-                        let r#type = parse_type(pair.next().unwrap().into_inner());
-
-                        Decl::FuncType { name: name.to_string(), r#type }
-                    },
-                    Rule::function => {
-                        let mut pair = pair.into_inner();
-
-                        let name = pair.next().unwrap().as_str();
-                        
-                        let mut args = vec![];
-                        let body;
-                        loop {
-                            let pair = pair.next().unwrap();
-
-                            match pair.as_rule() {
-                                Rule::argument => args.push(parse_argument(pair.into_inner())),
-                                _ => {
-                                    body = pair;
-                                    break;
-                                }
-                            }
-                        };
-
-                        let body = parse_statement(body.into_inner());
-
-                        Decl::Func { name: name.to_string(), args, body }
-                    },
-                    Rule::statement => Decl::Stm(parse_statement(pair.into_inner())),
-                    _ => unreachable!()
-                })
-            },
-            _ => ()
+            check(pair.clone())?;
+            prog.push((end, parse_declaration(pair)))
         }
     }
 
     Ok(prog)
 }
 
+// Rejects what the grammar accepts but the syntax tree can't hold: numbers
+// too large and case arms that don't make one case on numbers or lists
+fn check(pair: Pair<Rule>) -> Result<(), Box<Error<Rule>>> {
+    for inner in pair.clone().into_inner() {
+        check(inner)?
+    }
+    let invalid = match pair.as_rule() {
+        Rule::number => pair.as_str().parse::<usize>().err().map(|_| "the number is too large".to_string()),
+        Rule::cases => parse_cases(pair.clone().into_inner()).err(),
+        _ => None,
+    };
+    match invalid {
+        Some(message) => Err(Box::new(Error::new_from_span(ErrorVariant::CustomError { message }, pair.as_span()))),
+        None => Ok(()),
+    }
+}
+
+fn parse_declaration(pair: pest::iterators::Pair<Rule>) -> Decl {
+    match pair.as_rule() {
+        Rule::table => {
+            let name = pair.into_inner().nth(1).unwrap().as_str();
+
+            Decl::Table { name: name.to_string() }
+        },
+        Rule::function_type => {
+            let mut pair = pair.into_inner();
+
+            let name = pair.next().unwrap().as_str();
+            let r#type = parse_type(pair.next().unwrap().into_inner());
+
+            Decl::FuncType { name: name.to_string(), r#type }
+        },
+        Rule::function => {
+            let mut pair = pair.into_inner();
+
+            let name = pair.next().unwrap().as_str();
+        
+            let mut args = vec![];
+            let body;
+            loop {
+                let pair = pair.next().unwrap();
+
+                match pair.as_rule() {
+                    Rule::argument => args.push(parse_argument(pair.into_inner())),
+                    _ => {
+                        body = pair;
+                        break;
+                    }
+                }
+            };
+
+            let body = parse_statement(body.into_inner());
+
+            Decl::Func { name: name.to_string(), args, body }
+        },
+        Rule::statement => Decl::Stm(parse_statement(pair.into_inner())),
+        _ => unreachable!()
+    }
+}
+
 fn parse_argument(mut pairs: pest::iterators::Pairs<Rule>) -> Arg {
     let pair = pairs.next().unwrap();
 
@@ -128,11 +160,8 @@ fn parse_statement(mut pairs: pest::iterators::Pairs<Rule>) -> Stm {
             let mut pairs = pair.into_inner();
 
             let mut choice = vec![];
-            loop {
-                match pairs.next() {
-                    Some(p) => choice.push(parse_expression(p.into_inner())),
-                    None => break
-                }
+            for p in pairs {
+                choice.push(parse_expression(p.into_inner()))
             }
 
             Stm::Choice(choice)
@@ -141,7 +170,7 @@ fn parse_statement(mut pairs: pest::iterators::Pairs<Rule>) -> Stm {
             let mut pairs = pair.into_inner();
 
             let expr = parse_expression(pairs.next().unwrap().into_inner());
-            let cases = parse_cases(pairs.next().unwrap().into_inner());
+            let cases = parse_cases(pairs.next().unwrap().into_inner()).expect("the cases were checked");
 
             Stm::Case { expr, cases }
         },
@@ -171,11 +200,8 @@ fn parse_expression(mut pairs: pest::iterators::Pairs<Rule>) -> Expr {
             let mut pairs = pair.into_inner();
 
             let mut exprs = vec![];
-            loop {
-                match pairs.next() {
-                    Some(e) => exprs.push(parse_expression(e.into_inner())),
-                    None => break,
-                }
+            for e in pairs {
+                exprs.push(parse_expression(e.into_inner()))
             }
 
             exprs.iter().fold(None, |acc, x| {
@@ -218,57 +244,49 @@ fn parse_expression(mut pairs: pest::iterators::Pairs<Rule>) -> Expr {
     }
 }
 
-fn parse_cases(mut pairs: pest::iterators::Pairs<Rule>) -> Cases {
+fn parse_cases(mut pairs: pest::iterators::Pairs<Rule>) -> Result<Cases, String> {
     let mut cases = Cases::new();
 
-    loop {
-        match pairs.next() {
-            Some(p) => {
-                let expr = parse_expression(p.into_inner());
-                let body = parse_expression(pairs.next().unwrap().into_inner());
+    while let Some(p) = pairs.next() {
+        let expr = parse_expression(p.into_inner());
+        let body = parse_expression(pairs.next().unwrap().into_inner());
 
-                match expr.strip_parentheses() {
-                    Expr::Zero => {
-                        cases.set_type_or_check(CasesType::Nat);
-                        cases.set_nat_zero(body);
-                    },
-                    Expr::Succ(e) => {
-                        let var = match *e {
-                            Expr::Ident(s) => s,
-                            _ => panic!("expected identifier")
-                        };
-
-                        cases.set_type_or_check(CasesType::Nat);
-                        cases.set_nat_succ(var, body);
-                    },
-                    Expr::Nil => {
-                        cases.set_type_or_check(CasesType::List);
-                        cases.set_list_nil(body);
-                    },
-                    Expr::Cons(e1, e2) => {
-                        let x = match *e1 {
-                            Expr::Ident(s) => s,
-                            _ => panic!("expected identifier")
-                        };
-
-                        let xs = match *e2 {
-                            Expr::Ident(s) => s,
-                            _ => panic!("expected identifier")
-                        };
-
-                        cases.set_type_or_check(CasesType::List);
-                        cases.set_list_cons(x, xs, body);
-                    },
-                    _ => panic!("bad cases")
-                }
+        match expr.strip_parentheses() {
+            Expr::Zero => {
+                cases.set_type_or_check(CasesType::Nat)?;
+                cases.set_nat_zero(body)?;
+            },
+            Expr::Succ(e) => {
+                let var = match *e {
+                    Expr::Ident(s) => s,
+                    _ => return Err(EXPECTED_PATTERN.to_string())
+                };
+
+                cases.set_type_or_check(CasesType::Nat)?;
+                cases.set_nat_succ(var, body)?;
             },
-            None => break
+            Expr::Nil => {
+                cases.set_type_or_check(CasesType::List)?;
+                cases.set_list_nil(body)?;
+            },
+            Expr::Cons(e1, e2) => {
+                let (x, xs) = match (*e1, *e2) {
+                    (Expr::Ident(x), Expr::Ident(xs)) => (x, xs),
+                    _ => return Err(EXPECTED_PATTERN.to_string())
+                };
+
+                cases.set_type_or_check(CasesType::List)?;
+                cases.set_list_cons(x, xs, body)?;
+            },
+            _ => return Err(EXPECTED_PATTERN.to_string())
         }
     }
 
-    cases
+    Ok(cases)
 }
 
+const EXPECTED_PATTERN: &str = "expected a pattern Z, S n, [] or x : xs";
+
 fn parse_boolean_expression(mut pairs: pest::iterators::Pairs<Rule>) -> BExpr {
     let pair = pairs.next().unwrap();
 
@@ -301,11 +319,8 @@ fn parse_boolean_expression(mut pairs: pest::iterators::Pairs<Rule>) -> BExpr {
 fn parse_list(mut pairs: pest::iterators::Pairs<Rule>) -> Vec<Expr> {
     let mut list = vec![];
 
-    loop {
-        match pairs.next() {
-            Some(pair) => list.push(parse_expression(pair.into_inner())),
-            None => break
-        }
+    for pair in pairs {
+        list.push(parse_expression(pair.into_inner()))
     }
 
     list
@@ -870,4 +885,76 @@ id x = x.
             ))))]
         )
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test21() {
+        let src = "table reach
+reach x = x.
+
+tabled x = x.";
+
+        let ast = parse(src).unwrap();
+
+        assert_eq!(
+            ast,
+            vec![
+                Decl::Table { name: "reach".to_string() },
+                Decl::Func {
+                    name: "reach".to_string(),
+                    args: vec![Arg::Ident("x".to_string())],
+                    body: Stm::Expr(Expr::Ident("x".to_string()))
+                },
+                Decl::Func {
+                    name: "tabled".to_string(),
+                    args: vec![Arg::Ident("x".to_string())],
+                    body: Stm::Expr(Expr::Ident("x".to_string()))
+                }
+            ]
+        )
+    }
+
+    #[test]
+    fn test22() {
+        let src = "table x = x.
+
+table reach -- memoised
+reach x = table x.";
+
+        let ast = parse(src).unwrap();
+
+        assert_eq!(
+            ast,
+            vec![
+                Decl::Func {
+                    name: "table".to_string(),
+                    args: vec![Arg::Ident("x".to_string())],
+                    body: Stm::Expr(Expr::Ident("x".to_string()))
+                },
+                Decl::Table { name: "reach".to_string() },
+                Decl::Func {
+                    name: "reach".to_string(),
+                    args: vec![Arg::Ident("x".to_string())],
+                    body: Stm::Expr(Expr::App(
+                        Box::new(Expr::Ident("table".to_string())),
+                        Box::new(Expr::Ident("x".to_string()))
+                    ))
+                }
+            ]
+        )
+    }
+
+    #[test]
+    fn invalid() {
+        let message = |src: &str| match parse(src).map_err(|e| e.variant) {
+            Err(ErrorVariant::CustomError { message }) => message,
+            e => panic!("expected a custom error, found {:?}", e),
+        };
+        assert_eq!(message("case x of Z -> 1 | [] -> 2."), "a case has arms for both numbers and lists");
+        assert_eq!(message("case x of Z -> 1 | Z -> 2."), "a case has two arms for Z");
+        assert_eq!(message("case x of y : ys -> 1 | z : zs -> 2."), "a case has two arms for :");
+        assert_eq!(message("case x of S Z -> 1."), "expected a pattern Z, S n, [] or x : xs");
+        assert_eq!(message("case x of 1 -> 1."), "expected a pattern Z, S n, [] or x : xs");
+        assert_eq!(message("f x = case x of Z -> (case x of Z -> 1 | Z -> 2) | S n -> n."), "a case has two arms for Z");
+        assert_eq!(message("99999999999999999999999."), "the number is too large");
+    }
+}
@@ -0,0 +1,376 @@
+use std::{fmt::Display, marker::PhantomData};
+
+use crate::machine::{self, builtin::Builtin, compiled::Compiled, limits::LimitHit, mterms::MValue, translate::{value_type, Globals, TranslateError}, Answer, Answers, Config, Failure, RuntimeError, ValueType};
+use crate::parser::{self, decl::Decl, format::print_type, r#type::Type, stm::Stm};
+use crate::value::{FromValue, HasType, IntoValue, TypeError};
+
+// Why a program or query couldn't be run, or a branch of its search failed
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    Parse(String),
+    // a variable used where nothing of its name is in scope
+    Unbound(String),
+    // a construct that can be parsed but not yet run, as described
+    Unsupported(String),
+    // the source has no query to run
+    NoQuery,
+    // a builtin's type isn't a function between first-order types
+    Signature(String),
+    // a function called from Rust whose declared type isn't the one the
+    // argument and result types stand for
+    Call { name : String, expected : String, found : String },
+    // an answer that doesn't decode to the Rust type asked for
+    Type(TypeError),
+    Runtime(RuntimeError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Parse(e) => write!(f, "{}", e),
+            Error::Unbound(v) => write!(f, "Variable {} not found in environment", v),
+            Error::Unsupported(what) => write!(f, "Translating {} isn't supported yet", what),
+            Error::NoQuery => write!(f, "The program has no query."),
+            Error::Signature(s) => write!(f, "Expected the type of a function between first-order values, found '{}'.", s),
+            Error::Call { name, expected, found } => write!(f, "{} has type {}, but was called as {}.", name, found, expected),
+            Error::Type(e) => write!(f, "{}", e),
+            Error::Runtime(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<TranslateError> for Error {
+    fn from(e : TranslateError) -> Error {
+        match e {
+            TranslateError::Unbound(v) => Error::Unbound(v.0),
+            TranslateError::Unsupported(what) => Error::Unsupported(what),
+            TranslateError::NoQuery => Error::NoQuery,
+        }
+    }
+}
+
+// The translated functions of sources and builtins, against which queries
+// are solved with the options of `config`
+pub struct Program {
+    globals : Globals,
+    // the statements among the definitions, in order
+    queries : Vec<Stm>,
+    config : Config,
+}
+
+impl Default for Program {
+    fn default() -> Program { Program::new() }
+}
+
+impl Program {
+
+    pub fn new() -> Program {
+        Program { globals : Globals::new(), queries : vec![], config : Config::default() }
+    }
+
+    pub fn compile(src : &str) -> Result<Program, Error> {
+        Program::new().define(src)
+    }
+
+    // Adds the functions and queries of `src`, which may use those defined
+    // before
+    pub fn define(mut self, src : &str) -> Result<Program, Error> {
+        let decls = parser::parse(src).map_err(|e| Error::Parse(e.to_string()))?;
+        let queries = self.globals.define(decls)?;
+        self.queries.extend(queries);
+        Ok(self)
+    }
+
+    // Adds the host function `call` with the name and type of `signature`,
+    // as in `lookup_price :: Nat -> Nat`. A call waits until its arguments
+    // are ground, if need be until the rest of its branch has bound them,
+    // and each value `call` returns for them is a branch of the call. A
+    // value not of the result type fails the branch.
+    pub fn with_builtin(mut self, signature : &str, call : impl Fn(&[MValue]) -> Vec<MValue> + Send + Sync + 'static) -> Result<Program, Error> {
+        let (name, r#type) = match parser::parse(signature).map_err(|e| Error::Parse(e.to_string()))?.as_slice() {
+            [Decl::FuncType { name, r#type }] => (name.clone(), r#type.clone()),
+            _ => return Err(Error::Parse("Expected a type signature.".to_string())),
+        };
+        let first_order = |t| value_type(t).ok_or_else(|| Error::Signature(signature.to_string()));
+        let mut params = vec![];
+        let mut result = &r#type;
+        while let Type::Arrow(arg, rest) = result {
+            params.push(first_order(arg)?);
+            result = rest;
+        }
+        let result = first_order(result)?;
+        self.globals.builtin(Builtin::new(&name, params, result, call), r#type);
+        Ok(self)
+    }
+
+    pub fn with_config(self, config : Config) -> Program {
+        Program { config, ..self }
+    }
+
+    // The answers of `src`, a statement with or without its final '.'.
+    // A query that doesn't parse or translate gives its error as the only
+    // item.
+    pub fn query(&self, src : &str) -> Solutions {
+        match statement(src).and_then(|stm| self.solve(stm)) {
+            Ok(answers) => Solutions { answers : Some(answers), error : None },
+            Err(e) => Solutions { answers : None, error : Some(e) },
+        }
+    }
+
+    // The answers of the source's last query, which is what running the
+    // source prints
+    pub fn run(&self) -> Solutions {
+        match self.queries.last().cloned().ok_or(Error::NoQuery).and_then(|stm| self.solve(stm)) {
+            Ok(answers) => Solutions { answers : Some(answers), error : None },
+            Err(e) => Solutions { answers : None, error : Some(e) },
+        }
+    }
+
+    fn solve(&self, stm : Stm) -> Result<Answers, Error> {
+        let ptype = self.globals.query_type(&stm);
+        let comp = self.globals.query(stm)?;
+        Ok(machine::answers(comp, self.globals.env(), ptype, &self.config))
+    }
+
+    // The results of the function `name` on `arg`, decoded as `T`s. A
+    // declared type of `name` is checked against the types `arg` and `T`
+    // stand for before anything is run.
+    pub fn call<T : FromValue>(&self, name : &str, arg : impl IntoValue) -> TypedSolutions<T> {
+        let answers = self.check_call(name, type_of(&arg), T::value_type())
+            .and_then(|_| self.globals.call(name, arg.into_value()).map_err(|e| Error::Unbound(e.0)))
+            .map(|comp| machine::answers(comp, self.globals.env(), Some(T::value_type()), &self.config));
+        let solutions = match answers {
+            Ok(answers) => Solutions { answers : Some(answers), error : None },
+            Err(e) => Solutions { answers : None, error : Some(e) },
+        };
+        TypedSolutions { solutions, result : PhantomData }
+    }
+
+    fn check_call(&self, name : &str, arg : ValueType, result : ValueType) -> Result<(), Error> {
+        match self.globals.function_type(name) {
+            Some(Type::Arrow(a, r)) if value_type(a).as_ref() == Some(&arg) && value_type(r).as_ref() == Some(&result) => Ok(()),
+            Some(found) => Err(Error::Call { name : name.to_string(), expected : format!("{} -> {}", arg, result), found : print_type(found) }),
+            None => Ok(()),
+        }
+    }
+
+    // The program with its last query translated, to save or evaluate
+    pub fn compiled(&self) -> Result<Compiled, Error> {
+        let stm = self.queries.last().cloned().ok_or(Error::NoQuery)?;
+        let ptype = self.globals.query_type(&stm);
+        let query = self.globals.query(stm)?;
+        Ok(Compiled { query, env : self.globals.env(), ptype, names : self.globals.names().to_vec() })
+    }
+
+    pub fn config(&self) -> &Config { &self.config }
+}
+
+// The answers of a query, searched for as they are asked for
+pub struct Solutions {
+    answers : Option<Answers>,
+    // why the query couldn't be run, given before anything else
+    error : Option<Error>,
+}
+
+impl Solutions {
+
+    pub fn steps(&self) -> usize {
+        self.answers.as_ref().map_or(0, Answers::steps)
+    }
+
+    pub fn limit_hit(&self) -> Option<LimitHit> {
+        self.answers.as_ref().and_then(Answers::limit_hit)
+    }
+
+    // The branches that ended in runtime errors
+    pub fn failures(&self) -> &[Failure] {
+        self.answers.as_ref().map_or(&[], Answers::failures)
+    }
+}
+
+// The answers of a call decoded as `T`s, an answer that doesn't decode
+// giving a type error
+pub struct TypedSolutions<T> {
+    solutions : Solutions,
+    result : PhantomData<T>,
+}
+
+impl<T> TypedSolutions<T> {
+
+    pub fn solutions(&self) -> &Solutions { &self.solutions }
+}
+
+impl<T : FromValue> Iterator for TypedSolutions<T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Result<T, Error>> {
+        self.solutions.next().map(|answer| answer?.get().map_err(Error::Type))
+    }
+}
+
+impl Iterator for Solutions {
+    type Item = Result<Answer, Error>;
+
+    fn next(&mut self) -> Option<Result<Answer, Error>> {
+        if let Some(e) = self.error.take() { return Some(Err(e)) }
+        self.answers.as_mut()?.next().map(|answer| answer.map_err(Error::Runtime))
+    }
+}
+
+fn type_of<A : HasType>(_ : &A) -> ValueType { A::value_type() }
+
+// A single statement, with or without its final '.'
+pub(crate) fn statement(src : &str) -> Result<Stm, Error> {
+    let src = format!("{}.", src.trim().trim_end_matches('.'));
+    match parser::parse(&src) {
+        Ok(mut decls) if decls.len() == 1 => match decls.pop() {
+            Some(Decl::Stm(stm)) => Ok(stm),
+            _ => Err(Error::Parse("Expected a query.".to_string())),
+        },
+        Ok(_) => Err(Error::Parse("Expected a query.".to_string())),
+        Err(e) => Err(Error::Parse(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{machine::Engine, value::Value};
+    use super::*;
+
+    const SRC : &str = "add :: Nat -> Nat -> Nat
+add n m = case m of
+    Z -> n
+  | S z -> S (add n z).
+
+exists x :: Nat. exists y :: Nat. add x y =:= 2. (x, y).";
+
+    fn values(solutions : Solutions) -> Vec<Value> {
+        solutions.map(|answer| answer.unwrap().value()).collect()
+    }
+
+    #[test]
+    fn queries() {
+        let program = Program::compile(SRC).unwrap();
+        let pair = |x, y| Value::Pair(Box::new(Value::Nat(x)), Box::new(Value::Nat(y)));
+        assert_eq!(values(program.run()), [pair(2, 0), pair(1, 1), pair(0, 2)]);
+        assert_eq!(values(program.query("add 2 3")), [Value::Nat(5)]);
+        assert_eq!(values(program.query("exists xs :: [Nat]. xs =:= [1, 2]. xs")), [Value::List(vec![Value::Nat(1), Value::Nat(2)])]);
+
+        let program = program.with_config(Config { bindings : true, ..Config::default() });
+        let answer = program.query("exists x :: Nat. add x 1 =:= 3. S x").next().unwrap().unwrap();
+        assert_eq!(answer.binding("x"), Some(Value::Nat(2)));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(Program::compile("f x = "), Err(Error::Parse(_))));
+        assert_eq!(Program::compile("f :: Nat -> Nat\nf x = g x.").err(), Some(Error::Unbound("g".to_string())));
+        let program = Program::compile(SRC).unwrap();
+        assert_eq!(program.query("add z 1").next(), Some(Err(Error::Unbound("z".to_string()))));
+        assert_eq!(program.query("exists x :: Int. x").next(), Some(Err(Error::Unsupported("the type Int of the variable x".to_string()))));
+        assert!(matches!(program.query("add 1 1. 2. 3").next(), Some(Err(Error::Parse(_)))));
+        assert_eq!(Program::compile("").unwrap().run().next(), Some(Err(Error::NoQuery)));
+    }
+
+    fn shop() -> Program {
+        Program::new()
+            .with_builtin("lookup_price :: Nat -> Nat", |args| vec![(u64::from_value(&args[0]).unwrap() * 10).into_value()]).unwrap()
+            .with_builtin("divisors :: Nat -> Nat", |args| {
+                let n = u64::from_value(&args[0]).unwrap();
+                (1..=n).filter(|d| n % d == 0).map(u64::into_value).collect()
+            }).unwrap()
+            .define(SRC).unwrap()
+    }
+
+    #[test]
+    fn builtins() {
+        for engine in [Engine::Breadth, Engine::Depth, Engine::Bytecode] {
+            let program = shop().with_config(Config { engine, ..Config::default() });
+            assert_eq!(values(program.query("add (lookup_price 2) 1")), [Value::Nat(21)]);
+            let mut prices = values(program.query("exists d :: Nat. d =:= (divisors 12). lookup_price d"));
+            prices.sort_by_key(|v| if let Value::Nat(n) = v { *n } else { 0 });
+            assert_eq!(prices, [10, 20, 30, 40, 60, 120].map(Value::Nat), "{:?}", engine);
+            assert_eq!(values(program.query("divisors 0")), []);
+            // the call waits for the suspension binding its argument
+            assert_eq!(values(program.query("exists x :: Nat. let b = (x =:= 3. x) in lookup_price x")), [Value::Nat(30)]);
+            // with nothing left to bind it, the call waits for the rest of
+            // the branch
+            assert_eq!(values(program.query("exists x :: Nat. exists p :: Nat. p =:= (lookup_price x). x =:= 3. p")), [Value::Nat(30)]);
+            let mut divisors = values(program.query("exists n :: Nat. exists d :: Nat. d =:= (divisors n). n =:= 6. d"));
+            divisors.sort_by_key(|v| if let Value::Nat(n) = v { *n } else { 0 });
+            assert_eq!(divisors, [1, 2, 3, 6].map(Value::Nat), "{:?}", engine);
+            assert_eq!(values(program.query("exists x :: Nat. (lookup_price x) =:= 25. x =:= 2. x")), []);
+            let mut solutions = program.query("exists x :: Nat. lookup_price x");
+            assert_eq!(solutions.by_ref().map(|answer| answer.unwrap().to_string()).collect::<Vec<_>>(), ["_0 where lookup_price _1 = _0"]);
+            assert_eq!(solutions.failures(), []);
+        }
+        let steps = |query| {
+            let [breadth, bytecode] = [Engine::Breadth, Engine::Bytecode].map(|engine| {
+                let mut solutions = shop().with_config(Config { engine, ..Config::default() }).query(query);
+                solutions.by_ref().for_each(drop);
+                solutions.steps()
+            });
+            assert_eq!(breadth, bytecode, "{}", query)
+        };
+        steps("exists d :: Nat. d =:= (divisors 12). lookup_price d");
+        steps("exists n :: Nat. exists d :: Nat. d =:= (divisors n). n =:= 6. d");
+    }
+
+    #[test]
+    fn builtin_results() {
+        for engine in [Engine::Breadth, Engine::Depth, Engine::Bytecode] {
+            let program = Program::new()
+                .with_builtin("parity :: Nat -> Bool", |_| vec![MValue::Zero]).unwrap()
+                .with_builtin("echo :: Nat -> Nat", |_| vec![MValue::Var(0)]).unwrap()
+                .with_config(Config { engine, ..Config::default() });
+            for (query, name, found) in [("parity 1", "parity", MValue::Zero), ("echo 1", "echo", MValue::Var(0))] {
+                let mut solutions = program.query(query);
+                assert_eq!(solutions.next(), None);
+                let error = RuntimeError::BuiltinResult { name : name.to_string(), found : found.into() };
+                assert_eq!(solutions.failures()[0].error, error, "{:?}", engine);
+            }
+        }
+    }
+
+    #[test]
+    fn signatures() {
+        assert!(matches!(Program::new().with_builtin("f :: (Nat -> Nat) -> Nat", |_| vec![]), Err(Error::Signature(_))));
+        assert!(matches!(Program::new().with_builtin("f :: Nat -> Int", |_| vec![]), Err(Error::Signature(_))));
+        assert!(matches!(Program::new().with_builtin("f x = x.", |_| vec![]), Err(Error::Parse(_))));
+        assert_eq!(shop().globals.query_type(&statement("lookup_price 1").unwrap()), Some(crate::machine::ValueType::Nat));
+    }
+
+    #[test]
+    fn calls() {
+        let program = Program::compile(&(SRC.to_owned() + "
+split :: Nat -> Nat * Nat
+split n = exists x :: Nat. exists y :: Nat. add x y =:= n. (x, y).
+
+same :: [Bool] -> [Bool]
+same xs = xs.
+
+open n = exists x :: Nat. add x n.")).unwrap();
+        let mut splits = program.call::<(u64, u64)>("split", 4).collect::<Result<Vec<_>, _>>().unwrap();
+        splits.sort();
+        assert_eq!(splits, [(0, 4), (1, 3), (2, 2), (3, 1), (4, 0)]);
+        assert_eq!(program.call::<Vec<bool>>("same", vec![true, false]).collect::<Vec<_>>(), [Ok(vec![true, false])]);
+
+        let error = Error::Call { name : "split".to_string(), expected : "Nat -> Nat".to_string(), found : "Nat -> Nat * Nat".to_string() };
+        assert_eq!(program.call::<u64>("split", 4).collect::<Vec<_>>(), [Err(error)]);
+        assert_eq!(program.call::<u64>("splat", 4).next(), Some(Err(Error::Unbound("splat".to_string()))));
+        let error = TypeError { expected : ValueType::Nat, found : "S _0".to_string() };
+        assert_eq!(program.call::<u64>("open", 1).next(), Some(Err(Error::Type(error))));
+    }
+
+    #[test]
+    fn typed_values() {
+        let program = Program::compile(SRC).unwrap();
+        let boxed = Box::new;
+        let open = Value::Pair(boxed(Value::Bool(true)), boxed(Value::Pair(boxed(Value::Succ(boxed(Value::Var(0)))), boxed(Value::Cons(boxed(Value::Nat(1)), boxed(Value::Var(1)))))));
+        assert_eq!(values(program.query("exists b :: Bool. exists n :: Nat. exists xs :: [Nat]. b =:= true. (b, ((S n), (1 : xs)))")), [open]);
+        let list = Value::List(vec![Value::Bool(false), Value::Bool(true)]);
+        assert_eq!(values(program.query("exists b :: Bool. b =:= false. [b, true]")), [list]);
+    }
+}
@@ -0,0 +1,295 @@
+use std::{fs, io::{BufRead, Write}};
+
+use pest::error::InputLocation;
+
+use crate::machine::{self, pretty::{self, Printer}, translate::Globals, Config};
+use crate::parser::{self, decl::Decl, stm::Stm};
+use crate::program;
+
+const HELP : &str = "Enter definitions, or queries ending in '.' to solve them.
+After each answer, ';' or 'y' asks for the next one.
+  :load FILE   add the definitions of FILE
+  :reload      read the loaded files again and redo the definitions since
+  :type QUERY  the type of the query's answers
+  :ir [QUERY]  the translated definitions, or the translated query
+  :help        this message
+  :quit        leave";
+
+// Where definitions came from, replayed in order by `:reload`
+enum Source {
+    File(String),
+    Typed(Vec<Decl>),
+}
+
+// An interactive session over a growing set of definitions
+pub struct Repl<R, W> {
+    input : R,
+    out : W,
+    config : Config,
+    globals : Globals,
+    sources : Vec<Source>,
+}
+
+impl<R : BufRead, W : Write> Repl<R, W> {
+
+    pub fn new(input : R, out : W, config : Config) -> Repl<R, W> {
+        Repl { input, out, config, globals : Globals::new(), sources : vec![] }
+    }
+
+    // Reads entries until the input ends or `:quit`, starting with the
+    // definitions of `file` if given. An entry that fails leaves the
+    // definitions as they were.
+    pub fn run(&mut self, file : Option<String>) {
+        if let Some(file) = file { self.load(&file) }
+        self.say("Type :help for commands.");
+        while let Some(entry) = self.read_entry() {
+            if entry.trim() == ":quit" { break }
+            self.handle(&entry)
+        }
+    }
+
+    fn say(&mut self, s : &str) {
+        let _ = writeln!(self.out, "{}", s);
+    }
+
+    fn prompt(&mut self, s : &str) {
+        let _ = write!(self.out, "{}", s);
+        let _ = self.out.flush();
+    }
+
+    fn read_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        match self.input.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(['\n', '\r']).to_string()),
+        }
+    }
+
+    // A command, or lines up to one that completes a declaration or query.
+    // A blank line ends an entry that doesn't parse.
+    fn read_entry(&mut self) -> Option<String> {
+        let mut entry = String::new();
+        loop {
+            self.prompt(if entry.is_empty() { "?- " } else { "|  " });
+            let line = match self.read_line() {
+                Some(line) => line,
+                None if entry.is_empty() => return None,
+                None => return Some(entry),
+            };
+            if entry.is_empty() && line.trim().is_empty() { continue }
+            if entry.is_empty() && line.trim_start().starts_with(':') { return Some(line) }
+            entry += &line;
+            entry += "\n";
+            if line.trim().is_empty() || !incomplete(&entry) { return Some(entry) }
+        }
+    }
+
+    fn handle(&mut self, entry : &str) {
+        let entry = entry.trim();
+        if let Some(command) = entry.strip_prefix(':') {
+            let (name, arg) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+            return self.command(name, arg.trim())
+        }
+        match parser::parse(entry) {
+            Ok(decls) => {
+                let defs : Vec<Decl> = decls.iter().filter(|decl| !matches!(decl, Decl::Stm(_))).cloned().collect();
+                let mut globals = self.globals.clone();
+                let stms = match globals.define(decls) {
+                    Ok(stms) => stms,
+                    Err(e) => return self.say(&format!("Error: {}", e)),
+                };
+                self.globals = globals;
+                if !defs.is_empty() { self.sources.push(Source::Typed(defs)) }
+                for stm in stms { self.solve(stm) }
+            },
+            Err(e) => self.say(&format!("Error: {}", e)),
+        }
+    }
+
+    fn command(&mut self, name : &str, arg : &str) {
+        match name {
+            "load" if !arg.is_empty() => self.load(arg),
+            "reload" => {
+                let mut globals = Globals::new();
+                for source in &self.sources {
+                    let decls = match source {
+                        Source::File(file) => match read(file) {
+                            Ok(decls) => decls,
+                            Err(e) => return self.say(&e),
+                        },
+                        Source::Typed(decls) => decls.clone(),
+                    };
+                    if let Err(e) = globals.define(decls) { return self.say(&format!("Error: {}", e)) }
+                }
+                self.globals = globals;
+                self.say("Reloaded.")
+            },
+            "type" => match statement(arg) {
+                Ok(stm) => match self.globals.query_type(&stm) {
+                    Some(ptype) => self.say(&ptype.to_string()),
+                    None => self.say("The type isn't known."),
+                },
+                Err(e) => self.say(&e),
+            },
+            "ir" if arg.is_empty() => {
+                let out = pretty::definitions(&self.globals.env(), Some(self.globals.names()));
+                self.prompt(&out)
+            },
+            "ir" => match statement(arg).and_then(|stm| self.globals.query(stm).map_err(|e| format!("Error: {}", e))) {
+                Ok(comp) => {
+                    let out = Printer::new(true).with_scope(self.globals.names().to_vec()).comp(&comp, 0);
+                    self.say(&out)
+                },
+                Err(e) => self.say(&e),
+            },
+            "help" => self.say(HELP),
+            _ => self.say(&format!("Error: Unknown command ':{}'; try :help.", name)),
+        }
+    }
+
+    // Adds the definitions of `file`, leaving out its queries
+    fn load(&mut self, file : &str) {
+        match read(file) {
+            Ok(decls) => {
+                let mut globals = self.globals.clone();
+                let queries = match globals.define(decls) {
+                    Ok(queries) => queries,
+                    Err(e) => return self.say(&format!("Error: {}", e)),
+                };
+                self.globals = globals;
+                self.sources.push(Source::File(file.to_string()));
+                self.say(&format!("Loaded '{}'.", file));
+                if !queries.is_empty() { self.say("Its queries were not run.") }
+            },
+            Err(e) => self.say(&e),
+        }
+    }
+
+    // Prints the answers of a query one at a time while more are asked for
+    fn solve(&mut self, stm : Stm) {
+        let ptype = self.globals.query_type(&stm);
+        let comp = match self.globals.query(stm) {
+            Ok(comp) => comp,
+            Err(e) => return self.say(&format!("Error: {}", e)),
+        };
+        let mut answers = machine::answers(comp, self.globals.env(), ptype, &self.config);
+        let mut found = false;
+        loop {
+            match answers.next() {
+                Some(Ok(answer)) => {
+                    found = true;
+                    let line = self.config.format.answer(&answer);
+                    self.say(&line);
+                    self.prompt("more? ");
+                    match self.read_line().as_deref().map(str::trim) {
+                        Some(";") | Some("y") => continue,
+                        _ => return,
+                    }
+                },
+                Some(Err(e)) => {
+                    let line = self.config.format.answer_error(&e);
+                    self.say(&line)
+                },
+                None => break,
+            }
+        }
+        for failure in answers.failures().to_vec() {
+            self.say(&format!("Branch failed at step {}: {}", failure.steps, failure.error))
+        }
+        match answers.limit_hit() {
+            Some(hit) => self.say(&format!("Search stopped after {} steps: {}", answers.steps(), hit)),
+            None if found => self.say("No more solutions."),
+            None => self.say("No solutions."),
+        }
+    }
+}
+
+// Whether `src` fails to parse only for ending too soon
+fn incomplete(src : &str) -> bool {
+    match parser::parse(src) {
+        Err(e) => {
+            let at = match e.location {
+                InputLocation::Pos(at) => at,
+                InputLocation::Span((at, _)) => at,
+            };
+            at >= src.trim_end().len()
+        },
+        _ => false,
+    }
+}
+
+fn read(file : &str) -> Result<Vec<Decl>, String> {
+    let src = fs::read_to_string(file).map_err(|e| format!("Error: Could not read file '{}': {}", file, e))?;
+    parser::parse(&src).map_err(|e| format!("Error: Could not parse file '{}': {}", file, e))
+}
+
+// A query given to a command, with or without its final '.'
+fn statement(src : &str) -> Result<Stm, String> {
+    program::statement(src).map_err(|e| format!("Error: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(input : &str) -> String {
+        let mut out = vec![];
+        Repl::new(input.as_bytes(), &mut out, Config::default()).run(None);
+        String::from_utf8(out).unwrap()
+    }
+
+    const ADD : &str = "add :: Nat -> Nat -> Nat
+add n m = case m of
+    Z -> n
+  | S z -> S (add n z).
+";
+
+    #[test]
+    fn answers_on_demand() {
+        let out = session(&(ADD.to_owned() + "exists x :: Nat. exists y :: Nat. add x y =:= 2. (x, y).\n;\n;\n;\n"));
+        assert!(out.contains("?- ?- |  |  ?- "), "{}", out);
+        assert!(out.contains("> (2, 0)\nmore? > (1, 1)\nmore? > (0, 2)\nmore? No more solutions."), "{}", out);
+
+        let out = session(&(ADD.to_owned() + "exists x :: Nat. add x x =:= x. x.\n\n"));
+        assert!(out.contains("> 0\nmore? ?- "), "{}", out);
+    }
+
+    #[test]
+    fn commands() {
+        let out = session(&(ADD.to_owned() + ":type add 1 2\n:ir add 1\n:ir\n:frob\n"));
+        assert!(out.contains("?- Nat\n"), "{}", out);
+        assert!(out.contains("?- return add to x1. return 1 to x2. force x1 x2\n"), "{}", out);
+        assert!(out.contains("?- add = thunk("), "{}", out);
+        assert!(out.contains("Error: Unknown command ':frob'"), "{}", out);
+    }
+
+    #[test]
+    fn errors_keep_the_session() {
+        let out = session("f 1.\nexists x :: Int. x.\ncase 1 of Z -> 1 | Z -> 2.\n1 <> 2.\n;\n;\n");
+        assert!(out.contains("Error: Variable f not found in environment"), "{}", out);
+        assert!(out.contains("Error: Translating the type Int of the variable x isn't supported yet"), "{}", out);
+        assert!(out.contains("a case has two arms for Z"), "{}", out);
+        assert!(out.contains("> 1\nmore? > 2\nmore? No more solutions."), "{}", out);
+    }
+
+    #[test]
+    fn load_and_reload() {
+        let file = std::env::temp_dir().join(format!("flp_poc_repl_{}.gwk", std::process::id()));
+        fs::write(&file, "two :: Nat -> Nat\ntwo n = 2.\n\ntwo 0.").unwrap();
+        let input = format!(":load {}\ntwo 0.\n\n", file.display());
+        let out = session(&input);
+        assert!(out.contains("Its queries were not run."), "{}", out);
+        assert!(out.contains("> 2\nmore? "), "{}", out);
+
+        let mut out = vec![];
+        {
+            let mut repl = Repl::new(":reload\ntwo 0.\n\n".as_bytes(), &mut out, Config::default());
+            repl.load(&file.display().to_string());
+            fs::write(&file, "two :: Nat -> Nat\ntwo n = 3.").unwrap();
+            repl.run(None);
+        }
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("Reloaded.\n?- > 3"), "{}", out);
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+use std::{fmt::Display, sync::Arc};
+
+use crate::machine::{mterms::MValue, Answer, Term, Typed, ValueType};
+
+// An answer's value read by its type: numbers as `Nat`, booleans as
+// `Bool` and proper lists as `List`. What an unbound logic variable leaves
+// open stays as it is built, so `S _0` is `Succ(Var(0))` and `1 : _0` is
+// `Cons(Nat(1), Var(0))`. Without a type, sums are `Left` and `Right`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Value {
+    Unit,
+    Nat(u64),
+    Bool(bool),
+    List(Vec<Value>),
+    Pair(Box<Value>, Box<Value>),
+    Left(Box<Value>),
+    Right(Box<Value>),
+    Succ(Box<Value>),
+    Cons(Box<Value>, Box<Value>),
+    Var(usize),
+}
+
+impl Value {
+
+    fn read(term : &Term, ptype : Option<&ValueType>) -> Value {
+        let element = match ptype {
+            Some(ValueType::List(t)) => Some(&**t),
+            _ => None,
+        };
+        if let Some(n) = term.nat() { return Value::Nat(n as u64) }
+        match term {
+            Term::Unit => Value::Unit,
+            Term::Zero => Value::Nat(0),
+            Term::Succ(t) => Value::Succ(Box::new(Value::read(t, Some(&ValueType::Nat)))),
+            Term::Nil | Term::Cons(_, _) => {
+                let mut xs = vec![];
+                let mut rest = term;
+                while let Term::Cons(x, xs1) = rest {
+                    xs.push(Value::read(x, element));
+                    rest = xs1
+                }
+                match rest {
+                    Term::Nil => Value::List(xs),
+                    _ => xs.into_iter().rev().fold(Value::read(rest, ptype), |tail, x| Value::Cons(Box::new(x), Box::new(tail))),
+                }
+            },
+            Term::Pair(x, y) => Value::Pair(Box::new(Value::read(x, component(ptype, true))), Box::new(Value::read(y, component(ptype, false)))),
+            Term::Inl(_) if ptype == Some(&ValueType::bool()) => Value::Bool(true),
+            Term::Inr(_) if ptype == Some(&ValueType::bool()) => Value::Bool(false),
+            Term::Inl(x) => Value::Left(Box::new(Value::read(x, component(ptype, true)))),
+            Term::Inr(x) => Value::Right(Box::new(Value::read(x, component(ptype, false)))),
+            Term::Var(i) => Value::Var(*i),
+        }
+    }
+}
+
+// The type of a pair's or sum's left or right component
+fn component(ptype : Option<&ValueType>, left : bool) -> Option<&ValueType> {
+    match ptype {
+        Some(ValueType::Product(t, u)) | Some(ValueType::Sum(t, u)) => Some(if left { t } else { u }),
+        _ => None,
+    }
+}
+
+impl From<&Typed> for Value {
+    fn from(typed : &Typed) -> Value { Value::read(&typed.term, typed.ptype.as_ref()) }
+}
+
+impl Answer {
+
+    pub fn value(&self) -> Value { Value::from(&self.value) }
+
+    // The value decoded as a `T`
+    pub fn get<T : FromValue>(&self) -> Result<T, TypeError> { T::from_value(&encode(&self.value.term)) }
+
+    // The value of the query variable `name`, if bindings were asked for
+    pub fn binding(&self, name : &str) -> Option<Value> {
+        self.bindings.iter().find(|(var, _)| var == name).map(|(_, typed)| Value::from(typed))
+    }
+}
+
+// A value that isn't of the type a Rust type stands for, or isn't ground
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeError {
+    pub expected : ValueType,
+    pub found : String,
+}
+
+impl TypeError {
+    fn new<T : HasType>(found : &MValue) -> TypeError {
+        let term = decode(found).map_or_else(|| found.to_string(), |term| term.to_string());
+        TypeError { expected : T::value_type(), found : term }
+    }
+}
+
+impl Display for TypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Expected a value of type {}, found {}.", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+// Rust types standing for a type of the language
+pub trait HasType {
+    fn value_type() -> ValueType;
+}
+
+// Rust values that encode as ground values of their type
+pub trait IntoValue : HasType {
+    fn into_value(self) -> MValue;
+}
+
+// Rust values that ground values of their type decode to
+pub trait FromValue : HasType + Sized {
+    fn from_value(val : &MValue) -> Result<Self, TypeError>;
+}
+
+impl HasType for () {
+    fn value_type() -> ValueType { ValueType::Unit }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> MValue { MValue::Unit }
+}
+
+impl FromValue for () {
+    fn from_value(val : &MValue) -> Result<(), TypeError> {
+        match val {
+            MValue::Unit => Ok(()),
+            _ => Err(TypeError::new::<()>(val)),
+        }
+    }
+}
+
+impl HasType for u64 {
+    fn value_type() -> ValueType { ValueType::Nat }
+}
+
+impl IntoValue for u64 {
+    fn into_value(self) -> MValue {
+        (0..self).fold(MValue::Zero, |n, _| MValue::Succ(n.into()))
+    }
+}
+
+impl FromValue for u64 {
+    fn from_value(val : &MValue) -> Result<u64, TypeError> {
+        let mut n = 0;
+        let mut v = val;
+        loop {
+            match v {
+                MValue::Zero => return Ok(n),
+                MValue::Succ(w) => (n, v) = (n + 1, w),
+                _ => return Err(TypeError::new::<u64>(val)),
+            }
+        }
+    }
+}
+
+// Booleans are the sum of two units, `true` on the left
+impl HasType for bool {
+    fn value_type() -> ValueType { ValueType::bool() }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> MValue {
+        let unit = MValue::Unit.into();
+        if self { MValue::Inl(unit) } else { MValue::Inr(unit) }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(val : &MValue) -> Result<bool, TypeError> {
+        match val {
+            MValue::Inl(v) if **v == MValue::Unit => Ok(true),
+            MValue::Inr(v) if **v == MValue::Unit => Ok(false),
+            _ => Err(TypeError::new::<bool>(val)),
+        }
+    }
+}
+
+impl<T : HasType> HasType for Vec<T> {
+    fn value_type() -> ValueType { ValueType::List(Box::new(T::value_type())) }
+}
+
+impl<T : IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> MValue {
+        self.into_iter().rev().fold(MValue::Nil, |xs, x| MValue::Cons(x.into_value().into(), xs.into()))
+    }
+}
+
+impl<T : FromValue> FromValue for Vec<T> {
+    fn from_value(val : &MValue) -> Result<Vec<T>, TypeError> {
+        let mut xs = vec![];
+        let mut v = val;
+        loop {
+            match v {
+                MValue::Nil => return Ok(xs),
+                MValue::Cons(x, rest) => {
+                    xs.push(T::from_value(x)?);
+                    v = rest
+                },
+                _ => return Err(TypeError::new::<Vec<T>>(val)),
+            }
+        }
+    }
+}
+
+impl<A : HasType, B : HasType> HasType for (A, B) {
+    fn value_type() -> ValueType { ValueType::Product(Box::new(A::value_type()), Box::new(B::value_type())) }
+}
+
+impl<A : IntoValue, B : IntoValue> IntoValue for (A, B) {
+    fn into_value(self) -> MValue { MValue::Pair(self.0.into_value().into(), self.1.into_value().into()) }
+}
+
+impl<A : FromValue, B : FromValue> FromValue for (A, B) {
+    fn from_value(val : &MValue) -> Result<(A, B), TypeError> {
+        match val {
+            MValue::Pair(a, b) => Ok((A::from_value(a)?, B::from_value(b)?)),
+            _ => Err(TypeError::new::<(A, B)>(val)),
+        }
+    }
+}
+
+// The value of a closed term, its unbound variables left as `Var`s, which
+// no Rust type decodes
+fn encode(term : &Term) -> MValue {
+    let sub = |t : &Term| -> Arc<MValue> { encode(t).into() };
+    match term {
+        Term::Unit => MValue::Unit,
+        Term::Zero => MValue::Zero,
+        Term::Succ(t) => MValue::Succ(sub(t)),
+        Term::Nil => MValue::Nil,
+        Term::Cons(t, u) => MValue::Cons(sub(t), sub(u)),
+        Term::Pair(t, u) => MValue::Pair(sub(t), sub(u)),
+        Term::Inl(t) => MValue::Inl(sub(t)),
+        Term::Inr(t) => MValue::Inr(sub(t)),
+        Term::Var(i) => MValue::Var(*i),
+    }
+}
+
+// The term a value prints as in answers, unless it holds a thunk
+fn decode(val : &MValue) -> Option<Term> {
+    let sub = |v : &MValue| decode(v).map(Box::new);
+    Some(match val {
+        MValue::Unit => Term::Unit,
+        MValue::Zero => Term::Zero,
+        MValue::Succ(v) => Term::Succ(sub(v)?),
+        MValue::Nil => Term::Nil,
+        MValue::Cons(v, w) => Term::Cons(sub(v)?, sub(w)?),
+        MValue::Pair(v, w) => Term::Pair(sub(v)?, sub(w)?),
+        MValue::Inl(v) => Term::Inl(sub(v)?),
+        MValue::Inr(v) => Term::Inr(sub(v)?),
+        MValue::Var(i) => Term::Var(*i),
+        MValue::Thunk(_) => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let xs = vec![(3u64, true), (0, false)];
+        assert_eq!(Vec::<(u64, bool)>::from_value(&xs.clone().into_value()), Ok(xs));
+        assert_eq!(<Vec<(u64, bool)>>::value_type().to_string(), "[Nat * Bool]");
+        assert_eq!(<()>::from_value(&().into_value()), Ok(()));
+    }
+
+    #[test]
+    fn type_errors() {
+        let error = u64::from_value(&(1u64, 2u64).into_value()).unwrap_err();
+        assert_eq!(error.to_string(), "Expected a value of type Nat, found (1, 2).");
+        let open = MValue::Succ(MValue::Var(0).into());
+        assert_eq!(Vec::<u64>::from_value(&MValue::Cons(open.into(), MValue::Nil.into())).unwrap_err().found, "S _0");
+        assert_eq!(bool::from_value(&MValue::Inl(MValue::Zero.into())).unwrap_err().expected, ValueType::bool());
+    }
+}